@@ -0,0 +1,104 @@
+use crate::ray::Ray;
+use crate::vector::{Point3, Vec3};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn new(min: Point3, max: Point3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    pub fn surrounding(self, other: Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        (self.min + self.max) / 2.0
+    }
+
+    // Grows the box by delta on every side, flat boxes would never pass the slab test
+    pub fn padded(self, delta: f64) -> Aabb {
+        let delta = Vec3::new(delta, delta, delta);
+        Aabb {
+            min: self.min - delta,
+            max: self.max + delta,
+        }
+    }
+
+    // Slab test, returns the parametric interval the ray spends inside the box
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        let axes = [
+            (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+            (ray.origin.z, ray.direction.z, self.min.z, self.max.z),
+        ];
+
+        for (origin, direction, min, max) in axes {
+            let inv_d = 1.0 / direction;
+            let mut near = (min - origin) * inv_d;
+            let mut far = (max - origin) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut near, &mut far);
+            }
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+            if t1 <= t0 {
+                return None;
+            }
+        }
+
+        Some((t0, t1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+
+    #[test]
+    fn aabb_hit_interval() {
+        let aabb = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::one());
+        let ray = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 2.0));
+        let (t0, t1) = aabb.hit(&ray, 0.0, f64::MAX).unwrap();
+
+        assert_approx_eq!(2.0, t0, 0.0001);
+        assert_approx_eq!(3.0, t1, 0.0001);
+    }
+
+    #[test]
+    fn aabb_surrounding() {
+        let a = Aabb::new(Point3::new(-1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 1.0));
+        let b = Aabb::new(Point3::new(0.5, -2.0, 0.5), Point3::new(3.0, 0.0, 0.5));
+        let both = a.surrounding(b);
+
+        assert_eq!(Point3::new(-1.0, -2.0, 0.0), both.min);
+        assert_eq!(Point3::new(3.0, 1.0, 1.0), both.max);
+        assert_eq!(Point3::new(1.0, -0.5, 0.5), both.centroid());
+    }
+
+    #[test]
+    fn aabb_miss() {
+        let aabb = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::one());
+        let ray = Ray::new(Point3::new(0.0, 2.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+
+        assert!(aabb.hit(&ray, 0.0, f64::MAX).is_none());
+    }
+}
@@ -0,0 +1,370 @@
+use std::io;
+use std::path::Path;
+
+use rand::RngCore;
+
+use crate::film::Film;
+use crate::hittable::HitRecord;
+use crate::hittable_list::HittableList;
+use crate::ppm::write_ppm;
+use crate::ray::Ray;
+use crate::sampler::{mix_bits, Sampler};
+use crate::spectrum::{SampledSpectrum, SampledWavelengths};
+use crate::tonemap::View;
+use crate::vector::*;
+
+// Arbitrary output variables, images of what the camera rays hit first
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Aov {
+    Albedo,
+    // Shading normal in world space, facing the camera
+    Normal,
+    // Distance from the camera, infinite where nothing was hit
+    Depth,
+    // Index of the object in the world, -1 where nothing was hit
+    ObjectId,
+    // Index of the material in the world, -1 where nothing was hit
+    MaterialId,
+    // Light reaching the camera after at most one bounce, the sky seen directly included
+    Direct,
+    // Light reaching the camera after two or more bounces, adds up to the image with direct
+    Indirect,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 7] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Direct,
+        Aov::Indirect,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::ObjectId => "object",
+            Aov::MaterialId => "material",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+}
+
+// What a single camera ray saw
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AovSample {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub depth: Option<f64>,
+    pub object: Option<u32>,
+    pub material: Option<u32>,
+    pub direct: Color,
+    pub indirect: Color,
+}
+
+// Sums over the samples of a pixel, ids are those of the first sample that hit something
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AovPixel {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub depth: f64,
+    pub hits: u32,
+    pub object: Option<u32>,
+    pub material: Option<u32>,
+    pub direct: Color,
+    pub indirect: Color,
+}
+
+impl AovPixel {
+    pub fn new() -> AovPixel {
+        AovPixel {
+            albedo: Color::zero(),
+            normal: Vec3::zero(),
+            depth: 0.0,
+            hits: 0,
+            object: None,
+            material: None,
+            direct: Color::zero(),
+            indirect: Color::zero(),
+        }
+    }
+
+    pub fn add_sample(&mut self, sample: &AovSample) {
+        self.albedo += sample.albedo;
+        self.normal += sample.normal;
+        self.direct += sample.direct;
+        self.indirect += sample.indirect;
+        if let Some(depth) = sample.depth {
+            if self.hits == 0 {
+                self.object = sample.object;
+                self.material = sample.material;
+            }
+            self.depth += depth;
+            self.hits += 1;
+        }
+    }
+
+    // The value of the AOV over the given number of samples, scalars in every channel
+    pub fn value(&self, aov: Aov, samples: u32) -> Color {
+        let n = samples.max(1) as f64;
+        let id = |id: Option<u32>| Color::one() * id.map_or(-1.0, |id| id as f64);
+        match aov {
+            Aov::Albedo => self.albedo / n,
+            Aov::Normal if self.normal.length_squared() > 0.0 => self.normal.unit_vector(),
+            Aov::Normal => Vec3::zero(),
+            Aov::Depth if self.hits > 0 => Color::one() * (self.depth / self.hits as f64),
+            Aov::Depth => Color::one() * f64::INFINITY,
+            Aov::ObjectId => id(self.object),
+            Aov::MaterialId => id(self.material),
+            Aov::Direct => self.direct / n,
+            Aov::Indirect => self.indirect / n,
+        }
+    }
+}
+
+impl AovSample {
+    fn none() -> AovSample {
+        AovSample {
+            albedo: Color::zero(),
+            normal: Vec3::zero(),
+            depth: None,
+            object: None,
+            material: None,
+            direct: Color::zero(),
+            indirect: Color::zero(),
+        }
+    }
+
+    // What a ray sees at its first hit, without the light
+    fn hit(ray: &Ray, world: &HittableList, record: &HitRecord) -> AovSample {
+        AovSample {
+            albedo: world.material(record.material).albedo() * record.tint,
+            normal: record.normal,
+            depth: Some(record.t * ray.direction.length()),
+            object: Some(record.object as u32),
+            material: Some(record.material as u32),
+            direct: Color::zero(),
+            indirect: Color::zero(),
+        }
+    }
+}
+
+// Traces a camera ray like Ray::color, and records what its first hit looked like
+pub fn trace(
+    ray: &Ray,
+    world: &HittableList,
+    sampler: &mut dyn Sampler,
+    rng: &mut dyn RngCore,
+    depth: i32,
+) -> (Color, AovSample) {
+    if depth <= 0 {
+        return (Color::zero(), AovSample::none());
+    }
+
+    let record = match ray.hit(world, rng) {
+        Some(record) => record,
+        None => {
+            let mut sample = AovSample::none();
+            sample.albedo = ray.background(world);
+            sample.direct = ray.background(world);
+            return (sample.direct, sample);
+        }
+    };
+    let mut sample = AovSample::hit(ray, world, &record);
+
+    // Split the first bounce by whether it reaches the sky straight away
+    let material = world.material(record.material);
+    if let Some(scatter) = material.scatter(ray, &record, sampler, rng) {
+        if depth > 1 {
+            match scatter.ray.hit(world, rng) {
+                Some(next) => {
+                    let light = scatter.ray.shade(&next, world, sampler, rng, depth - 1);
+                    sample.indirect = scatter.attenuation * light;
+                }
+                None => sample.direct = scatter.attenuation * scatter.ray.background(world),
+            }
+        }
+    }
+    (sample.direct + sample.indirect, sample)
+}
+
+// Spectral version of trace, the light is converted to the working space once the path is
+// done and its wavelengths are final
+pub fn trace_spectral(
+    ray: &Ray,
+    world: &HittableList,
+    wavelengths: &mut SampledWavelengths,
+    sampler: &mut dyn Sampler,
+    rng: &mut dyn RngCore,
+    depth: i32,
+) -> (Color, AovSample) {
+    if depth <= 0 {
+        return (Color::zero(), AovSample::none());
+    }
+
+    let (mut sample, direct, indirect) = match ray.hit(world, rng) {
+        None => {
+            let mut sample = AovSample::none();
+            sample.albedo = ray.background(world);
+            let sky = world.spectrum(sample.albedo, wavelengths);
+            (sample, sky, SampledSpectrum::zero())
+        }
+        Some(record) => {
+            let sample = AovSample::hit(ray, world, &record);
+            let (mut direct, mut indirect) = (SampledSpectrum::zero(), SampledSpectrum::zero());
+            let material = world.material(record.material);
+            if let Some(scatter) =
+                material.scatter_spectral(ray, &record, wavelengths, sampler, rng)
+            {
+                if depth > 1 {
+                    let attenuation = world.spectrum(scatter.attenuation, wavelengths);
+                    match scatter.ray.hit(world, rng) {
+                        Some(next) => {
+                            let light = scatter.ray.spectral_shade(
+                                &next,
+                                world,
+                                wavelengths,
+                                sampler,
+                                rng,
+                                depth - 1,
+                            );
+                            indirect = attenuation * light;
+                        }
+                        None => {
+                            let sky = scatter.ray.background(world);
+                            direct = attenuation * world.spectrum(sky, wavelengths);
+                        }
+                    }
+                }
+            }
+            (sample, direct, indirect)
+        }
+    };
+    sample.direct = world.spectrum_color(direct, wavelengths);
+    sample.indirect = world.spectrum_color(indirect, wavelengths);
+    (sample.direct + sample.indirect, sample)
+}
+
+// Writes every AOV as a PPM next to the image, render.ppm gets render.albedo.ppm and so on.
+// The light AOVs go through the view like the image
+pub fn write_aovs(path: &Path, film: &Film, view: &View) -> io::Result<()> {
+    for aov in Aov::ALL {
+        let colors = displayable(aov, &film.aov(aov), view);
+        let path = path.with_extension(format!("{}.ppm", aov.name()));
+        write_ppm(path, film.width, film.height, &colors)?;
+    }
+    Ok(())
+}
+
+// Maps AOV values to something to look at: normals to [0, 1], depth from white up close to
+// black far away, every id to its own color
+fn displayable(aov: Aov, values: &[Color], view: &View) -> Vec<Color> {
+    let max_depth = values
+        .iter()
+        .map(|v| v.x)
+        .filter(|d| d.is_finite())
+        .fold(0.0, f64::max);
+    let id_color = |id: f64| {
+        if id < 0.0 {
+            return Color::zero();
+        }
+        let bits = mix_bits(id as u64 + 1);
+        let channel = |shift: u32| ((bits >> shift) & 0xff) as f64 / 255.0;
+        Color::new(channel(0), channel(8), channel(16))
+    };
+
+    values
+        .iter()
+        .map(|&value| match aov {
+            Aov::Albedo => view.encode(value),
+            Aov::Direct | Aov::Indirect => view.apply(value),
+            Aov::Normal => 0.5 * (value + Vec3::one()),
+            Aov::Depth if value.x.is_finite() => Color::one() * (1.0 - value.x / max_depth),
+            Aov::Depth => Color::zero(),
+            Aov::ObjectId | Aov::MaterialId => id_color(value.x),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::render::tests::{config, foggy_world, render_new};
+    use crate::sampler::SamplerKind;
+    use assert_approx_eq::*;
+
+    #[test]
+    fn aov_direct_and_indirect_add_up_to_image() {
+        let film = render_new(
+            &Camera::new(0.0),
+            &foggy_world(),
+            &config(8, SamplerKind::Sobol, 0, 8),
+        );
+        let direct = film.aov(Aov::Direct);
+        let indirect = film.aov(Aov::Indirect);
+
+        for (i, color) in film.colors().iter().enumerate() {
+            let sum = direct[i] + indirect[i];
+            assert_approx_eq!(color.x, sum.x, 1e-9);
+            assert_approx_eq!(color.z, sum.z, 1e-9);
+        }
+        assert!(indirect.iter().any(|c| c.y > 0.0));
+    }
+
+    #[test]
+    fn aov_first_hit() {
+        let world = HittableList::new();
+        let film = render_new(
+            &Camera::new(0.0),
+            &world,
+            &config(4, SamplerKind::Sobol, 0, 4),
+        );
+        let (top, bottom) = (0, film.pixels.len() - 1);
+        let albedo = film.aov(Aov::Albedo);
+        let depth = film.aov(Aov::Depth);
+        let object = film.aov(Aov::ObjectId);
+        let normal = film.aov(Aov::Normal);
+
+        // The top left corner sees the sky, the bottom row the ground sphere right below
+        assert_eq!(-1.0, object[top].x);
+        assert!(depth[top].x.is_infinite());
+        assert_eq!(1.0, object[bottom].x);
+        assert_eq!(0.0, film.aov(Aov::MaterialId)[bottom].x);
+        assert_eq!(Color::new(0.5, 0.5, 0.5), albedo[bottom]);
+        assert!(depth[bottom].x > 0.5 && depth[bottom].x < 2.0);
+        assert!(normal[bottom].y > 0.9);
+    }
+
+    #[test]
+    fn aov_pixel_keeps_first_hit_ids() {
+        let miss = AovSample {
+            albedo: Color::one(),
+            normal: Vec3::zero(),
+            depth: None,
+            object: None,
+            material: None,
+            direct: Color::one(),
+            indirect: Color::zero(),
+        };
+        let hit = |object| AovSample {
+            depth: Some(2.0),
+            object: Some(object),
+            material: Some(0),
+            ..miss
+        };
+        let mut pixel = AovPixel::new();
+        pixel.add_sample(&miss);
+        pixel.add_sample(&hit(3));
+        pixel.add_sample(&hit(5));
+
+        assert_eq!(Color::one() * 3.0, pixel.value(Aov::ObjectId, 3));
+        assert_eq!(Color::one() * 2.0, pixel.value(Aov::Depth, 3));
+        assert_eq!(Color::one(), pixel.value(Aov::Albedo, 3));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::aabb::Aabb;
+use crate::ray::Ray;
+use crate::vector::Vec3;
+
+// Primitives per leaf
+const LEAF_SIZE: usize = 2;
+
+struct Node {
+    bounds: Aabb,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    // A range of `order`
+    Leaf { start: usize, end: usize },
+    // The first child follows its parent, the second is further on. Rays heading the negative
+    // way along the split axis visit the second child first
+    Interior { second: usize, axis: usize },
+}
+
+// Bounding volume hierarchy over primitives given by index, split at the median of their
+// centers along the widest axis. Primitives without bounds, like infinite planes, are kept
+// outside the hierarchy and tested for every ray
+pub struct Bvh {
+    nodes: Vec<Node>,
+    order: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Option<Aabb>]) -> Bvh {
+        let (bounded, unbounded): (Vec<usize>, Vec<usize>) =
+            (0..bounds.len()).partition(|&i| bounds[i].is_some());
+        let boxes: Vec<Aabb> = bounds.iter().flatten().copied().collect();
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            order: Vec::new(),
+            unbounded,
+        };
+        if !bounded.is_empty() {
+            // Build over positions in `boxes`, then point the leaves at the primitives
+            let mut positions: Vec<usize> = (0..boxes.len()).collect();
+            bvh.build(&boxes, &mut positions, 0);
+            bvh.order = positions.iter().map(|&p| bounded[p]).collect();
+        }
+        bvh
+    }
+
+    fn build(&mut self, boxes: &[Aabb], positions: &mut [usize], start: usize) {
+        let bounds = positions
+            .iter()
+            .map(|&p| boxes[p])
+            .reduce(Aabb::surrounding)
+            .unwrap();
+        let index = self.nodes.len();
+        let leaf = NodeKind::Leaf {
+            start,
+            end: start + positions.len(),
+        };
+        self.nodes.push(Node { bounds, kind: leaf });
+        if positions.len() <= LEAF_SIZE {
+            return;
+        }
+
+        let centroids = positions
+            .iter()
+            .map(|&p| {
+                let c = boxes[p].centroid();
+                Aabb::new(c, c)
+            })
+            .reduce(Aabb::surrounding)
+            .unwrap();
+        let extent = centroids.max - centroids.min;
+        let axis = (0..3)
+            .max_by(|&a, &b| component(extent, a).total_cmp(&component(extent, b)))
+            .unwrap();
+        if component(extent, axis) == 0.0 {
+            return;
+        }
+
+        let middle = positions.len() / 2;
+        positions.select_nth_unstable_by(middle, |&a, &b| {
+            let (a, b) = (boxes[a].centroid(), boxes[b].centroid());
+            component(a, axis).total_cmp(&component(b, axis))
+        });
+        let (first, rest) = positions.split_at_mut(middle);
+        self.build(boxes, first, start);
+        let second = self.nodes.len();
+        self.build(boxes, rest, start + middle);
+        self.nodes[index].kind = NodeKind::Interior { second, axis };
+    }
+
+    // The closest hit. `hit` tests the primitive with the given index against the ray up to
+    // the given distance, and returns the distance of the hit with what it found
+    pub fn hit<T>(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        mut hit: impl FnMut(usize, f64) -> Option<(f64, T)>,
+    ) -> Option<T> {
+        let mut closest = t_max;
+        let mut result = None;
+        let mut test = |indices: &[usize], closest: &mut f64, result: &mut Option<T>| {
+            for &index in indices {
+                if let Some((t, found)) = hit(index, *closest) {
+                    *closest = t;
+                    *result = Some(found);
+                }
+            }
+        };
+
+        test(&self.unbounded, &mut closest, &mut result);
+        if self.nodes.is_empty() {
+            return result;
+        }
+
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let Node { bounds, kind } = &self.nodes[node];
+            if bounds.hit(ray, t_min, closest).is_none() {
+                continue;
+            }
+            match *kind {
+                NodeKind::Leaf { start, end } => {
+                    test(&self.order[start..end], &mut closest, &mut result)
+                }
+                NodeKind::Interior { second, axis } => {
+                    if component(ray.direction, axis) < 0.0 {
+                        stack.push(node + 1);
+                        stack.push(second);
+                    } else {
+                        stack.push(second);
+                        stack.push(node + 1);
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+fn component(v: Vec3, axis: usize) -> f64 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittable;
+    use crate::plane::Plane;
+    use crate::sphere::Sphere;
+    use crate::vector::Point3;
+    use rand::prelude::*;
+
+    #[test]
+    fn bvh_matches_linear_search() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut objects: Vec<Box<dyn Hittable>> = (0..200)
+            .map(|_| {
+                let center = Point3::random(-10.0..10.0, &mut rng);
+                let sphere = Sphere::new(center, rng.gen_range(0.1..1.0), 0);
+                Box::new(sphere) as Box<dyn Hittable>
+            })
+            .collect();
+        objects.push(Box::new(Plane::new(
+            Point3::new(0.0, -8.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            0,
+        )));
+        let bounds: Vec<_> = objects.iter().map(|o| o.bounding_box()).collect();
+        let bvh = Bvh::new(&bounds);
+
+        for _ in 0..500 {
+            let ray = Ray::new(
+                Point3::random(-12.0..12.0, &mut rng),
+                Vec3::random_unit_vector(&mut rng),
+            );
+            let linear = objects
+                .iter()
+                .enumerate()
+                .filter_map(|(i, o)| o.hit(&ray, 0.001, f64::MAX, &mut rng).map(|r| (r.t, i)))
+                .min_by(|a, b| a.0.total_cmp(&b.0));
+            let found = bvh.hit(&ray, 0.001, f64::MAX, |i, t_max| {
+                let record = objects[i].hit(&ray, 0.001, t_max, &mut rng)?;
+                Some((record.t, (record.t, i)))
+            });
+            assert_eq!(linear, found);
+        }
+    }
+
+    #[test]
+    fn bvh_empty() {
+        let bvh = Bvh::new(&[]);
+        let ray = Ray::new(Point3::zero(), Vec3::new(0.0, 0.0, 1.0));
+        assert!(bvh.hit(&ray, 0.0, 1.0, |_, _| Some((0.5, ()))).is_none());
+    }
+}
@@ -6,10 +6,12 @@ pub struct Camera {
     horizontal: Vec3,
     vertical: Vec3,
     lower_left_corner: Vec3,
+    lens_radius: f64,
 }
 
 impl Camera {
-    pub fn new() -> Camera {
+    // The focus plane is at the focal length, where the scene's center sphere sits
+    pub fn new(aperture: f64) -> Camera {
         let aspect_ratio = 16.0 / 9.0;
         let viewport_height = 2.0;
         let viewport_width = aspect_ratio * viewport_height;
@@ -26,13 +28,36 @@ impl Camera {
                 - horizontal / 2.0
                 - vertical / 2.0
                 - Vec3::new(0.0, 0.0, focal_length),
+            lens_radius: aperture / 2.0,
         }
     }
 
-    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
+    pub fn origin(&self) -> Point3 {
+        self.origin
+    }
+
+    pub fn focus_distance(&self) -> f64 {
+        let center = self.lower_left_corner + self.horizontal / 2.0 + self.vertical / 2.0;
+        (center - self.origin).length()
+    }
+
+    // Vertical, in degrees
+    pub fn field_of_view(&self) -> f64 {
+        let half_height = self.vertical.length() / 2.0;
+        2.0 * (half_height / self.focus_distance()).atan().to_degrees()
+    }
+
+    pub fn aperture(&self) -> f64 {
+        2.0 * self.lens_radius
+    }
+
+    pub fn get_ray(&self, u: f64, v: f64, lens_sample: (f64, f64)) -> Ray {
+        let rd = self.lens_radius * Vec3::in_unit_disk(lens_sample);
+        let offset = rd.x * self.horizontal.unit_vector() + rd.y * self.vertical.unit_vector();
+
         Ray::new(
-            self.origin,
-            self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin,
+            self.origin + offset,
+            self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin - offset,
         )
     }
 }
@@ -0,0 +1,350 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::aov::AovPixel;
+use crate::film::{Film, FilmPixel, Splat};
+use crate::vector::Color;
+
+const CHECKPOINT_MAGIC: &[u8; 4] = b"RTCK";
+const CHECKPOINT_VERSION: u32 = 3;
+
+// The render settings are kept as the command line they were parsed from, which also
+// describes the scene, so a resumed render is set up exactly like the original one
+pub struct Checkpoint {
+    pub args: Vec<String>,
+    pub film: Film,
+}
+
+impl Checkpoint {
+    // File layout, all little endian:
+    //   "RTCK", version as u32, argument count as u32, every argument as its u32 byte length
+    //   followed by utf-8, width and height as u32, then per pixel the color sum as three f64,
+    //   the luminance sum of squares as f64, the sample count as u32 and the AOV sums: albedo
+    //   and normal as three f64, depth as f64, hits as u32, object and material id as u32
+    //   (u32::MAX for none), direct and indirect light as three f64, then per pixel the splat
+    //   sums through the filter as three i128 and their weight as i128
+    pub fn save<P: AsRef<Path>>(path: P, args: &[String], film: &Film) -> io::Result<()> {
+        // Write next to the target and rename, a render killed mid write keeps the old checkpoint
+        let path = path.as_ref();
+        let temporary = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+        write_args(&mut writer, args)?;
+        writer.write_all(&film.width.to_le_bytes())?;
+        writer.write_all(&film.height.to_le_bytes())?;
+        for pixel in &film.pixels {
+            write_pixel(&mut writer, pixel)?;
+        }
+        for splat in &film.splats {
+            write_splat(&mut writer, splat)?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        fs::rename(temporary, path)
+    }
+
+    // The film must have the size of the render it resumes, which is checked before reading
+    // the pixels so a damaged header can't ask for any more memory than the render needs
+    pub fn load<P: AsRef<Path>>(path: P, size: (u32, u32)) -> io::Result<Checkpoint> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC || read_u32(&mut reader)? != CHECKPOINT_VERSION {
+            return Err(invalid_data("not a checkpoint file"));
+        }
+
+        let args = read_args(&mut reader)?;
+        let width = read_u32(&mut reader)?;
+        let height = read_u32(&mut reader)?;
+        if (width, height) != size {
+            return Err(invalid_data(&format!(
+                "checkpoint is {}x{}, the render is {}x{}",
+                width, height, size.0, size.1
+            )));
+        }
+        let mut film = Film::new(width, height);
+        for pixel in film.pixels.iter_mut() {
+            *pixel = read_pixel(&mut reader)?;
+        }
+        for splat in film.splats.iter_mut() {
+            *splat = read_splat(&mut reader)?;
+        }
+
+        Ok(Checkpoint { args, film })
+    }
+}
+
+// The pieces of the file format, also used to send renders over the network
+
+pub fn write_args(writer: &mut impl Write, args: &[String]) -> io::Result<()> {
+    writer.write_all(&(args.len() as u32).to_le_bytes())?;
+    for arg in args {
+        writer.write_all(&(arg.len() as u32).to_le_bytes())?;
+        writer.write_all(arg.as_bytes())?;
+    }
+    Ok(())
+}
+
+// Command lines are short, longer ones come from damaged files or other programs
+const MAX_ARGS: u32 = 1024;
+const MAX_ARG_LENGTH: u32 = 4096;
+
+pub fn read_args(reader: &mut impl Read) -> io::Result<Vec<String>> {
+    let count = read_u32(reader)?;
+    if count > MAX_ARGS {
+        return Err(invalid_data("too many arguments"));
+    }
+    let mut args = Vec::new();
+    for _ in 0..count {
+        let length = read_u32(reader)?;
+        if length > MAX_ARG_LENGTH {
+            return Err(invalid_data("argument too long"));
+        }
+        let mut bytes = vec![0u8; length as usize];
+        reader.read_exact(&mut bytes)?;
+        args.push(String::from_utf8(bytes).map_err(|_| invalid_data("invalid argument"))?);
+    }
+    Ok(args)
+}
+
+pub fn write_pixel(writer: &mut impl Write, pixel: &FilmPixel) -> io::Result<()> {
+    write_color(writer, pixel.sum)?;
+    writer.write_all(&pixel.luminance_sum_squared.to_le_bytes())?;
+    writer.write_all(&pixel.samples.to_le_bytes())?;
+
+    let aovs = &pixel.aovs;
+    write_color(writer, aovs.albedo)?;
+    write_color(writer, aovs.normal)?;
+    writer.write_all(&aovs.depth.to_le_bytes())?;
+    writer.write_all(&aovs.hits.to_le_bytes())?;
+    for id in [aovs.object, aovs.material] {
+        writer.write_all(&id.unwrap_or(u32::MAX).to_le_bytes())?;
+    }
+    write_color(writer, aovs.direct)?;
+    write_color(writer, aovs.indirect)
+}
+
+pub fn read_pixel(reader: &mut impl Read) -> io::Result<FilmPixel> {
+    let id = |id: u32| if id == u32::MAX { None } else { Some(id) };
+    Ok(FilmPixel {
+        sum: read_color(reader)?,
+        luminance_sum_squared: read_f64(reader)?,
+        samples: read_u32(reader)?,
+        aovs: AovPixel {
+            albedo: read_color(reader)?,
+            normal: read_color(reader)?,
+            depth: read_f64(reader)?,
+            hits: read_u32(reader)?,
+            object: id(read_u32(reader)?),
+            material: id(read_u32(reader)?),
+            direct: read_color(reader)?,
+            indirect: read_color(reader)?,
+        },
+    })
+}
+
+pub fn write_splat(writer: &mut impl Write, splat: &Splat) -> io::Result<()> {
+    for value in [splat.sum[0], splat.sum[1], splat.sum[2], splat.weight] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn read_splat(reader: &mut impl Read) -> io::Result<Splat> {
+    let mut read_i128 = || {
+        let mut buf = [0u8; 16];
+        reader
+            .read_exact(&mut buf)
+            .map(|_| i128::from_le_bytes(buf))
+    };
+    Ok(Splat {
+        sum: [read_i128()?, read_i128()?, read_i128()?],
+        weight: read_i128()?,
+    })
+}
+
+fn write_color(writer: &mut impl Write, color: Color) -> io::Result<()> {
+    for value in [color.x, color.y, color.z] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_color(reader: &mut impl Read) -> io::Result<Color> {
+    Ok(Color::new(
+        read_f64(reader)?,
+        read_f64(reader)?,
+        read_f64(reader)?,
+    ))
+}
+
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+// Saves the film whenever the interval has passed since the last save. The film is copied
+// while the caller holds it and written on a thread of its own, so rendering never waits for
+// the disk. A save still being written when the next one is due delays that one
+pub struct Checkpointer {
+    interval: Duration,
+    last_save: Mutex<Instant>,
+    films: Option<SyncSender<Film>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Checkpointer {
+    pub fn new(path: PathBuf, interval: Duration, args: Vec<String>) -> Checkpointer {
+        let (films, received) = mpsc::sync_channel::<Film>(0);
+        let writer = thread::spawn(move || {
+            for film in received {
+                if let Err(e) = Checkpoint::save(&path, &args, &film) {
+                    eprintln!("Failed to write checkpoint {}: {}", path.display(), e);
+                }
+            }
+        });
+        Checkpointer {
+            interval,
+            last_save: Mutex::new(Instant::now()),
+            films: Some(films),
+            writer: Some(writer),
+        }
+    }
+
+    pub fn tick(&self, film: &Film) {
+        let mut last_save = self.last_save.lock().unwrap();
+        if last_save.elapsed() < self.interval {
+            return;
+        }
+        if let Some(films) = &self.films {
+            // Only taken when the writer is waiting for it, so this never blocks
+            if films.try_send(film.clone()).is_ok() {
+                *last_save = Instant::now();
+            }
+        }
+    }
+}
+
+// Waits for the last save to be written
+impl Drop for Checkpointer {
+    fn drop(&mut self) {
+        self.films.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let path = std::env::temp_dir().join("raytracing_weekend_checkpoint_round_trip.ckpt");
+        let args = vec![String::from("--samples"), String::from("64")];
+        let mut film = Film::new(3, 2);
+        film.pixels[4].add_sample(Color::new(0.1, 0.2, 0.3));
+        film.pixels[4].add_sample(Color::new(1.0 / 3.0, 0.0, 7.5));
+        film.pixels[4].aovs.depth = 2.5;
+        film.pixels[4].aovs.hits = 1;
+        film.pixels[4].aovs.object = Some(3);
+        film.splats[1].add(Color::new(-0.5, 2.0, 1e6), 0.75);
+
+        Checkpoint::save(&path, &args, &film).unwrap();
+        let checkpoint = Checkpoint::load(&path, (3, 2)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(args, checkpoint.args);
+        assert_eq!((3, 2), (checkpoint.film.width, checkpoint.film.height));
+        assert_eq!(film.pixels, checkpoint.film.pixels);
+        assert_eq!(film.splats, checkpoint.film.splats);
+    }
+
+    #[test]
+    fn checkpoint_rejects_other_files() {
+        let path = std::env::temp_dir().join("raytracing_weekend_checkpoint_rejects.ckpt");
+        std::fs::write(&path, b"P6\n1 1\n255\n").unwrap();
+        let result = Checkpoint::load(&path, (3, 2));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(io::ErrorKind::InvalidData, result.err().unwrap().kind());
+    }
+
+    #[test]
+    fn checkpoint_rejects_other_sizes() {
+        let path = std::env::temp_dir().join("raytracing_weekend_checkpoint_other_sizes.ckpt");
+        Checkpoint::save(&path, &[], &Film::new(3, 2)).unwrap();
+        // The header claims a huge film, it is refused before anything is allocated
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[12..20].copy_from_slice(&[0xff; 8]);
+        std::fs::write(&path, &bytes).unwrap();
+        let huge = Checkpoint::load(&path, (3, 2));
+        let other = Checkpoint::load(&path, (2, 3));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(io::ErrorKind::InvalidData, huge.err().unwrap().kind());
+        assert_eq!(io::ErrorKind::InvalidData, other.err().unwrap().kind());
+    }
+
+    #[test]
+    fn checkpointer_writes_in_the_background() {
+        let path = std::env::temp_dir().join("raytracing_weekend_checkpointer.ckpt");
+        let args = vec![String::from("--samples"), String::from("4")];
+        let mut film = Film::new(2, 2);
+        film.pixels[1].add_sample(Color::new(0.5, 0.25, 1.0));
+
+        let _ = std::fs::remove_file(&path);
+        let checkpointer = Checkpointer::new(path.clone(), Duration::ZERO, args.clone());
+        // Saves are skipped until the writer is ready for them, and renamed into place whole
+        while !path.exists() {
+            checkpointer.tick(&film);
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(checkpointer);
+        let checkpoint = Checkpoint::load(&path, (2, 2)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(args, checkpoint.args);
+        assert_eq!(film.pixels, checkpoint.film.pixels);
+    }
+
+    #[test]
+    fn checkpoint_limits_args() {
+        let args = |count: u32, length: u32| {
+            let mut bytes = count.to_le_bytes().to_vec();
+            bytes.extend_from_slice(&length.to_le_bytes());
+            bytes.extend_from_slice(b"--seed");
+            read_args(&mut &bytes[..])
+        };
+        assert_eq!(vec![String::from("--seed")], args(1, 6).unwrap());
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            args(u32::MAX, 6).err().unwrap().kind()
+        );
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            args(1, u32::MAX).err().unwrap().kind()
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use std::ops::Mul;
+use std::str::FromStr;
+
+use crate::vector::Color;
+
+// Row major 3x3 matrix, for linear transforms between color spaces
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Matrix3(pub [[f64; 3]; 3]);
+
+impl Matrix3 {
+    pub fn diagonal(v: Color) -> Matrix3 {
+        Matrix3([[v.x, 0.0, 0.0], [0.0, v.y, 0.0], [0.0, 0.0, v.z]])
+    }
+
+    pub fn from_columns(a: Color, b: Color, c: Color) -> Matrix3 {
+        Matrix3([[a.x, b.x, c.x], [a.y, b.y, c.y], [a.z, b.z, c.z]])
+    }
+
+    pub fn inverse(&self) -> Matrix3 {
+        let m = &self.0;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let adjugate = [
+            [
+                cofactor(1, 2, 1, 2),
+                -cofactor(0, 2, 1, 2),
+                cofactor(0, 1, 1, 2),
+            ],
+            [
+                -cofactor(1, 2, 0, 2),
+                cofactor(0, 2, 0, 2),
+                -cofactor(0, 1, 0, 2),
+            ],
+            [
+                cofactor(1, 2, 0, 1),
+                -cofactor(0, 2, 0, 1),
+                cofactor(0, 1, 0, 1),
+            ],
+        ];
+        let determinant =
+            m[0][0] * adjugate[0][0] + m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+        Matrix3(adjugate.map(|row| row.map(|v| v / determinant)))
+    }
+}
+
+impl Mul<Color> for Matrix3 {
+    type Output = Color;
+
+    fn mul(self, color: Color) -> Color {
+        let row = |r: &[f64; 3]| r[0] * color.x + r[1] * color.y + r[2] * color.z;
+        Color::new(row(&self.0[0]), row(&self.0[1]), row(&self.0[2]))
+    }
+}
+
+impl Mul for Matrix3 {
+    type Output = Matrix3;
+
+    fn mul(self, other: Matrix3) -> Matrix3 {
+        let mut result = [[0.0; 3]; 3];
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| self.0[i][k] * other.0[k][j]).sum();
+            }
+        }
+        Matrix3(result)
+    }
+}
+
+// RGB color spaces, by their primaries, white point and transfer function. Rendering
+// happens in a linear working space, sRGB or ACEScg, images are encoded for an output space
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorSpace {
+    // Rec. 709 primaries, the sRGB transfer function on output
+    Srgb,
+    // ACES AP1 primaries with the ACES white point, linear
+    AcesCg,
+    // DCI-P3 primaries with a D65 white point, the sRGB transfer function on output
+    DisplayP3,
+    // Rec. 2020 primaries, the Rec. 2020 transfer function on output
+    Rec2020,
+}
+
+// CIE 1931 xy chromaticities
+type Chromaticity = (f64, f64);
+
+const D65: Chromaticity = (0.3127, 0.3290);
+const ACES_WHITE: Chromaticity = (0.32168, 0.33767);
+
+// Bradford cone response, for adapting colors from one white point to another
+const BRADFORD: Matrix3 = Matrix3([
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+]);
+
+impl ColorSpace {
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorSpace::Srgb => "srgb",
+            ColorSpace::AcesCg => "acescg",
+            ColorSpace::DisplayP3 => "display-p3",
+            ColorSpace::Rec2020 => "rec2020",
+        }
+    }
+
+    // Red, green, blue and white
+    pub fn chromaticities(self) -> [Chromaticity; 4] {
+        match self {
+            ColorSpace::Srgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), D65],
+            ColorSpace::AcesCg => [(0.713, 0.293), (0.165, 0.830), (0.128, 0.044), ACES_WHITE],
+            ColorSpace::DisplayP3 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060), D65],
+            ColorSpace::Rec2020 => [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046), D65],
+        }
+    }
+
+    pub fn is_working_space(self) -> bool {
+        matches!(self, ColorSpace::Srgb | ColorSpace::AcesCg)
+    }
+
+    pub fn is_output_space(self) -> bool {
+        self != ColorSpace::AcesCg
+    }
+
+    // Linear RGB to CIE XYZ, white has a Y of 1
+    pub fn to_xyz(self) -> Matrix3 {
+        let [red, green, blue, white] = self.chromaticities().map(xyz);
+        let primaries = Matrix3::from_columns(red, green, blue);
+        let scale = primaries.inverse() * white;
+        primaries * Matrix3::diagonal(scale)
+    }
+
+    // Converts linear colors from this space to another, white stays white
+    pub fn conversion(self, to: ColorSpace) -> Matrix3 {
+        let adaptation = adapt(xyz(self.chromaticities()[3]), xyz(to.chromaticities()[3]));
+        to.to_xyz().inverse() * adaptation * self.to_xyz()
+    }
+
+    // Encodes a linear value for display, clipped to [0, 1]
+    pub fn encode(self, x: f64) -> f64 {
+        match self {
+            ColorSpace::Srgb | ColorSpace::DisplayP3 => srgb_oetf(x),
+            ColorSpace::AcesCg => x,
+            ColorSpace::Rec2020 => rec2020_oetf(x),
+        }
+    }
+}
+
+impl FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColorSpace, String> {
+        match s {
+            "srgb" | "rec709" => Ok(ColorSpace::Srgb),
+            "acescg" => Ok(ColorSpace::AcesCg),
+            "display-p3" => Ok(ColorSpace::DisplayP3),
+            "rec2020" => Ok(ColorSpace::Rec2020),
+            _ => Err(format!("unknown color space {}", s)),
+        }
+    }
+}
+
+// XYZ of the chromaticity, with a Y of 1
+fn xyz((x, y): Chromaticity) -> Color {
+    Color::new(x / y, 1.0, (1.0 - x - y) / y)
+}
+
+// Von Kries adaptation in the Bradford cone space, from XYZ colors seen under one white to
+// the XYZ colors they look like under another
+pub fn adapt(from: Color, to: Color) -> Matrix3 {
+    if from == to {
+        return Matrix3::diagonal(Color::one());
+    }
+    let (source, destination) = (BRADFORD * from, BRADFORD * to);
+    let scale = Color::new(
+        destination.x / source.x,
+        destination.y / source.y,
+        destination.z / source.z,
+    );
+    BRADFORD.inverse() * Matrix3::diagonal(scale) * BRADFORD
+}
+
+// sRGB encoding of a linear value, clipped to [0, 1]
+pub fn srgb_oetf(x: f64) -> f64 {
+    let x = x.clamp(0.0, 1.0);
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Rec. 2020 encoding of a linear value, clipped to [0, 1]
+pub fn rec2020_oetf(x: f64) -> f64 {
+    const ALPHA: f64 = 1.09929682680944;
+    const BETA: f64 = 0.018053968510807;
+    let x = x.clamp(0.0, 1.0);
+    if x < BETA {
+        4.5 * x
+    } else {
+        ALPHA * x.powf(0.45) - (ALPHA - 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+
+    fn assert_colors_equal(expected: Color, actual: Color) {
+        assert_approx_eq!(expected.x, actual.x, 1e-4);
+        assert_approx_eq!(expected.y, actual.y, 1e-4);
+        assert_approx_eq!(expected.z, actual.z, 1e-4);
+    }
+
+    #[test]
+    fn color_matrix_inverse() {
+        let m = Matrix3([[2.0, 1.0, 0.0], [0.0, 3.0, 1.0], [1.0, 0.0, 4.0]]);
+        let v = Color::new(1.0, -2.0, 0.5);
+
+        assert_colors_equal(v, m.inverse() * (m * v));
+        assert_colors_equal(m * (m * v), (m * m) * v);
+    }
+
+    #[test]
+    fn color_srgb_to_xyz() {
+        // The luminance weights are the middle row
+        let y = ColorSpace::Srgb.to_xyz().0[1];
+        assert_approx_eq!(0.2126, y[0], 1e-4);
+        assert_approx_eq!(0.7152, y[1], 1e-4);
+        assert_approx_eq!(0.0722, y[2], 1e-4);
+    }
+
+    #[test]
+    fn color_conversions() {
+        let to_acescg = ColorSpace::Srgb.conversion(ColorSpace::AcesCg);
+        assert_colors_equal(
+            Color::new(0.6131, 0.0702, 0.0206),
+            to_acescg * Color::new(1.0, 0.0, 0.0),
+        );
+
+        let spaces = [
+            ColorSpace::Srgb,
+            ColorSpace::AcesCg,
+            ColorSpace::DisplayP3,
+            ColorSpace::Rec2020,
+        ];
+        let color = Color::new(0.2, 0.5, 0.9);
+        for from in spaces {
+            for to in spaces {
+                let there = from.conversion(to);
+                assert_colors_equal(Color::one(), there * Color::one());
+                assert_colors_equal(color, to.conversion(from) * (there * color));
+            }
+        }
+    }
+
+    #[test]
+    fn color_transfer_functions() {
+        assert_eq!(0.0, srgb_oetf(-1.0));
+        assert_approx_eq!(12.92 * 0.002, srgb_oetf(0.002), 1e-12);
+        assert_approx_eq!(0.7354, srgb_oetf(0.5), 1e-4);
+        assert_approx_eq!(1.0, srgb_oetf(4.0), 1e-12);
+        assert_approx_eq!(4.5 * 0.01, rec2020_oetf(0.01), 1e-12);
+        assert_approx_eq!(0.7055, rec2020_oetf(0.5), 1e-4);
+        assert_approx_eq!(1.0, rec2020_oetf(1.0), 1e-12);
+    }
+
+    #[test]
+    fn color_space_from_str() {
+        assert_eq!(Ok(ColorSpace::Srgb), "rec709".parse());
+        assert_eq!(Ok(ColorSpace::DisplayP3), "display-p3".parse());
+        assert!("adobe-rgb".parse::<ColorSpace>().is_err());
+        assert!(!ColorSpace::AcesCg.is_output_space());
+        assert!(!ColorSpace::Rec2020.is_working_space());
+    }
+}
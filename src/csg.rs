@@ -0,0 +1,202 @@
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::{hit_spans, HitRecord, Hittable, Solid, Span};
+use crate::ray::Ray;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Operation {
+    Union,
+    Intersection,
+    // The first solid with the second cut out of it
+    Difference,
+}
+
+impl Operation {
+    fn inside(self, a: bool, b: bool) -> bool {
+        match self {
+            Operation::Union => a || b,
+            Operation::Intersection => a && b,
+            Operation::Difference => a && !b,
+        }
+    }
+}
+
+// Two solids combined by walking the spans of both along the ray and keeping where the
+// operation is inside. Surfaces keep the material of the solid they come from, the walls of a
+// cut are the inside of the solid cut out and face the other way
+pub struct Csg {
+    operation: Operation,
+    a: Box<dyn Solid>,
+    b: Box<dyn Solid>,
+}
+
+impl Csg {
+    pub fn new(operation: Operation, a: Box<dyn Solid>, b: Box<dyn Solid>) -> Csg {
+        Csg { operation, a, b }
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        hit_spans(&self.spans(ray, rng), ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match self.operation {
+            Operation::Union => Some(self.a.bounding_box()?.surrounding(self.b.bounding_box()?)),
+            // Never bigger than the first solid
+            Operation::Intersection | Operation::Difference => self.a.bounding_box(),
+        }
+    }
+}
+
+impl Solid for Csg {
+    fn spans(&self, ray: &Ray, rng: &mut dyn RngCore) -> Vec<Span> {
+        // Each crossing of either solid, with which one it belongs to and if it goes in
+        let mut crossings = Vec::new();
+        for (spans, from_b) in [
+            (self.a.spans(ray, rng), false),
+            (self.b.spans(ray, rng), true),
+        ] {
+            for span in spans {
+                crossings.push((span.enter, from_b, true));
+                crossings.push((span.exit, from_b, false));
+            }
+        }
+        crossings.sort_by(|a, b| a.0.t.total_cmp(&b.0.t));
+
+        let (mut in_a, mut in_b) = (false, false);
+        let mut enter = None;
+        let mut spans = Vec::new();
+        for (mut crossing, from_b, entering) in crossings {
+            if from_b {
+                in_b = entering;
+            } else {
+                in_a = entering;
+            }
+            if from_b && self.operation == Operation::Difference {
+                crossing.normal = -crossing.normal;
+            }
+            let inside = self.operation.inside(in_a, in_b);
+            match enter {
+                None if inside => enter = Some(crossing),
+                Some(start) if !inside => {
+                    spans.push(Span {
+                        enter: start,
+                        exit: crossing,
+                    });
+                    enter = None;
+                }
+                _ => {}
+            }
+        }
+        spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cuboid::Cuboid;
+    use crate::sphere::Sphere;
+    use crate::vector::{Point3, Vec3};
+    use assert_approx_eq::*;
+    use rand::prelude::*;
+
+    // Two unit spheres overlapping between x = -0.5 and 0.5, with materials 1 and 2
+    fn pair(operation: Operation) -> Csg {
+        Csg::new(
+            operation,
+            Box::new(Sphere::new(Point3::new(-0.5, 0.0, 0.0), 1.0, 1)),
+            Box::new(Sphere::new(Point3::new(0.5, 0.0, 0.0), 1.0, 2)),
+        )
+    }
+
+    fn along_x() -> Ray {
+        Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0))
+    }
+
+    // Where the spans start and end along the x axis
+    fn ends(csg: &Csg) -> Vec<(f64, f64)> {
+        csg.spans(&along_x(), &mut StdRng::seed_from_u64(1))
+            .iter()
+            .map(|span| (span.enter.t - 5.0, span.exit.t - 5.0))
+            .collect()
+    }
+
+    fn assert_ends(expected: &[(f64, f64)], csg: &Csg) {
+        let ends = ends(csg);
+        assert_eq!(expected.len(), ends.len(), "{:?}", ends);
+        for (expected, end) in expected.iter().zip(&ends) {
+            assert_approx_eq!(expected.0, end.0, 1e-12);
+            assert_approx_eq!(expected.1, end.1, 1e-12);
+        }
+    }
+
+    #[test]
+    fn csg_operations() {
+        assert_ends(&[(-1.5, 1.5)], &pair(Operation::Union));
+        assert_ends(&[(-0.5, 0.5)], &pair(Operation::Intersection));
+        assert_ends(&[(-1.5, -0.5)], &pair(Operation::Difference));
+
+        // Cutting the middle out of a solid leaves two pieces
+        let hollowed = Csg::new(
+            Operation::Difference,
+            Box::new(Sphere::new(Point3::zero(), 1.0, 1)),
+            Box::new(Sphere::new(Point3::zero(), 0.5, 2)),
+        );
+        assert_ends(&[(-1.0, -0.5), (0.5, 1.0)], &hollowed);
+    }
+
+    #[test]
+    fn csg_difference_flips_the_cut() {
+        let csg = pair(Operation::Difference);
+        let rng = &mut StdRng::seed_from_u64(1);
+
+        let record = csg.hit(&along_x(), 0.001, f64::MAX, rng).unwrap();
+        assert_eq!(1, record.material);
+        assert_eq!(Vec3::new(-1.0, 0.0, 0.0), record.normal);
+        assert!(record.front_face);
+
+        // Leaving through the wall of the cut, which faces into it
+        let record = csg.hit(&along_x(), record.t, f64::MAX, rng).unwrap();
+        assert_approx_eq!(4.5, record.t, 1e-12);
+        assert_eq!(2, record.material);
+        assert!(!record.front_face);
+        assert_eq!(Vec3::new(-1.0, 0.0, 0.0), record.normal);
+
+        // Seen from inside the cut the wall faces the ray
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let record = csg.hit(&ray, 0.001, f64::MAX, rng).unwrap();
+        assert_approx_eq!(0.5, record.t, 1e-12);
+        assert!(record.front_face);
+        assert_eq!(Vec3::new(1.0, 0.0, 0.0), record.normal);
+    }
+
+    #[test]
+    fn csg_nested() {
+        // A box with a hole through it along x, and another along y
+        let cuboid = Cuboid::new(Point3::one() * -1.0, Point3::one(), 0);
+        let hole_x = Cuboid::new(Point3::new(-2.0, -0.3, -0.3), Point3::new(2.0, 0.3, 0.3), 0);
+        let hole_y = Cuboid::new(Point3::new(-0.3, -2.0, -0.3), Point3::new(0.3, 2.0, 0.3), 0);
+        let holes = Csg::new(Operation::Union, Box::new(hole_x), Box::new(hole_y));
+        let csg = Csg::new(Operation::Difference, Box::new(cuboid), Box::new(holes));
+        let rng = &mut StdRng::seed_from_u64(1);
+
+        assert!(csg.hit(&along_x(), 0.001, f64::MAX, rng).is_none());
+        let down = Ray::new(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(csg.hit(&down, 0.001, f64::MAX, rng).is_none());
+        // Down beside the hole the ray hits the top, then the floor of the x hole
+        let down = Ray::new(Point3::new(0.5, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let spans = csg.spans(&down, rng);
+        assert_eq!(2, spans.len());
+        assert_approx_eq!(4.0, spans[0].enter.t, 1e-12);
+        assert_approx_eq!(4.7, spans[0].exit.t, 1e-12);
+        assert_eq!(Vec3::new(0.0, -1.0, 0.0), spans[0].exit.normal);
+        assert_eq!(
+            Some(Aabb::new(Point3::one() * -1.0, Point3::one())),
+            csg.bounding_box()
+        );
+    }
+}
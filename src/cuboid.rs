@@ -0,0 +1,115 @@
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::{hit_spans, Crossing, HitRecord, Hittable, Solid, Span};
+use crate::ray::Ray;
+use crate::vector::Point3;
+
+// A box along the axes. On each face u and v go across it along the two other axes in order
+pub struct Cuboid {
+    bounds: Aabb,
+    material: usize,
+}
+
+impl Cuboid {
+    pub fn new(min: Point3, max: Point3, material: usize) -> Cuboid {
+        Cuboid {
+            bounds: Aabb::new(min, max),
+            material,
+        }
+    }
+
+    fn crossing(&self, ray: &Ray, t: f64) -> Crossing {
+        let Aabb { min, max } = self.bounds;
+        let center = (min + max) / 2.0;
+        let half = (max - min) / 2.0;
+        // The face is on the axis where the point is furthest out
+        let offset = ray.at(t) - center;
+        let scaled = [offset.x / half.x, offset.y / half.y, offset.z / half.z];
+        let axis = (0..3)
+            .max_by(|&a, &b| scaled[a].abs().total_cmp(&scaled[b].abs()))
+            .unwrap();
+        let mut normal = Point3::zero();
+        let sign = scaled[axis].signum();
+        let across = |a: usize| (scaled[a] + 1.0) / 2.0;
+        let (u, v) = match axis {
+            0 => {
+                normal.x = sign;
+                (across(1), across(2))
+            }
+            1 => {
+                normal.y = sign;
+                (across(0), across(2))
+            }
+            _ => {
+                normal.z = sign;
+                (across(0), across(1))
+            }
+        };
+        Crossing {
+            t,
+            normal,
+            material: self.material,
+            u,
+            v,
+        }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        hit_spans(&self.spans(ray, rng), ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
+
+impl Solid for Cuboid {
+    fn spans(&self, ray: &Ray, _rng: &mut dyn RngCore) -> Vec<Span> {
+        match self.bounds.hit(ray, f64::NEG_INFINITY, f64::INFINITY) {
+            Some((enter, exit)) => vec![Span {
+                enter: self.crossing(ray, enter),
+                exit: self.crossing(ray, exit),
+            }],
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::Vec3;
+    use assert_approx_eq::*;
+    use rand::prelude::*;
+
+    #[test]
+    fn cuboid_hit() {
+        let cuboid = Cuboid::new(Point3::new(-1.0, -2.0, -1.0), Point3::new(1.0, 2.0, 1.0), 0);
+        let rng = &mut StdRng::seed_from_u64(1);
+
+        let ray = Ray::new(Point3::new(0.5, 1.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let record = cuboid.hit(&ray, 0.001, f64::MAX, rng).unwrap();
+        assert_approx_eq!(4.0, record.t, 1e-12);
+        assert_eq!(Vec3::new(0.0, 0.0, 1.0), record.normal);
+        assert!(record.front_face);
+        assert_approx_eq!(0.75, record.u, 1e-12);
+        assert_approx_eq!(0.75, record.v, 1e-12);
+
+        // From inside the far face faces away
+        let ray = Ray::new(Point3::zero(), Vec3::new(0.0, -1.0, 0.0));
+        let record = cuboid.hit(&ray, 0.001, f64::MAX, rng).unwrap();
+        assert_approx_eq!(2.0, record.t, 1e-12);
+        assert!(!record.front_face);
+        assert_eq!(Vec3::new(0.0, 1.0, 0.0), record.normal);
+
+        let spans = cuboid.spans(&ray, rng);
+        assert_eq!(1, spans.len());
+        assert_approx_eq!(-2.0, spans[0].enter.t, 1e-12);
+
+        let ray = Ray::new(Point3::new(3.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(cuboid.hit(&ray, 0.001, f64::MAX, rng).is_none());
+    }
+}
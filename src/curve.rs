@@ -0,0 +1,375 @@
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::quadric::Frame;
+use crate::ray::Ray;
+use crate::vector::{Point3, Vec3};
+
+// The most times a curve is split in half, into 2^10 pieces
+const MAX_DEPTH: u32 = 10;
+
+#[derive(Debug, Copy, Clone)]
+enum Profile {
+    // A strip that always faces the ray, shaded as if it were round
+    Cylinder,
+    // A flat strip that turns from the first normal to the second along the curve, narrower
+    // when seen from the side
+    Ribbon([Vec3; 2]),
+}
+
+// A cubic Bézier curve swept out to a width that changes linearly from one end to the other,
+// for hair, fur and grass. Rays are tested against straight pieces of the curve, found by
+// splitting it in halves until the pieces are within a twentieth of its width of the curve.
+// u runs along the curve and v across it
+pub struct Curve {
+    points: [Point3; 4],
+    widths: [f64; 2],
+    profile: Profile,
+    depth: u32,
+    bounds: Aabb,
+    material: usize,
+}
+
+// The ray in a frame where it starts at the origin and runs along z
+struct Query<'a> {
+    ray: &'a Ray,
+    length: f64,
+    t_min: f64,
+    t_max: f64,
+}
+
+impl Curve {
+    pub fn cylinder(points: [Point3; 4], widths: [f64; 2], material: usize) -> Curve {
+        Curve::new(points, widths, Profile::Cylinder, material)
+    }
+
+    pub fn ribbon(
+        points: [Point3; 4],
+        widths: [f64; 2],
+        normals: [Vec3; 2],
+        material: usize,
+    ) -> Curve {
+        let normals = normals.map(Vec3::unit_vector);
+        Curve::new(points, widths, Profile::Ribbon(normals), material)
+    }
+
+    fn new(points: [Point3; 4], widths: [f64; 2], profile: Profile, material: usize) -> Curve {
+        let width = widths[0].max(widths[1]);
+        // The curve stays inside the hull of its control points
+        let bounds = points
+            .iter()
+            .map(|&p| Aabb::new(p, p))
+            .reduce(Aabb::surrounding)
+            .unwrap()
+            .padded(width * 0.5);
+        // Each split divides how far the pieces stray from straight lines by four
+        let bend = (0..2)
+            .map(|i| (points[i] - points[i + 1] * 2.0 + points[i + 2]).length())
+            .fold(0.0, f64::max);
+        let depth = (2.0f64.sqrt() * 6.0 * bend / (8.0 * width * 0.05)).log2() * 0.5;
+        Curve {
+            points,
+            widths,
+            profile,
+            depth: depth.round().clamp(0.0, MAX_DEPTH as f64) as u32,
+            bounds,
+            material,
+        }
+    }
+
+    fn width(&self, u: f64) -> f64 {
+        self.widths[0] * (1.0 - u) + self.widths[1] * u
+    }
+
+    fn ribbon_normal(normals: [Vec3; 2], u: f64) -> Vec3 {
+        (normals[0] * (1.0 - u) + normals[1] * u).unit_vector()
+    }
+
+    // Closest hit on the part of the curve from u0 to u1, as t, u and v
+    fn intersect(
+        &self,
+        query: &Query,
+        points: [Point3; 4],
+        (u0, u1): (f64, f64),
+        depth: u32,
+        closest: &mut Option<(f64, f64, f64)>,
+    ) {
+        let t_max = closest.map_or(query.t_max, |(t, _, _)| t);
+        let half_width = self.width(u0).max(self.width(u1)) * 0.5;
+        let (min, max) = points.iter().fold(
+            (
+                Point3::one() * f64::INFINITY,
+                Point3::one() * -f64::INFINITY,
+            ),
+            |(min, max), p| {
+                (
+                    Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                    Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+                )
+            },
+        );
+        if min.x - half_width > 0.0
+            || max.x + half_width < 0.0
+            || min.y - half_width > 0.0
+            || max.y + half_width < 0.0
+            || min.z - half_width > t_max * query.length
+            || max.z + half_width < query.t_min * query.length
+        {
+            return;
+        }
+
+        if depth > 0 {
+            let split = split(points);
+            let middle = (u0 + u1) * 0.5;
+            let first = [split[0], split[1], split[2], split[3]];
+            let second = [split[3], split[4], split[5], split[6]];
+            self.intersect(query, first, (u0, middle), depth - 1, closest);
+            self.intersect(query, second, (middle, u1), depth - 1, closest);
+            return;
+        }
+
+        // Past the ends of the piece, which the pieces next to it cover
+        let beyond = |a: Point3, b: Point3| (b.x - a.x) * -a.x + (b.y - a.y) * -a.y < 0.0;
+        if beyond(points[0], points[1]) || beyond(points[3], points[2]) {
+            return;
+        }
+
+        // Where the ray passes closest to the piece, taken as a straight line
+        let (dx, dy) = (points[3].x - points[0].x, points[3].y - points[0].y);
+        let denominator = dx * dx + dy * dy;
+        if denominator == 0.0 {
+            return;
+        }
+        let w = -(points[0].x * dx + points[0].y * dy) / denominator;
+        let u = (u0 + (u1 - u0) * w).clamp(u0, u1);
+        let mut width = self.width(u);
+        if let Profile::Ribbon(normals) = self.profile {
+            let normal = Curve::ribbon_normal(normals, u);
+            width *= normal.dot(query.ray.direction).abs() / query.length;
+        }
+        if width <= 0.0 {
+            return;
+        }
+
+        let (point, derivative) = evaluate(points, w.clamp(0.0, 1.0));
+        let distance_squared = point.x * point.x + point.y * point.y;
+        if distance_squared > width * width * 0.25 {
+            return;
+        }
+        let t = point.z / query.length;
+        if t <= query.t_min || t >= t_max {
+            return;
+        }
+        // Which side of the curve the ray passes
+        let offset = distance_squared.sqrt() / width;
+        let v = if derivative.x * -point.y + point.x * derivative.y > 0.0 {
+            0.5 + offset
+        } else {
+            0.5 - offset
+        };
+        *closest = Some((t, u, v));
+    }
+}
+
+// The control points of both halves, the middle point shared
+fn split(p: [Point3; 4]) -> [Point3; 7] {
+    [
+        p[0],
+        (p[0] + p[1]) * 0.5,
+        (p[0] + p[1] * 2.0 + p[2]) * 0.25,
+        (p[0] + p[1] * 3.0 + p[2] * 3.0 + p[3]) * 0.125,
+        (p[1] + p[2] * 2.0 + p[3]) * 0.25,
+        (p[2] + p[3]) * 0.5,
+        p[3],
+    ]
+}
+
+// The point at u and the derivative there, by de Casteljau's algorithm
+fn evaluate(p: [Point3; 4], u: f64) -> (Point3, Vec3) {
+    let lerp = |a: Point3, b: Point3| a * (1.0 - u) + b * u;
+    let q = [lerp(p[0], p[1]), lerp(p[1], p[2]), lerp(p[2], p[3])];
+    let r = [lerp(q[0], q[1]), lerp(q[1], q[2])];
+    let derivative = (r[1] - r[0]) * 3.0;
+    // Control points on top of each other leave no derivative at the ends
+    let derivative = if derivative.length_squared() > 0.0 {
+        derivative
+    } else {
+        p[3] - p[0]
+    };
+    (lerp(r[0], r[1]), derivative)
+}
+
+impl Hittable for Curve {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let frame = Frame::new(ray.origin, ray.direction);
+        let query = Query {
+            ray,
+            length: ray.direction.length(),
+            t_min,
+            t_max,
+        };
+        let points = self.points.map(|p| frame.local_point(p));
+        let mut closest = None;
+        self.intersect(&query, points, (0.0, 1.0), self.depth, &mut closest);
+        let (t, u, v) = closest?;
+
+        let (center, tangent) = evaluate(self.points, u);
+        let tangent = tangent.unit_vector();
+        let point = ray.at(t);
+        let across = |v: Vec3| v - tangent * v.dot(tangent);
+        let normal = match self.profile {
+            Profile::Ribbon(normals) => {
+                let normal = across(Curve::ribbon_normal(normals, u)).unit_vector();
+                if normal.dot(ray.direction) > 0.0 {
+                    -normal
+                } else {
+                    normal
+                }
+            }
+            Profile::Cylinder => {
+                // The normal of a round fibre where the ray meets it, from how far off the
+                // middle of the strip it passes
+                let facing = across(-ray.direction).unit_vector();
+                let offset = across(point - center);
+                let offset = offset - facing * offset.dot(facing);
+                let s = (offset.length() / (self.width(u) * 0.5)).min(1.0);
+                if s > 0.0 {
+                    facing * (1.0 - s * s).sqrt() + offset.unit_vector() * s
+                } else {
+                    facing
+                }
+            }
+        };
+        Some(
+            HitRecord::new(point, normal, t, true, self.material)
+                .with_uv(u, v)
+                .with_tangent(tangent),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+    use rand::prelude::*;
+
+    fn straight(widths: [f64; 2]) -> Curve {
+        let points = [-1.0, -1.0 / 3.0, 1.0 / 3.0, 1.0].map(|x| Point3::new(x, 0.0, 0.0));
+        Curve::cylinder(points, widths, 0)
+    }
+
+    fn from_above(x: f64, y: f64) -> Ray {
+        Ray::new(Point3::new(x, y, 5.0), Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn curve_cylinder() {
+        let curve = straight([0.2, 0.2]);
+        let rng = &mut thread_rng();
+
+        let record = curve
+            .hit(&from_above(0.3, 0.05), 0.001, f64::MAX, rng)
+            .unwrap();
+        assert_approx_eq!(5.0, record.t, 1e-9);
+        assert_approx_eq!(0.65, record.u, 1e-9);
+        assert_approx_eq!(0.25, (record.v - 0.5).abs(), 1e-9);
+        assert!(record.front_face);
+        // Halfway to the edge, the normal of a cylinder
+        assert_approx_eq!(0.5, record.normal.y, 1e-9);
+        assert_approx_eq!(0.75f64.sqrt(), record.normal.z, 1e-9);
+        assert_approx_eq!(1.0, record.tangent.x, 1e-9);
+
+        assert!(curve
+            .hit(&from_above(0.3, 0.15), 0.001, f64::MAX, rng)
+            .is_none());
+        assert!(curve
+            .hit(&from_above(1.2, 0.0), 0.001, f64::MAX, rng)
+            .is_none());
+        assert!(curve.hit(&from_above(0.3, 0.0), 0.001, 4.0, rng).is_none());
+    }
+
+    #[test]
+    fn curve_width_varies() {
+        let curve = straight([0.4, 0.0]);
+        let rng = &mut thread_rng();
+
+        assert!(curve
+            .hit(&from_above(-0.9, 0.15), 0.001, f64::MAX, rng)
+            .is_some());
+        assert!(curve
+            .hit(&from_above(0.9, 0.15), 0.001, f64::MAX, rng)
+            .is_none());
+        assert!(curve
+            .hit(&from_above(0.9, 0.0), 0.001, f64::MAX, rng)
+            .is_some());
+    }
+
+    #[test]
+    fn curve_bent() {
+        let points = [
+            Point3::new(-1.0, 0.0, 0.0),
+            Point3::new(-0.5, 1.0, 0.3),
+            Point3::new(0.5, 1.0, -0.3),
+            Point3::new(1.0, 0.0, 0.0),
+        ];
+        let width = 0.05;
+        let curve = Curve::cylinder(points, [width, width], 0);
+        assert!(curve.depth > 0);
+        let bounds = curve.bounding_box().unwrap();
+        let mut rng = StdRng::seed_from_u64(4);
+
+        // Rays aimed at points of the curve from all around hit it there, unless another
+        // part of the curve is in the way
+        let mut seen = 0;
+        for _ in 0..200 {
+            let u = rng.gen_range(0.02..0.98);
+            let (target, _) = evaluate(points, u);
+            let inside = Ray::new(target, Vec3::new(1.0, 0.0, 0.0));
+            assert!(bounds.hit(&inside, -1.0, 1.0).is_some());
+            let origin = target + Vec3::random_unit_vector(&mut rng) * 3.0;
+            let ray = Ray::new(origin, target - origin);
+            let record = curve.hit(&ray, 0.001, f64::MAX, &mut rng).unwrap();
+            assert!(record.t < 1.0 + width);
+            assert!(record.normal.dot(ray.direction) < 0.0);
+            assert_approx_eq!(0.0, record.normal.dot(record.tangent), 1e-9);
+            if (record.point - target).length() < width {
+                assert!((record.u - u).abs() < 0.05, "{} {}", u, record.u);
+                seen += 1;
+            }
+        }
+        assert!(seen > 180, "{}", seen);
+    }
+
+    #[test]
+    fn curve_ribbon() {
+        let points = [-1.0, -1.0 / 3.0, 1.0 / 3.0, 1.0].map(|x| Point3::new(x, 0.0, 0.0));
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let curve = Curve::ribbon(points, [0.2, 0.2], [normal, normal], 0);
+        let rng = &mut thread_rng();
+
+        let record = curve
+            .hit(&from_above(0.3, 0.09), 0.001, f64::MAX, rng)
+            .unwrap();
+        assert_approx_eq!(1.0, record.normal.z, 1e-9);
+        let below = Ray::new(Point3::new(0.3, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let record = curve.hit(&below, 0.001, f64::MAX, rng).unwrap();
+        assert_approx_eq!(-1.0, record.normal.z, 1e-9);
+
+        // Seen at sixty degrees it looks half as wide but so does the offset, edge on it
+        // disappears
+        let slanted = |y: f64| {
+            let direction = Vec3::new(0.0, -(0.75f64.sqrt()), -0.5);
+            Ray::new(Point3::new(0.3, y, 0.0) - direction * 4.0, direction)
+        };
+        assert!(curve.hit(&slanted(0.08), 0.001, f64::MAX, rng).is_some());
+        assert!(curve.hit(&slanted(0.12), 0.001, f64::MAX, rng).is_none());
+        let edge_on = Ray::new(Point3::new(0.3, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(curve.hit(&edge_on, 0.001, f64::MAX, rng).is_none());
+    }
+}
@@ -0,0 +1,367 @@
+use std::str::FromStr;
+
+use rayon::prelude::*;
+
+use crate::aov::Aov;
+use crate::film::Film;
+use crate::vector::*;
+
+// Edge stopping: how far the features of two pixels may differ before they stop mixing
+const SIGMA_NORMAL: f64 = 64.0;
+const SIGMA_ALBEDO: f64 = 0.1;
+// Relative to the depth of the pixel
+const SIGMA_DEPTH: f64 = 0.05;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Denoiser {
+    // Five passes of a 5x5 wavelet filter with growing gaps, fast and wide
+    Atrous,
+    // Joint bilateral filter over a 13x13 window
+    Bilateral,
+    // Non-local means, compares 3x3 patches over a 13x13 window
+    NonLocalMeans,
+}
+
+impl FromStr for Denoiser {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Denoiser, String> {
+        match s {
+            "atrous" => Ok(Denoiser::Atrous),
+            "bilateral" => Ok(Denoiser::Bilateral),
+            "nlm" => Ok(Denoiser::NonLocalMeans),
+            _ => Err(format!("unknown denoiser {}", s)),
+        }
+    }
+}
+
+// A noisy image and the features that guide the filter, from the first hits of the camera
+// rays
+#[derive(Clone)]
+pub struct Guide {
+    pub width: u32,
+    pub height: u32,
+    pub color: Vec<Color>,
+    pub albedo: Vec<Color>,
+    pub normal: Vec<Vec3>,
+    // Infinite where nothing was hit
+    pub depth: Vec<f64>,
+}
+
+impl Guide {
+    pub fn from_film(film: &Film) -> Guide {
+        Guide {
+            width: film.width,
+            height: film.height,
+            color: film.colors(),
+            albedo: film.aov(Aov::Albedo),
+            normal: film.aov(Aov::Normal),
+            depth: film.aov(Aov::Depth).iter().map(|d| d.x).collect(),
+        }
+    }
+
+    // How much the features of two pixels agree, from 0 to 1
+    fn feature_weight(&self, p: usize, q: usize) -> f64 {
+        let (depth_p, depth_q) = (self.depth[p], self.depth[q]);
+        let depth = match (depth_p.is_finite(), depth_q.is_finite()) {
+            (true, true) => (-(depth_p - depth_q).abs() / (SIGMA_DEPTH * depth_p.max(1e-3))).exp(),
+            (false, false) => 1.0,
+            _ => return 0.0,
+        };
+        // Compared by angle only, normals averaged over samples that disagree are shorter
+        let (normal_p, normal_q) = (self.normal[p], self.normal[q]);
+        let lengths = normal_p.length() * normal_q.length();
+        let normal = if normal_p.length_squared() == 0.0 && normal_q.length_squared() == 0.0 {
+            1.0
+        } else if lengths == 0.0 {
+            0.0
+        } else {
+            (normal_p.dot(normal_q) / lengths)
+                .max(0.0)
+                .powf(SIGMA_NORMAL)
+        };
+        let albedo = (self.albedo[p] - self.albedo[q]).length_squared();
+        depth * normal * (-albedo / (SIGMA_ALBEDO * SIGMA_ALBEDO)).exp()
+    }
+
+    fn index(&self, x: i64, y: i64) -> Option<usize> {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            None
+        } else {
+            Some((y * self.width as i64 + x) as usize)
+        }
+    }
+}
+
+impl Denoiser {
+    // Smooths the color of the guide where its features say the surface is the same. The
+    // filters work on the light arriving at the surface, the color divided by the albedo, so
+    // that texture stays sharp
+    pub fn denoise(self, guide: &Guide) -> Vec<Color> {
+        let illumination: Vec<Color> = guide
+            .color
+            .iter()
+            .zip(&guide.albedo)
+            .map(|(&color, &albedo)| map(color, albedo, |c, a| if a > 0.01 { c / a } else { c }))
+            .collect();
+
+        let filtered = match self {
+            Denoiser::Atrous => (0..5).fold(illumination, |image, i| atrous(guide, &image, i)),
+            Denoiser::Bilateral => bilateral(guide, &illumination),
+            Denoiser::NonLocalMeans => non_local_means(guide, &illumination),
+        };
+
+        filtered
+            .iter()
+            .zip(&guide.albedo)
+            .map(|(&light, &albedo)| map(light, albedo, |l, a| if a > 0.01 { l * a } else { l }))
+            .collect()
+    }
+}
+
+fn map(a: Color, b: Color, f: impl Fn(f64, f64) -> f64) -> Color {
+    Color::new(f(a.x, b.x), f(a.y, b.y), f(a.z, b.z))
+}
+
+// Weighted average over the neighbours the window function returns, with their weights
+fn filter<W>(guide: &Guide, image: &[Color], neighbours: W) -> Vec<Color>
+where
+    W: Fn(usize, i64, i64) -> Vec<(usize, f64)> + Sync,
+{
+    (0..image.len())
+        .into_par_iter()
+        .map(|p| {
+            let (x, y) = (
+                (p as u32 % guide.width) as i64,
+                (p as u32 / guide.width) as i64,
+            );
+            let (sum, total) = neighbours(p, x, y)
+                .into_iter()
+                .fold((Color::zero(), 0.0), |(sum, total), (q, weight)| {
+                    (sum + image[q] * weight, total + weight)
+                });
+            if total > 0.0 {
+                sum / total
+            } else {
+                image[p]
+            }
+        })
+        .collect()
+}
+
+fn color_weight(image: &[Color], p: usize, q: usize, sigma: f64) -> f64 {
+    (-(image[p] - image[q]).length_squared() / (sigma * sigma)).exp()
+}
+
+// One pass of the edge-avoiding à-trous wavelet filter of Dammertz et al., the taps spread
+// out twice as far every pass while the color tolerance halves
+fn atrous(guide: &Guide, image: &[Color], pass: u32) -> Vec<Color> {
+    const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+    let step = 1i64 << pass;
+    let sigma_color = 0.5f64.powi(pass as i32);
+    filter(guide, image, |p, x, y| {
+        let mut taps = Vec::with_capacity(25);
+        for (j, ky) in KERNEL.iter().enumerate() {
+            for (i, kx) in KERNEL.iter().enumerate() {
+                let (dx, dy) = ((i as i64 - 2) * step, (j as i64 - 2) * step);
+                if let Some(q) = guide.index(x + dx, y + dy) {
+                    let weight = kx
+                        * ky
+                        * guide.feature_weight(p, q)
+                        * color_weight(image, p, q, sigma_color);
+                    taps.push((q, weight));
+                }
+            }
+        }
+        taps
+    })
+}
+
+const RADIUS: i64 = 6;
+
+fn bilateral(guide: &Guide, image: &[Color]) -> Vec<Color> {
+    const SIGMA_SPATIAL: f64 = 3.0;
+    const SIGMA_COLOR: f64 = 1.0;
+    filter(guide, image, |p, x, y| {
+        let mut taps = Vec::new();
+        for dy in -RADIUS..=RADIUS {
+            for dx in -RADIUS..=RADIUS {
+                if let Some(q) = guide.index(x + dx, y + dy) {
+                    let distance = (dx * dx + dy * dy) as f64;
+                    let spatial = (-distance / (2.0 * SIGMA_SPATIAL * SIGMA_SPATIAL)).exp();
+                    let weight = spatial
+                        * guide.feature_weight(p, q)
+                        * color_weight(image, p, q, SIGMA_COLOR);
+                    taps.push((q, weight));
+                }
+            }
+        }
+        taps
+    })
+}
+
+// Buades' non-local means: pixels mix in proportion to how alike the 3x3 patches around them
+// are, which averages noise away better than comparing single pixels
+fn non_local_means(guide: &Guide, image: &[Color]) -> Vec<Color> {
+    const H: f64 = 0.5;
+    let patch_distance = |x: i64, y: i64, dx: i64, dy: i64| {
+        let (mut distance, mut count) = (0.0, 0);
+        for py in -1..=1 {
+            for px in -1..=1 {
+                let p = guide.index(x + px, y + py);
+                let q = guide.index(x + dx + px, y + dy + py);
+                if let (Some(p), Some(q)) = (p, q) {
+                    distance += (image[p] - image[q]).length_squared();
+                    count += 1;
+                }
+            }
+        }
+        distance / count.max(1) as f64
+    };
+    filter(guide, image, |p, x, y| {
+        let mut taps = Vec::new();
+        for dy in -RADIUS..=RADIUS {
+            for dx in -RADIUS..=RADIUS {
+                if let Some(q) = guide.index(x + dx, y + dy) {
+                    let similarity = (-patch_distance(x, y, dx, dy) / (H * H)).exp();
+                    taps.push((q, similarity * guide.feature_weight(p, q)));
+                }
+            }
+        }
+        taps
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::hittable_list::HittableList;
+    use crate::render::tests::{config, render_new};
+    use crate::sampler::SamplerKind;
+    use assert_approx_eq::*;
+    use rand::prelude::*;
+
+    const DENOISERS: [Denoiser; 3] = [
+        Denoiser::Atrous,
+        Denoiser::Bilateral,
+        Denoiser::NonLocalMeans,
+    ];
+
+    // A noisy grey image of two walls meeting in the middle, the right one red
+    fn walls() -> (Guide, Vec<Color>) {
+        let (width, height) = (32, 16);
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut guide = Guide {
+            width,
+            height,
+            color: Vec::new(),
+            albedo: Vec::new(),
+            normal: Vec::new(),
+            depth: Vec::new(),
+        };
+        let mut clean = Vec::new();
+        for _ in 0..height {
+            for x in 0..width {
+                let left = x < width / 2;
+                let albedo = if left {
+                    Color::one() * 0.5
+                } else {
+                    Color::new(0.8, 0.1, 0.1)
+                };
+                let light = 0.8;
+                clean.push(albedo * light);
+                let noise = 0.6 + 0.8 * rng.gen::<f64>();
+                guide.color.push(albedo * light * noise);
+                guide.albedo.push(albedo);
+                guide.normal.push(if left {
+                    Vec3::new(1.0, 0.0, 0.0)
+                } else {
+                    Vec3::new(0.0, 0.0, 1.0)
+                });
+                guide.depth.push(2.0);
+            }
+        }
+        (guide, clean)
+    }
+
+    fn error(image: &[Color], clean: &[Color]) -> f64 {
+        image
+            .iter()
+            .zip(clean)
+            .map(|(a, b)| (*a - *b).length_squared())
+            .sum::<f64>()
+            / image.len() as f64
+    }
+
+    #[test]
+    fn denoise_reduces_noise() {
+        let (guide, clean) = walls();
+        let noisy = error(&guide.color, &clean);
+        for denoiser in DENOISERS {
+            let denoised = error(&denoiser.denoise(&guide), &clean);
+            assert!(
+                denoised < noisy / 4.0,
+                "{:?} {} {}",
+                denoiser,
+                denoised,
+                noisy
+            );
+        }
+    }
+
+    #[test]
+    fn denoise_keeps_edges() {
+        let (guide, _) = walls();
+        for denoiser in DENOISERS {
+            let image = denoiser.denoise(&guide);
+            // The columns either side of the edge keep their own color
+            for y in 0..guide.height {
+                let row = (y * guide.width) as usize;
+                let (left, right) = (image[row + 15], image[row + 16]);
+                assert!((left.x - left.z).abs() < 0.05, "{:?} {}", denoiser, left);
+                assert!(right.x > 4.0 * right.z, "{:?} {}", denoiser, right);
+            }
+        }
+    }
+
+    #[test]
+    fn denoise_leaves_clean_images_alone() {
+        let (mut guide, clean) = walls();
+        guide.color = clean.clone();
+        for denoiser in DENOISERS {
+            assert!(error(&denoiser.denoise(&guide), &clean) < 1e-20);
+        }
+    }
+
+    #[test]
+    fn denoise_compares_normals_by_angle() {
+        let (mut guide, _) = walls();
+        let (same, edge) = (guide.feature_weight(0, 1), guide.feature_weight(15, 16));
+        guide.normal[1] *= 0.3;
+        guide.normal[16] *= 0.3;
+
+        assert_approx_eq!(same, guide.feature_weight(0, 1), 1e-12);
+        assert_approx_eq!(edge, guide.feature_weight(15, 16), 1e-12);
+        guide.normal[1] = Vec3::zero();
+        assert_eq!(0.0, guide.feature_weight(0, 1));
+    }
+
+    #[test]
+    fn denoise_render() {
+        let (camera, world) = (Camera::new(0.0), HittableList::new());
+        let noisy = render_new(&camera, &world, &config(4, SamplerKind::Independent, 1, 8));
+        let reference = render_new(&camera, &world, &config(1024, SamplerKind::Sobol, 1, 8));
+        let denoised = Denoiser::Atrous.denoise(&Guide::from_film(&noisy));
+
+        assert!(
+            error(&denoised, &reference.colors()) < error(&noisy.colors(), &reference.colors())
+        );
+    }
+
+    #[test]
+    fn denoiser_from_str() {
+        assert_eq!(Ok(Denoiser::NonLocalMeans), "nlm".parse());
+        assert!("oidn".parse::<Denoiser>().is_err());
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use crate::subdivision::{edge, Corner, Edge, PolyMesh, Topology};
+use crate::texture::ScalarTexture;
+use crate::vector::Vec3;
+
+// More than enough for any detail that shows in the image, at a few gigabytes of memory
+const MAX_TRIANGLES: usize = 1 << 25;
+
+impl PolyMesh {
+    // Triangles with no edge longer than the given length. Every long edge is split at its
+    // middle, once for both faces on it so the surface has no cracks, and each triangle is
+    // divided to fit the edges split around it. Repeats until all edges are short enough, or
+    // fails before there would be too many triangles
+    pub fn tessellated(&self, max_edge: f64) -> Result<PolyMesh, String> {
+        self.tessellated_within(max_edge, MAX_TRIANGLES)
+    }
+
+    fn tessellated_within(&self, max_edge: f64, max_triangles: usize) -> Result<PolyMesh, String> {
+        if max_edge.is_nan() || max_edge <= 0.0 {
+            return Err(format!("invalid edge length {}", max_edge));
+        }
+        let mut mesh = self.triangulated();
+        loop {
+            let topology = Topology::new(&mesh);
+            let mut middles = HashMap::new();
+            for &(a, b) in &topology.edges {
+                let (pa, pb) = (mesh.positions[a], mesh.positions[b]);
+                if (pb - pa).length() > max_edge {
+                    mesh.positions.push((pa + pb) * 0.5);
+                    middles.insert((a, b), mesh.positions.len() - 1);
+                }
+            }
+            if middles.is_empty() {
+                return Ok(mesh);
+            }
+            // Each split edge adds a triangle on either side of it
+            if mesh.faces.len() + 2 * middles.len() > max_triangles {
+                return Err(format!(
+                    "edges of {} need more than {} triangles",
+                    max_edge, max_triangles
+                ));
+            }
+            mesh = mesh.split(&middles);
+        }
+    }
+
+    fn split(mut self, middles: &HashMap<Edge, usize>) -> PolyMesh {
+        let mut uv_middles = HashMap::new();
+        let mut faces = Vec::with_capacity(self.faces.len() * 2);
+        for face in &self.faces {
+            // Turned so the split edges come first, as seen from the first corner
+            let split = |i: usize| {
+                let (a, b) = (face[i].position, face[(i + 1) % 3].position);
+                middles.contains_key(&edge(a, b))
+            };
+            let count = (0..3).filter(|&i| split(i)).count();
+            let start = match count {
+                1 => (0..3).find(|&i| split(i)).unwrap(),
+                2 => (0..3).find(|&i| !split((i + 2) % 3)).unwrap(),
+                _ => 0,
+            };
+            let [a, b, c] = [0, 1, 2].map(|i| face[(start + i) % 3]);
+            let mut middle = |p: Corner, q: Corner| Corner {
+                position: middles[&edge(p.position, q.position)],
+                uv: match (p.uv, q.uv) {
+                    (Some(i), Some(j)) => {
+                        Some(*uv_middles.entry((i.min(j), i.max(j))).or_insert_with(|| {
+                            let (u, v) = (self.uvs[i], self.uvs[j]);
+                            self.uvs.push(((u.0 + v.0) * 0.5, (u.1 + v.1) * 0.5));
+                            self.uvs.len() - 1
+                        }))
+                    }
+                    _ => None,
+                },
+            };
+            match count {
+                0 => faces.push(vec![a, b, c]),
+                1 => {
+                    let ab = middle(a, b);
+                    faces.push(vec![a, ab, c]);
+                    faces.push(vec![ab, b, c]);
+                }
+                2 => {
+                    let (ab, bc) = (middle(a, b), middle(b, c));
+                    faces.push(vec![ab, b, bc]);
+                    // The rest is a quad, cut along its shorter diagonal
+                    let length = |p: Corner, q: Corner| {
+                        (self.positions[q.position] - self.positions[p.position]).length()
+                    };
+                    if length(a, bc) <= length(ab, c) {
+                        faces.push(vec![a, ab, bc]);
+                        faces.push(vec![a, bc, c]);
+                    } else {
+                        faces.push(vec![a, ab, c]);
+                        faces.push(vec![ab, bc, c]);
+                    }
+                }
+                _ => {
+                    let (ab, bc, ca) = (middle(a, b), middle(b, c), middle(c, a));
+                    faces.push(vec![a, ab, ca]);
+                    faces.push(vec![ab, b, bc]);
+                    faces.push(vec![ca, bc, c]);
+                    faces.push(vec![ab, bc, ca]);
+                }
+            }
+        }
+
+        let creases = self
+            .creases
+            .iter()
+            .flat_map(|&(a, b)| match middles.get(&(a, b)) {
+                Some(&m) => vec![edge(a, m), edge(m, b)],
+                None => vec![(a, b)],
+            })
+            .collect();
+        PolyMesh {
+            faces,
+            creases,
+            ..self
+        }
+    }
+
+    // Every position moved along the normal of the surface by the texture times the scale.
+    // Positions have a single normal, averaged over all their faces, even on creases, so the
+    // sides of a crease move together and stay joined. The texture is looked up with the UVs
+    // of the first corner at a position and the position before it moves
+    pub fn displaced(&self, texture: &dyn ScalarTexture, scale: f64) -> PolyMesh {
+        let mut normals = vec![Vec3::zero(); self.positions.len()];
+        let mut uvs = vec![None; self.positions.len()];
+        for face in &self.faces {
+            let p = |i: usize| self.positions[face[i].position];
+            // Newell's method, area weighted and fine for any polygon
+            let normal = (0..face.len()).fold(Vec3::zero(), |sum, i| {
+                sum + p(i).cross(p((i + 1) % face.len()))
+            });
+            for corner in face {
+                normals[corner.position] += normal;
+                if uvs[corner.position].is_none() {
+                    uvs[corner.position] = corner.uv.map(|uv| self.uvs[uv]);
+                }
+            }
+        }
+
+        let positions = self
+            .positions
+            .iter()
+            .zip(normals.iter().zip(&uvs))
+            .map(|(&p, (&normal, uv))| {
+                if normal.length() == 0.0 {
+                    return p;
+                }
+                let (u, v) = uv.unwrap_or((0.0, 0.0));
+                p + normal.unit_vector() * texture.value(u, v, p) * scale
+            })
+            .collect();
+        PolyMesh {
+            positions,
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::subdivision::tests::cube;
+    use crate::subdivision::{PolyMesh, Topology};
+    use crate::texture::{Noise, ScalarTexture};
+    use crate::vector::Point3;
+    use assert_approx_eq::*;
+
+    struct Constant(f64);
+
+    impl ScalarTexture for Constant {
+        fn value(&self, _u: f64, _v: f64, _p: Point3) -> f64 {
+            self.0
+        }
+    }
+
+    fn longest_edge(mesh: &PolyMesh) -> f64 {
+        Topology::new(mesh)
+            .edges
+            .iter()
+            .map(|&(a, b)| (mesh.positions[b] - mesh.positions[a]).length())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn displacement_tessellated() {
+        let mut cage = cube();
+        cage.creases.insert((0, 4));
+        let mesh = cage.tessellated(0.3).unwrap();
+
+        assert!(longest_edge(&mesh) <= 0.3);
+        assert!(mesh.faces.iter().all(|face| face.len() == 3));
+        // Closed, every edge is shared by two faces
+        let topology = Topology::new(&mesh);
+        assert!(topology.edge_faces.iter().all(|faces| faces.len() == 2));
+        // Flat faces stay flat, the cube keeps its shape
+        for p in &mesh.positions {
+            assert_approx_eq!(1.0, p.x.abs().max(p.y.abs()).max(p.z.abs()), 1e-12);
+        }
+        // The crease is split along with its edge, all of it still creased
+        let along = mesh
+            .creases
+            .iter()
+            .all(|&(a, b)| [a, b].iter().all(|&p| mesh.positions[p].x == -1.0));
+        assert!(along);
+        let length: f64 = mesh
+            .creases
+            .iter()
+            .map(|&(a, b)| (mesh.positions[b] - mesh.positions[a]).length())
+            .sum();
+        assert_approx_eq!(2.0, length, 1e-12);
+
+        // Already fine enough, so only split into triangles
+        assert_eq!(12, cube().tessellated(3.0).unwrap().faces.len());
+
+        // Lengths that are not positive, and too many triangles, fail
+        assert!(cube().tessellated(0.0).is_err());
+        assert!(cube().tessellated(f64::NAN).is_err());
+        assert!(cube().tessellated_within(0.3, 10_000).is_ok());
+        assert!(cube().tessellated_within(0.3, 1000).is_err());
+    }
+
+    #[test]
+    fn displacement_tessellated_uvs() {
+        let mut quad = cube();
+        quad.faces.truncate(1);
+        quad.uvs = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        for (i, corner) in quad.faces[0].iter_mut().enumerate() {
+            corner.uv = Some(i);
+        }
+        let mesh = quad.tessellated(0.5).unwrap();
+
+        // Face 0 is at x = -1 with its corners going (y, z) = (-1, -1), (-1, 1), (1, 1),
+        // (1, -1), so u follows z and v follows y
+        for face in &mesh.faces {
+            for corner in face {
+                let p = mesh.positions[corner.position];
+                let (u, v) = mesh.uvs[corner.uv.unwrap()];
+                assert_approx_eq!((p.z + 1.0) * 0.5, u, 1e-12);
+                assert_approx_eq!((p.y + 1.0) * 0.5, v, 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn displacement_displaced() {
+        let mesh = cube().tessellated(0.25).unwrap();
+        let raised = mesh.displaced(&Constant(1.0), 0.5);
+        assert_eq!(mesh.faces, raised.faces);
+        // Moved outwards, the middle of a face by the full amount
+        for (p, q) in mesh.positions.iter().zip(&raised.positions) {
+            assert!(q.length() > p.length());
+            if p.x == 1.0 && p.y == 0.0 && p.z == 0.0 {
+                assert_approx_eq!(1.5, q.x, 1e-12);
+            }
+        }
+        // Still closed, and the same mesh when the texture is zero
+        let flat = mesh.displaced(&Constant(0.0), 0.5);
+        assert_eq!(mesh.positions, flat.positions);
+
+        let rough = mesh.displaced(&Noise::new(2.0, 3), 0.2);
+        let moved = mesh
+            .positions
+            .iter()
+            .zip(&rough.positions)
+            .map(|(p, q)| (*q - *p).length())
+            .collect::<Vec<_>>();
+        assert!(moved.iter().all(|&d| d <= 0.2 + 1e-12));
+        assert!(moved.iter().any(|&d| (d - moved[0]).abs() > 0.01));
+    }
+}
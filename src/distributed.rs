@@ -0,0 +1,519 @@
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::camera::Camera;
+use crate::checkpoint::{
+    invalid_data, read_args, read_pixel, read_splat, read_u32, write_args, write_pixel, write_splat,
+};
+use crate::film::{Film, FilmPixel, Splats};
+use crate::hittable_list::HittableList;
+use crate::progress::Progress;
+use crate::render::{render_passes, render_tile, RenderConfig, RenderReport, TileCallback};
+use crate::tile::{tiles, Tile};
+
+// Protocol, all little endian and in the encoding of checkpoints:
+//   on connect the coordinator sends "RTNW", the version as u32, the heartbeat interval in
+//   milliseconds as u32 and the command line of the render, which the worker builds its scene
+//   and settings from
+//   then the coordinator sends tasks, TASK followed by the tile as four u32, the current
+//   pixels of the tile and the samples to add as u32, the worker answers with RESULT, the
+//   tile and its new pixels, then the region its samples splatted into as four u32 and the
+//   splats over it. Tiles are checked against the image or the task before their pixels are
+//   read
+//   while it builds the scene or renders, the worker sends ALIVE every heartbeat interval
+//   DONE ends the session
+const PROTOCOL_MAGIC: &[u8; 4] = b"RTNW";
+const PROTOCOL_VERSION: u32 = 4;
+const TASK: u8 = 1;
+const DONE: u8 = 0;
+const RESULT: u8 = 2;
+const ALIVE: u8 = 3;
+// Heartbeats in every timeout, so a late one or two don't lose the worker
+const HEARTBEATS_PER_TIMEOUT: u32 = 4;
+
+// Tiles of the current pass, shared by the connections to all workers
+struct Queue {
+    pending: VecDeque<Tile>,
+    samples: u32,
+    // Tiles of the pass that have not come back yet, including those being rendered
+    outstanding: usize,
+    finished: bool,
+}
+
+struct Coordinator<'a> {
+    args: &'a [String],
+    config: &'a RenderConfig,
+    film: &'a Mutex<Film>,
+    queue: Mutex<Queue>,
+    changed: Condvar,
+    on_tile: TileCallback<'a>,
+    timeout: Duration,
+}
+
+// Renders the film on the workers that connect to the listener, tiles go out one at a time
+// to whichever worker is free. A tile whose worker disconnects, or goes without a heartbeat
+// for longer than the timeout, goes back in the queue for another worker. However long a
+// tile takes, a worker that keeps sending heartbeats keeps it. The samples only depend on the
+// seed, so the result is the same as a local render
+pub fn coordinate(
+    listener: TcpListener,
+    args: &[String],
+    config: &RenderConfig,
+    film: Film,
+    on_tile: TileCallback,
+    timeout: Duration,
+) -> io::Result<(Film, RenderReport)> {
+    listener.set_nonblocking(true)?;
+    let film = Mutex::new(film);
+    let coordinator = Coordinator {
+        args,
+        config,
+        film: &film,
+        queue: Mutex::new(Queue {
+            pending: VecDeque::new(),
+            samples: 0,
+            outstanding: 0,
+            finished: false,
+        }),
+        changed: Condvar::new(),
+        on_tile,
+        timeout,
+    };
+
+    let report = thread::scope(|scope| {
+        let coordinator = &coordinator;
+        scope.spawn(move || {
+            while !coordinator.queue.lock().unwrap().finished {
+                match listener.accept() {
+                    Ok((stream, address)) => {
+                        eprintln!("Worker {} connected", address);
+                        scope.spawn(move || {
+                            if let Err(e) = coordinator.serve(stream) {
+                                eprintln!("Lost worker {}: {}", address, e);
+                            }
+                        });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(50))
+                    }
+                    Err(e) => eprintln!("Failed to accept a worker: {}", e),
+                }
+            }
+        });
+
+        let report = render_passes(config, &film, Instant::now, |samples, label| {
+            coordinator.render_pass(samples, label)
+        });
+        coordinator.queue.lock().unwrap().finished = true;
+        coordinator.changed.notify_all();
+        report
+    });
+
+    Ok((film.into_inner().unwrap(), report))
+}
+
+impl<'a> Coordinator<'a> {
+    fn render_pass(&self, samples: u32, label: String) {
+        let tiles = tiles(
+            self.config.image_width,
+            self.config.image_height,
+            self.config.tile_size,
+            self.config.tile_order,
+        );
+        let progress = Progress::new(label, tiles.len());
+
+        let mut queue = self.queue.lock().unwrap();
+        queue.outstanding = tiles.len();
+        queue.pending = tiles.into();
+        queue.samples = samples;
+        self.changed.notify_all();
+
+        let mut reported = queue.outstanding;
+        while queue.outstanding > 0 {
+            queue = self.changed.wait(queue).unwrap();
+            for _ in queue.outstanding..reported {
+                progress.tick();
+            }
+            reported = queue.outstanding;
+        }
+        drop(queue);
+        progress.finish();
+    }
+
+    // Blocks until there is a tile to render, None once the render is done
+    fn next_tile(&self) -> Option<(Tile, u32)> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if queue.finished {
+                return None;
+            }
+            if let Some(tile) = queue.pending.pop_front() {
+                return Some((tile, queue.samples));
+            }
+            queue = self.changed.wait(queue).unwrap();
+        }
+    }
+
+    fn serve(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+
+        let heartbeat = (self.timeout.as_millis() / HEARTBEATS_PER_TIMEOUT as u128)
+            .clamp(1, u32::MAX as u128) as u32;
+        writer.write_all(PROTOCOL_MAGIC)?;
+        writer.write_all(&PROTOCOL_VERSION.to_le_bytes())?;
+        writer.write_all(&heartbeat.to_le_bytes())?;
+        write_args(&mut writer, self.args)?;
+        writer.flush()?;
+
+        while let Some((tile, samples)) = self.next_tile() {
+            let pixels = self.film.lock().unwrap().tile_pixels(&tile);
+            let (width, height) = (self.config.image_width, self.config.image_height);
+            let region = self.config.filter.region(&tile, width, height);
+            let result = write_task(&mut writer, &tile, samples, &pixels)
+                .and_then(|_| read_result(&mut reader))
+                .and_then(|_| read_tile(&mut reader, &tile))
+                .and_then(|pixels| Ok((pixels, read_splats(&mut reader, &region)?)));
+
+            let mut queue = self.queue.lock().unwrap();
+            match result {
+                Ok((pixels, splats)) => {
+                    let mut film = self.film.lock().unwrap();
+                    film.set_tile_pixels(&tile, &pixels);
+                    film.add_splats(&splats);
+                    (self.on_tile)(&region, &film);
+                    queue.outstanding -= 1;
+                }
+                Err(e) => {
+                    queue.pending.push_front(tile);
+                    self.changed.notify_all();
+                    return Err(e);
+                }
+            }
+            self.changed.notify_all();
+        }
+
+        writer.write_all(&[DONE])?;
+        writer.flush()
+    }
+}
+
+// Skips the heartbeats up to the answer to a task
+fn read_result(reader: &mut impl Read) -> io::Result<()> {
+    let mut tag = [0u8];
+    loop {
+        reader.read_exact(&mut tag)?;
+        match tag[0] {
+            ALIVE => continue,
+            RESULT => return Ok(()),
+            _ => return Err(invalid_data("unknown message")),
+        }
+    }
+}
+
+// The scene and settings of a render, built from its command line
+pub type Setup = dyn Fn(&[String]) -> Result<(Camera, HittableList, RenderConfig), String> + Sync;
+
+// Renders tiles for the coordinator at the address until it is done, returns the number of
+// tiles rendered
+pub fn work<A: ToSocketAddrs>(address: A, setup: &Setup) -> io::Result<usize> {
+    let stream = TcpStream::connect(address)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != PROTOCOL_MAGIC || read_u32(&mut reader)? != PROTOCOL_VERSION {
+        return Err(invalid_data("not a render coordinator"));
+    }
+    let heartbeat = Duration::from_millis(read_u32(&mut reader)?.max(1) as u64);
+    let args = read_args(&mut reader)?;
+    let (camera, world, config) = with_heartbeats(&mut writer, heartbeat, || setup(&args))?
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut rendered = 0;
+    let image = (config.image_width, config.image_height);
+    while let Some((tile, samples, mut pixels)) = read_task(&mut reader, image)? {
+        let splats = with_heartbeats(&mut writer, heartbeat, || {
+            render_tile(&camera, &world, &config, &tile, &mut pixels, samples)
+        })?;
+        writer.write_all(&[RESULT])?;
+        write_tile(&mut writer, &tile, &pixels)?;
+        write_splats(&mut writer, &splats)?;
+        writer.flush()?;
+        rendered += 1;
+    }
+    Ok(rendered)
+}
+
+// Runs the job on another thread, sending a heartbeat every interval until it is done
+fn with_heartbeats<T: Send>(
+    writer: &mut impl Write,
+    interval: Duration,
+    job: impl FnOnce() -> T + Send,
+) -> io::Result<T> {
+    thread::scope(|scope| {
+        let (done, finished) = mpsc::channel();
+        scope.spawn(move || done.send(job()));
+        loop {
+            match finished.recv_timeout(interval) {
+                Ok(value) => return Ok(value),
+                Err(RecvTimeoutError::Timeout) => {
+                    writer.write_all(&[ALIVE])?;
+                    writer.flush()?;
+                }
+                Err(RecvTimeoutError::Disconnected) => panic!("the job panicked"),
+            }
+        }
+    })
+}
+
+fn write_task(
+    writer: &mut impl Write,
+    tile: &Tile,
+    samples: u32,
+    pixels: &[FilmPixel],
+) -> io::Result<()> {
+    writer.write_all(&[TASK])?;
+    write_tile(writer, tile, pixels)?;
+    writer.write_all(&samples.to_le_bytes())?;
+    writer.flush()
+}
+
+// The tile must lie within the image of the given width and height
+fn read_task(
+    reader: &mut impl Read,
+    (width, height): (u32, u32),
+) -> io::Result<Option<(Tile, u32, Vec<FilmPixel>)>> {
+    let mut tag = [0u8];
+    reader.read_exact(&mut tag)?;
+    match tag[0] {
+        DONE => Ok(None),
+        TASK => {
+            let tile = read_region(reader)?;
+            let inside = |start: u32, size: u32, end: u32| {
+                start.checked_add(size).is_some_and(|last| last <= end)
+            };
+            if !inside(tile.x, tile.width, width) || !inside(tile.y, tile.height, height) {
+                return Err(invalid_data("tile outside the image"));
+            }
+            let pixels = read_pixels(reader, &tile)?;
+            let samples = read_u32(reader)?;
+            Ok(Some((tile, samples, pixels)))
+        }
+        _ => Err(invalid_data("unknown message")),
+    }
+}
+
+fn write_tile(writer: &mut impl Write, tile: &Tile, pixels: &[FilmPixel]) -> io::Result<()> {
+    for value in [tile.x, tile.y, tile.width, tile.height] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    for pixel in pixels {
+        write_pixel(writer, pixel)?;
+    }
+    Ok(())
+}
+
+// The pixels of the expected tile, checked against the header before reading them
+fn read_tile(reader: &mut impl Read, expected: &Tile) -> io::Result<Vec<FilmPixel>> {
+    if read_region(reader)? != *expected {
+        return Err(invalid_data("worker returned another tile"));
+    }
+    read_pixels(reader, expected)
+}
+
+fn read_region(reader: &mut impl Read) -> io::Result<Tile> {
+    Ok(Tile {
+        x: read_u32(reader)?,
+        y: read_u32(reader)?,
+        width: read_u32(reader)?,
+        height: read_u32(reader)?,
+    })
+}
+
+fn read_pixels(reader: &mut impl Read, tile: &Tile) -> io::Result<Vec<FilmPixel>> {
+    (0..tile.width as u64 * tile.height as u64)
+        .map(|_| read_pixel(reader))
+        .collect()
+}
+
+fn write_splats(writer: &mut impl Write, splats: &Splats) -> io::Result<()> {
+    let region = &splats.region;
+    for value in [region.x, region.y, region.width, region.height] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    for splat in &splats.values {
+        write_splat(writer, splat)?;
+    }
+    Ok(())
+}
+
+// The splats over the expected region, checked against the header before reading them
+fn read_splats(reader: &mut impl Read, expected: &Tile) -> io::Result<Splats> {
+    if read_region(reader)? != *expected {
+        return Err(invalid_data("worker returned another tile"));
+    }
+    let mut splats = Splats::new(*expected);
+    for splat in splats.values.iter_mut() {
+        *splat = read_splat(reader)?;
+    }
+    Ok(splats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aabb::Aabb;
+    use crate::filter::{Filter, FilterKind};
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::ray::Ray;
+    use crate::render::tests::{config, foggy_world, render_new};
+    use crate::sampler::SamplerKind;
+    use rand::RngCore;
+
+    // Takes a task and then misbehaves: drops the connection or never answers
+    fn take_task_and_fail(
+        address: std::net::SocketAddr,
+        disconnect: bool,
+        taken: mpsc::Sender<()>,
+    ) {
+        let stream = TcpStream::connect(address).unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header).unwrap();
+        read_args(&mut reader).unwrap();
+        assert!(read_task(&mut reader, (16, 9)).unwrap().is_some());
+        taken.send(()).unwrap();
+        if !disconnect {
+            // Stays connected until the coordinator gives up on it
+            let _ = reader.read(&mut [0u8]);
+        }
+    }
+
+    #[test]
+    fn distributed_rejects_bad_tiles() {
+        let tile = Tile {
+            x: 4,
+            y: 0,
+            width: 4,
+            height: 4,
+        };
+        let message = |tag: u8, header: [u32; 4]| {
+            let mut bytes = vec![tag];
+            for value in header {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes
+        };
+        // A worker answering with another or a huge tile is refused before its pixels are read
+        for header in [[0, 0, 4, 4], [4, 0, u32::MAX, u32::MAX]] {
+            let bytes = message(TASK, header);
+            let error = read_tile(&mut &bytes[1..], &tile).err().unwrap();
+            assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        }
+        // Or splats over another or a huge region
+        for header in [[2, 0, 8, 4], [0, 0, u32::MAX, u32::MAX]] {
+            let bytes = message(TASK, header);
+            let error = read_splats(&mut &bytes[1..], &tile).err().unwrap();
+            assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        }
+        // And so is a task for pixels outside the image
+        for header in [[14, 0, 4, 4], [u32::MAX, 0, 2, 2], [0, 0, 16, u32::MAX]] {
+            let bytes = message(TASK, header);
+            let error = read_task(&mut &bytes[..], (16, 9)).err().unwrap();
+            assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        }
+    }
+
+    #[test]
+    fn distributed_render_matches_local_render() {
+        let mut config = config(8, SamplerKind::Sobol, 3, 8);
+        config.filter = Filter::new(FilterKind::Gaussian);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let args = vec![String::from("--seed"), String::from("3")];
+        let args = &args;
+        let setup = move |args: &[String]| {
+            assert_eq!(["--seed", "3"], args);
+            Ok((Camera::new(0.0), foggy_world(), config))
+        };
+        let setup = &setup;
+
+        let (film, rendered) = thread::scope(|scope| {
+            let film = Film::new(config.image_width, config.image_height);
+            let timeout = Duration::from_millis(500);
+            let coordinator =
+                scope.spawn(move || coordinate(listener, args, &config, film, &|_, _| {}, timeout));
+
+            // Two tiles get lost before any worker shows up
+            let (taken, lost) = mpsc::channel();
+            for disconnect in [true, false] {
+                let taken = taken.clone();
+                scope.spawn(move || take_task_and_fail(address, disconnect, taken));
+                lost.recv().unwrap();
+            }
+
+            let workers: Vec<_> = (0..2)
+                .map(|_| scope.spawn(move || work(address, setup).unwrap()))
+                .collect();
+            let (film, _) = coordinator.join().unwrap().unwrap();
+            let rendered: usize = workers.into_iter().map(|w| w.join().unwrap()).sum();
+            (film, rendered)
+        });
+
+        assert_eq!(12, rendered);
+        let local = render_new(&Camera::new(0.0), &foggy_world(), &config);
+        assert_eq!(local.pixels, film.pixels);
+        assert_eq!(local.splats, film.splats);
+    }
+
+    // Takes its time over every ray
+    struct Slow;
+
+    impl Hittable for Slow {
+        fn hit(&self, _: &Ray, _: f64, _: f64, _: &mut dyn RngCore) -> Option<HitRecord> {
+            thread::sleep(Duration::from_millis(50));
+            None
+        }
+
+        fn bounding_box(&self) -> Option<Aabb> {
+            None
+        }
+    }
+
+    #[test]
+    fn distributed_keeps_slow_workers() {
+        // A single tile that takes several times the timeout
+        let mut config = config(2, SamplerKind::Independent, 1, 1);
+        (config.image_width, config.image_height, config.tile_size) = (2, 2, 2);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let setup = move |_: &[String]| {
+            let mut world = HittableList::new();
+            world.add(Box::new(Slow));
+            Ok((Camera::new(0.0), world, config))
+        };
+        let setup = &setup;
+
+        let (film, rendered) = thread::scope(|scope| {
+            let film = Film::new(config.image_width, config.image_height);
+            let timeout = Duration::from_millis(40);
+            let coordinator =
+                scope.spawn(move || coordinate(listener, &[], &config, film, &|_, _| {}, timeout));
+            let worker = scope.spawn(move || work(address, setup).unwrap());
+            let (film, _) = coordinator.join().unwrap().unwrap();
+            (film, worker.join().unwrap())
+        });
+
+        assert_eq!(1, rendered);
+        assert!(film.pixels.iter().all(|pixel| pixel.samples == 2));
+    }
+}
@@ -0,0 +1,459 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::aov::Aov;
+use crate::film::Film;
+use crate::vector::{Color, Vec3};
+
+const EXR_MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+// Single part scanline image, attribute names up to 31 bytes
+const EXR_VERSION: u32 = 2;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Precision {
+    Half,
+    Float,
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Precision, String> {
+        match s {
+            "half" => Ok(Precision::Half),
+            "float" => Ok(Precision::Float),
+            _ => Err(format!("unknown precision {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Compression {
+    None,
+    Rle,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Compression, String> {
+        match s {
+            "none" => Ok(Compression::None),
+            "rle" => Ok(Compression::Rle),
+            _ => Err(format!("unknown compression {}", s)),
+        }
+    }
+}
+
+// Header attributes beyond the required ones
+#[derive(Debug, Clone, PartialEq)]
+pub enum Attribute {
+    Int(i32),
+    Float(f32),
+    String(String),
+    V3f(Vec3),
+    // xy of the red, green and blue primaries and the white point
+    Chromaticities([f32; 8]),
+}
+
+pub struct Channel {
+    // Layers are a prefix to the name, "albedo.R" is the red channel of the albedo layer
+    pub name: String,
+    pub precision: Precision,
+    pub values: Vec<f32>,
+}
+
+// The beauty image as R, G and B and, when asked for, every AOV of the film as a layer. Depth
+// and ids are always stored as full floats, halfs only count exactly up to 2048
+pub fn film_channels(
+    film: &Film,
+    beauty: &[Color],
+    precision: Precision,
+    aovs: bool,
+) -> Vec<Channel> {
+    let mut channels = color_channels("", &["R", "G", "B"], beauty, precision);
+    if aovs {
+        for aov in Aov::ALL {
+            let values = film.aov(aov);
+            let layer = match aov {
+                Aov::Normal => color_channels("normal.", &["X", "Y", "Z"], &values, precision),
+                Aov::Depth => color_channels("depth.", &["Z"], &values, Precision::Float),
+                Aov::ObjectId | Aov::MaterialId => {
+                    let prefix = format!("{}.", aov.name());
+                    color_channels(&prefix, &["id"], &values, Precision::Float)
+                }
+                _ => {
+                    let prefix = format!("{}.", aov.name());
+                    color_channels(&prefix, &["R", "G", "B"], &values, precision)
+                }
+            };
+            channels.extend(layer);
+        }
+    }
+    channels
+}
+
+// One channel per name, taken from x, y and z of the colors in turn
+fn color_channels(
+    prefix: &str,
+    names: &[&str],
+    colors: &[Color],
+    precision: Precision,
+) -> Vec<Channel> {
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| Channel {
+            name: format!("{}{}", prefix, name),
+            precision,
+            values: colors.iter().map(|c| [c.x, c.y, c.z][i] as f32).collect(),
+        })
+        .collect()
+}
+
+// Scanline OpenEXR, one line per chunk
+pub fn write_exr<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    mut channels: Vec<Channel>,
+    compression: Compression,
+    attributes: &[(&str, Attribute)],
+) -> io::Result<()> {
+    // Readers expect the channels in alphabetical order, in the header and the pixel data
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&EXR_MAGIC);
+    header.extend_from_slice(&EXR_VERSION.to_le_bytes());
+
+    let mut channel_list = Vec::new();
+    for channel in &channels {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        let pixel_type: i32 = match channel.precision {
+            Precision::Half => 1,
+            Precision::Float => 2,
+        };
+        channel_list.extend_from_slice(&pixel_type.to_le_bytes());
+        // Perceptually linear flag and three reserved bytes, then x and y sampling
+        channel_list.extend_from_slice(&[0, 0, 0, 0]);
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    write_attribute(&mut header, "channels", "chlist", &channel_list);
+
+    let compression_code = match compression {
+        Compression::None => 0,
+        Compression::Rle => 1,
+    };
+    write_attribute(
+        &mut header,
+        "compression",
+        "compression",
+        &[compression_code],
+    );
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+    // Increasing y, the top line first
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    for (name, attribute) in attributes {
+        match attribute {
+            Attribute::Int(value) => {
+                write_attribute(&mut header, name, "int", &value.to_le_bytes())
+            }
+            Attribute::Float(value) => {
+                write_attribute(&mut header, name, "float", &value.to_le_bytes())
+            }
+            Attribute::String(value) => {
+                write_attribute(&mut header, name, "string", value.as_bytes())
+            }
+            Attribute::V3f(value) => {
+                let bytes: Vec<u8> = [value.x, value.y, value.z]
+                    .iter()
+                    .flat_map(|&v| (v as f32).to_le_bytes())
+                    .collect();
+                write_attribute(&mut header, name, "v3f", &bytes)
+            }
+            Attribute::Chromaticities(values) => {
+                let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+                write_attribute(&mut header, name, "chromaticities", &bytes)
+            }
+        }
+    }
+    header.push(0);
+
+    let chunks: Vec<Vec<u8>> = (0..height as usize)
+        .map(|y| {
+            let mut line = Vec::new();
+            for channel in &channels {
+                let row = &channel.values[y * width as usize..(y + 1) * width as usize];
+                for &value in row {
+                    match channel.precision {
+                        Precision::Half => line.extend_from_slice(&f32_to_f16(value).to_le_bytes()),
+                        Precision::Float => line.extend_from_slice(&value.to_le_bytes()),
+                    }
+                }
+            }
+            match compression {
+                Compression::None => line,
+                Compression::Rle => {
+                    // Stored as is when compressing does not make it smaller
+                    let compressed = rle_compress(&line);
+                    if compressed.len() < line.len() {
+                        compressed
+                    } else {
+                        line
+                    }
+                }
+            }
+        })
+        .collect();
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&header)?;
+    // Table of where every chunk starts, each chunk has its line and size in front
+    let mut offset = (header.len() + 8 * chunks.len()) as u64;
+    for chunk in &chunks {
+        writer.write_all(&offset.to_le_bytes())?;
+        offset += 8 + chunk.len() as u64;
+    }
+    for (y, chunk) in chunks.iter().enumerate() {
+        writer.write_all(&(y as i32).to_le_bytes())?;
+        writer.write_all(&(chunk.len() as i32).to_le_bytes())?;
+        writer.write_all(chunk)?;
+    }
+    writer.flush()
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+// Nearest half precision float, ties to even. Too large values become infinity, too small
+// ones zero
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        // Subnormal, the implicit leading one becomes explicit
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = mantissa >> shift;
+        let remainder = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = remainder > halfway || (remainder == halfway && half & 1 == 1);
+        return sign | (half + round as u32) as u16;
+    }
+
+    // Rounding may carry into the exponent, up to infinity, which is what it should do
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    let remainder = mantissa & 0x1fff;
+    let round = remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1);
+    sign | (half + round as u32) as u16
+}
+
+// OpenEXR's RLE: the bytes are split into even and odd halves, turned into differences and
+// then run length encoded
+fn rle_compress(data: &[u8]) -> Vec<u8> {
+    let mut reordered: Vec<u8> = data.iter().step_by(2).copied().collect();
+    reordered.extend(data.iter().skip(1).step_by(2));
+    for i in (1..reordered.len()).rev() {
+        reordered[i] = reordered[i]
+            .wrapping_sub(reordered[i - 1])
+            .wrapping_add(128);
+    }
+
+    const MIN_RUN: usize = 3;
+    const MAX_RUN: usize = 127;
+    let input = &reordered;
+    let mut output = Vec::new();
+    let mut start = 0;
+    while start < input.len() {
+        let mut end = start + 1;
+        while end < input.len() && input[end] == input[start] && end - start <= MAX_RUN {
+            end += 1;
+        }
+        if end - start >= MIN_RUN {
+            // A run, stored as its length minus one and the byte
+            output.push((end - start - 1) as u8);
+            output.push(input[start]);
+        } else {
+            // Literal bytes up to where the next run of three starts, stored as minus their count
+            end = start;
+            while end < input.len()
+                && end - start < MAX_RUN
+                && !(end + 2 < input.len()
+                    && input[end] == input[end + 1]
+                    && input[end] == input[end + 2])
+            {
+                end += 1;
+            }
+            output.push((-((end - start) as i32)) as u8);
+            output.extend_from_slice(&input[start..end]);
+        }
+        start = end;
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rle_decompress(data: &[u8], size: usize) -> Vec<u8> {
+        let mut decoded = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            let count = data[i] as i8;
+            if count < 0 {
+                let n = -(count as i32) as usize;
+                decoded.extend_from_slice(&data[i + 1..i + 1 + n]);
+                i += 1 + n;
+            } else {
+                decoded.extend(std::iter::repeat_n(data[i + 1], count as usize + 1));
+                i += 2;
+            }
+        }
+        for i in 1..decoded.len() {
+            decoded[i] = decoded[i - 1].wrapping_add(decoded[i]).wrapping_sub(128);
+        }
+        let (even, odd) = decoded.split_at(size.div_ceil(2));
+        (0..size)
+            .map(|i| if i % 2 == 0 { even[i / 2] } else { odd[i / 2] })
+            .collect()
+    }
+
+    #[test]
+    fn exr_half_conversion() {
+        assert_eq!(0x3c00, f32_to_f16(1.0));
+        assert_eq!(0xc000, f32_to_f16(-2.0));
+        assert_eq!(0x2e66, f32_to_f16(0.1));
+        assert_eq!(0x7bff, f32_to_f16(65504.0));
+        assert_eq!(0x7c00, f32_to_f16(65520.0));
+        assert_eq!(0x0001, f32_to_f16(5.9604645e-8));
+        assert_eq!(0x0000, f32_to_f16(1e-9));
+        assert_eq!(0x7c00, f32_to_f16(f32::INFINITY));
+        assert!(f32_to_f16(f32::NAN) & 0x3ff != 0);
+    }
+
+    #[test]
+    fn exr_rle_round_trip() {
+        let mut data = vec![0u8; 300];
+        data.extend((0..=255).map(|i| (i * 7 % 251) as u8));
+        data.extend([1, 1, 2, 2, 2, 3, 9, 9]);
+        let compressed = rle_compress(&data);
+
+        assert!(compressed.len() < data.len());
+        assert_eq!(data, rle_decompress(&compressed, data.len()));
+        assert_eq!(vec![7u8], rle_decompress(&rle_compress(&[7]), 1));
+    }
+
+    // Reads back the pixel data of a written file, given the size of a line
+    fn read_lines(bytes: &[u8], height: usize, line_size: usize) -> Vec<u8> {
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap()) as usize;
+        let table = offset_table(bytes);
+        let mut data = Vec::new();
+        for y in 0..height {
+            let offset = u32_at(table + 8 * y) + (u32_at(table + 8 * y + 4) << 32);
+            assert_eq!(y, u32_at(offset));
+            let size = u32_at(offset + 4);
+            let chunk = &bytes[offset + 8..offset + 8 + size];
+            if size < line_size {
+                data.extend(rle_decompress(chunk, line_size));
+            } else {
+                data.extend_from_slice(chunk);
+            }
+        }
+        data
+    }
+
+    // The header ends with an empty attribute name, right after the last attribute value
+    fn offset_table(bytes: &[u8]) -> usize {
+        let string_end = |i: usize| i + bytes[i..].iter().position(|&b| b == 0).unwrap();
+        let mut i = 8;
+        while bytes[i] != 0 {
+            let kind_end = string_end(string_end(i) + 1);
+            let size = u32::from_le_bytes(bytes[kind_end + 1..kind_end + 5].try_into().unwrap());
+            i = kind_end + 5 + size as usize;
+        }
+        i + 1
+    }
+
+    #[test]
+    fn exr_write() {
+        let path = std::env::temp_dir().join("raytracing_weekend_exr_write.exr");
+        let channels = vec![
+            Channel {
+                name: String::from("R"),
+                precision: Precision::Half,
+                values: vec![1.0, 0.5, 0.0, 2.0],
+            },
+            Channel {
+                name: String::from("depth.Z"),
+                precision: Precision::Float,
+                values: vec![0.25; 4],
+            },
+        ];
+        let attributes = [("samplesPerPixel", Attribute::Float(64.0))];
+        write_exr(&path, 2, 2, channels, Compression::Rle, &attributes).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(EXR_MAGIC, bytes[..4]);
+        // Channels are sorted by their bytes, upper case first
+        let header = String::from_utf8_lossy(&bytes);
+        assert!(header.find("R\0").unwrap() < header.find("depth.Z").unwrap());
+        assert!(header.contains("samplesPerPixel\0float"));
+
+        let data = read_lines(&bytes, 2, 2 * 4 + 2 * 2);
+        let first_line = [
+            0x3c00u16.to_le_bytes().as_slice(),
+            &0x3800u16.to_le_bytes(),
+            &0.25f32.to_le_bytes(),
+            &0.25f32.to_le_bytes(),
+        ]
+        .concat();
+        assert_eq!(first_line, data[..12]);
+        assert_eq!([0x00, 0x00, 0x00, 0x40], data[12..16]);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::aov::{Aov, AovPixel};
+use crate::tile::Tile;
+use crate::vector::Color;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FilmPixel {
+    pub sum: Color,
+    pub luminance_sum_squared: f64,
+    pub samples: u32,
+    pub aovs: AovPixel,
+}
+
+impl FilmPixel {
+    pub fn new() -> FilmPixel {
+        FilmPixel {
+            sum: Color::zero(),
+            luminance_sum_squared: 0.0,
+            samples: 0,
+            aovs: AovPixel::new(),
+        }
+    }
+
+    pub fn add_sample(&mut self, color: Color) {
+        self.sum += color;
+        self.luminance_sum_squared += color.luminance() * color.luminance();
+        self.samples += 1;
+    }
+
+    pub fn color(&self) -> Color {
+        if self.samples == 0 {
+            Color::zero()
+        } else {
+            self.sum / self.samples as f64
+        }
+    }
+
+    // Standard error of the mean luminance relative to the mean itself
+    pub fn relative_error(&self) -> f64 {
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.samples as f64;
+        let mean = self.sum.luminance() / n;
+        let variance = ((self.luminance_sum_squared - mean * mean * n) / (n - 1.0)).max(0.0);
+        (variance / n).sqrt() / mean.max(1e-3)
+    }
+}
+
+// Fixed point with 32 fractional bits. Integer sums are the same in any order, so splats from
+// tiles that finish in any order, on any machine, add up to the same bits
+const SPLAT_SCALE: f64 = (1u64 << 32) as f64;
+// Weights below a thousandth of a sample are what is left of negative lobes cancelling out,
+// dividing by them would blow up the color
+const MIN_SPLAT_WEIGHT: i128 = 1 << 22;
+
+// The samples that landed near a pixel, weighted by the reconstruction filter
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Splat {
+    pub sum: [i128; 3],
+    pub weight: i128,
+}
+
+impl Splat {
+    // Samples that are not finite are left out, they would saturate the sums for good
+    pub fn add(&mut self, color: Color, weight: f64) {
+        if ![color.x, color.y, color.z, weight]
+            .iter()
+            .all(|v| v.is_finite())
+        {
+            return;
+        }
+        let fixed = |v: f64| (v * weight * SPLAT_SCALE) as i128;
+        self.merge(&Splat {
+            sum: [fixed(color.x), fixed(color.y), fixed(color.z)],
+            weight: fixed(1.0),
+        });
+    }
+
+    pub fn merge(&mut self, other: &Splat) {
+        for i in 0..3 {
+            self.sum[i] = self.sum[i].saturating_add(other.sum[i]);
+        }
+        self.weight = self.weight.saturating_add(other.weight);
+    }
+
+    // The weighted mean, None while the weights do not add up to enough as can happen with
+    // the negative lobes of some filters
+    pub fn color(&self) -> Option<Color> {
+        if self.weight < MIN_SPLAT_WEIGHT {
+            return None;
+        }
+        let weight = self.weight as f64;
+        let [r, g, b] = self.sum.map(|v| v as f64 / weight);
+        Some(Color::new(r, g, b))
+    }
+}
+
+// Splats of one tile, over the tile grown by the reach of the filter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Splats {
+    pub region: Tile,
+    pub values: Vec<Splat>,
+}
+
+impl Splats {
+    pub fn new(region: Tile) -> Splats {
+        Splats {
+            region,
+            values: vec![Splat::default(); region.width as usize * region.height as usize],
+        }
+    }
+
+    // Ignores pixels outside the region
+    pub fn add(&mut self, x: i64, y: i64, color: Color, weight: f64) {
+        let region = self.region;
+        let (x, y) = (x - region.x as i64, y - region.y as i64);
+        if x >= 0 && y >= 0 && x < region.width as i64 && y < region.height as i64 {
+            self.values[(y * region.width as i64 + x) as usize].add(color, weight);
+        }
+    }
+
+    pub fn merge(mut self, other: Splats) -> Splats {
+        for (value, other) in self.values.iter_mut().zip(&other.values) {
+            value.merge(other);
+        }
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<FilmPixel>,
+    // Apart from the pixels, as tiles add to the splats of their neighbours
+    pub splats: Vec<Splat>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        Film {
+            width,
+            height,
+            pixels: vec![FilmPixel::new(); (width * height) as usize],
+            splats: vec![Splat::default(); (width * height) as usize],
+        }
+    }
+
+    pub fn tile_pixels(&self, tile: &Tile) -> Vec<FilmPixel> {
+        tile.pixels()
+            .map(|(x, y)| self.pixels[(y * self.width + x) as usize])
+            .collect()
+    }
+
+    pub fn set_tile_pixels(&mut self, tile: &Tile, pixels: &[FilmPixel]) {
+        for ((x, y), pixel) in tile.pixels().zip(pixels) {
+            self.pixels[(y * self.width + x) as usize] = *pixel;
+        }
+    }
+
+    pub fn add_splats(&mut self, splats: &Splats) {
+        for ((x, y), splat) in splats.region.pixels().zip(&splats.values) {
+            self.splats[(y * self.width + x) as usize].merge(splat);
+        }
+    }
+
+    // The filtered color of every pixel. Pixels whose splats have no weight fall back to the
+    // mean of their own samples
+    pub fn colors(&self) -> Vec<Color> {
+        (0..self.pixels.len()).map(|i| self.color(i)).collect()
+    }
+
+    pub fn tile_colors(&self, tile: &Tile) -> Vec<Color> {
+        tile.pixels()
+            .map(|(x, y)| self.color((y * self.width + x) as usize))
+            .collect()
+    }
+
+    fn color(&self, index: usize) -> Color {
+        self.splats[index]
+            .color()
+            .unwrap_or_else(|| self.pixels[index].color())
+    }
+
+    pub fn aov(&self, aov: Aov) -> Vec<Color> {
+        self.pixels
+            .iter()
+            .map(|p| p.aovs.value(aov, p.samples))
+            .collect()
+    }
+
+    pub fn total_samples(&self) -> u64 {
+        self.pixels.iter().map(|p| p.samples as u64).sum()
+    }
+
+    // Heat map of the sample count of every pixel, black for none up to white for the most
+    pub fn sample_map(&self) -> Vec<Color> {
+        let max = self
+            .pixels
+            .iter()
+            .map(|p| p.samples)
+            .max()
+            .unwrap_or(0)
+            .max(1) as f64;
+        self.pixels
+            .iter()
+            .map(|p| {
+                let t = p.samples as f64 / max;
+                Color::new(
+                    (3.0 * t).min(1.0),
+                    (3.0 * t - 1.0).clamp(0.0, 1.0),
+                    (3.0 * t - 2.0).clamp(0.0, 1.0),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+
+    #[test]
+    fn film_pixel_mean() {
+        let mut pixel = FilmPixel::new();
+        pixel.add_sample(Color::new(1.0, 0.0, 0.0));
+        pixel.add_sample(Color::new(0.0, 1.0, 0.0));
+
+        assert_eq!(2, pixel.samples);
+        assert_eq!(Color::new(0.5, 0.5, 0.0), pixel.color());
+    }
+
+    #[test]
+    fn film_pixel_relative_error() {
+        let mut constant = FilmPixel::new();
+        let mut noisy = FilmPixel::new();
+        for i in 0..16 {
+            constant.add_sample(Color::one());
+            noisy.add_sample(Color::one() * (i % 2) as f64 * 2.0);
+        }
+
+        assert_approx_eq!(0.0, constant.relative_error(), 1e-6);
+        // Luminance alternates between 0 and 2: standard deviation ~1.03, mean 1
+        assert_approx_eq!(1.0328 / 4.0, noisy.relative_error(), 1e-3);
+        assert!(FilmPixel::new().relative_error().is_infinite());
+    }
+
+    #[test]
+    fn film_sample_map() {
+        let mut film = Film::new(2, 1);
+        film.pixels[0].add_sample(Color::one());
+        film.pixels[0].add_sample(Color::one());
+        film.pixels[1].add_sample(Color::one());
+
+        let map = film.sample_map();
+        assert_eq!(Color::one(), map[0]);
+        assert_eq!(3, film.total_samples());
+    }
+
+    #[test]
+    fn film_splat_bad_samples() {
+        let mut splat = Splat::default();
+        splat.add(Color::new(0.5, 1.0, 2.0), 0.5);
+        // Left out, the mean is unchanged
+        splat.add(Color::new(f64::INFINITY, 0.0, 0.0), 1.0);
+        splat.add(Color::new(f64::NAN, 0.0, 0.0), 1.0);
+        splat.add(Color::one(), f64::INFINITY);
+        assert_eq!(Some(Color::new(0.5, 1.0, 2.0)), splat.color());
+
+        // Huge but finite samples saturate instead of wrapping around
+        splat.add(Color::one() * 1e300, 1.0);
+        splat.add(Color::one() * 1e300, 1.0);
+        assert!(splat.color().unwrap().x > 0.0);
+
+        // Negative lobes that nearly cancel out leave no color
+        let mut cancelled = Splat::default();
+        cancelled.add(Color::one(), 0.5);
+        cancelled.add(Color::one() * 4.0, -0.4999999);
+        assert_eq!(None, cancelled.color());
+    }
+}
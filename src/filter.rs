@@ -0,0 +1,217 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use crate::tile::Tile;
+
+// Wide enough to blur the image, any wider and every sample splats over thousands of pixels
+pub const MAX_RADIUS: f64 = 16.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FilterKind {
+    // Every sample counts the same, one pixel wide this is the plain average
+    Box,
+    Tent,
+    // Truncated at the radius and shifted down to reach zero there
+    Gaussian,
+    // Mitchell-Netravali with B = C = 1/3, sharp with little ringing
+    Mitchell,
+    // Windowed sinc, the sharpest but rings around edges
+    Lanczos,
+}
+
+impl FilterKind {
+    pub fn default_radius(self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell | FilterKind::Lanczos => 2.0,
+        }
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FilterKind, String> {
+        match s {
+            "box" => Ok(FilterKind::Box),
+            "tent" => Ok(FilterKind::Tent),
+            "gaussian" => Ok(FilterKind::Gaussian),
+            "mitchell" => Ok(FilterKind::Mitchell),
+            "lanczos" => Ok(FilterKind::Lanczos),
+            _ => Err(format!("unknown filter {}", s)),
+        }
+    }
+}
+
+// Separable reconstruction filter. A sample adds to every pixel whose center is within the
+// radius in x and y, weighted by the filter at its offset from that center
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    // In pixels
+    pub radius: f64,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind) -> Filter {
+        Filter {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    // The support is half open, so a sample on the border between two pixels only counts
+    // for one of them with a box one pixel wide
+    fn weight_1d(&self, d: f64) -> f64 {
+        let radius = self.radius;
+        if d < -radius || d >= radius {
+            return 0.0;
+        }
+        let d = d.abs();
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - d / radius,
+            FilterKind::Gaussian => {
+                let sigma = radius / 3.0;
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                gaussian(d) - gaussian(radius)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * d / radius),
+            FilterKind::Lanczos => sinc(d) * sinc(d / radius),
+        }
+    }
+
+    // How many pixels past its own a sample can reach
+    pub fn reach(&self) -> u32 {
+        (self.radius - 0.5).max(0.0).ceil() as u32
+    }
+
+    // The pixels the samples of a tile can reach, within the image
+    pub fn region(&self, tile: &Tile, image_width: u32, image_height: u32) -> Tile {
+        let reach = self.reach();
+        let (x, y) = (tile.x.saturating_sub(reach), tile.y.saturating_sub(reach));
+        let end = |start: u32, size: u32| start.saturating_add(size).saturating_add(reach);
+        Tile {
+            x,
+            y,
+            width: end(tile.x, tile.width).min(image_width) - x,
+            height: end(tile.y, tile.height).min(image_height) - y,
+        }
+    }
+}
+
+fn mitchell(x: f64) -> f64 {
+    const B: f64 = 1.0 / 3.0;
+    const C: f64 = 1.0 / 3.0;
+    let x = x.abs();
+    let value = if x < 1.0 {
+        (12.0 - 9.0 * B - 6.0 * C) * x * x * x
+            + (-18.0 + 12.0 * B + 6.0 * C) * x * x
+            + (6.0 - 2.0 * B)
+    } else if x < 2.0 {
+        (-B - 6.0 * C) * x * x * x
+            + (6.0 * B + 30.0 * C) * x * x
+            + (-12.0 * B - 48.0 * C) * x
+            + (8.0 * B + 24.0 * C)
+    } else {
+        0.0
+    };
+    value / 6.0
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+
+    const KINDS: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+        FilterKind::Lanczos,
+    ];
+
+    #[test]
+    fn filter_shapes() {
+        for kind in KINDS {
+            let filter = Filter::new(kind);
+            assert!(filter.weight(0.0, 0.0) > 0.0, "{:?}", kind);
+            assert_eq!(0.0, filter.weight(filter.radius, 0.0), "{:?}", kind);
+            assert_approx_eq!(filter.weight(0.3, -0.7), filter.weight(-0.3, 0.7), 1e-12);
+            assert!(
+                filter.weight(0.2, 0.0) >= filter.weight(0.4, 0.0),
+                "{:?}",
+                kind
+            );
+        }
+        // Mitchell and Lanczos dip below zero towards their edges
+        assert!(Filter::new(FilterKind::Mitchell).weight(1.5, 0.0) < 0.0);
+        assert!(Filter::new(FilterKind::Lanczos).weight(1.5, 0.0) < 0.0);
+        assert_approx_eq!(0.5, Filter::new(FilterKind::Tent).weight(0.5, 0.0), 1e-12);
+    }
+
+    #[test]
+    fn filter_box_keeps_samples_in_their_pixel() {
+        let filter = Filter::new(FilterKind::Box);
+        assert_eq!(0, filter.reach());
+        // A sample on the left edge of its pixel, the pixel to the left gets nothing
+        assert_eq!(1.0, filter.weight(-0.5, 0.0));
+        assert_eq!(0.0, filter.weight(0.5, 0.0));
+    }
+
+    #[test]
+    fn filter_region() {
+        let filter = Filter::new(FilterKind::Mitchell);
+        let tile = Tile {
+            x: 0,
+            y: 4,
+            width: 4,
+            height: 4,
+        };
+
+        assert_eq!(2, filter.reach());
+        assert_eq!(
+            Tile {
+                x: 0,
+                y: 2,
+                width: 6,
+                height: 7
+            },
+            filter.region(&tile, 16, 9)
+        );
+
+        // However far a filter reaches, the region stays within the image
+        let mut wide = Filter::new(FilterKind::Box);
+        wide.radius = f64::INFINITY;
+        assert_eq!(
+            Tile {
+                x: 0,
+                y: 0,
+                width: 16,
+                height: 9
+            },
+            wide.region(&tile, 16, 9)
+        );
+    }
+
+    #[test]
+    fn filter_from_str() {
+        assert_eq!(Ok(FilterKind::Lanczos), "lanczos".parse());
+        assert!("catmull-rom".parse::<FilterKind>().is_err());
+    }
+}
@@ -0,0 +1,401 @@
+use std::f64::consts::{PI, TAU};
+
+use rand::RngCore;
+
+use crate::hittable::HitRecord;
+use crate::material::{Material, Scatter};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vector::{Color, Vec3};
+
+// Index of refraction of keratin
+const ETA: f64 = 1.55;
+// The scales on the surface of a fibre tilt its reflections, by two degrees
+const SCALE_ANGLE: f64 = 2.0 * PI / 180.0;
+// Paths through the fibre that are followed separately: reflection, transmission and one
+// internal reflection. The rest are lumped together
+const P_MAX: usize = 3;
+
+// The hair scattering model of d'Eon et al., as extended by Chiang et al. Light reflects off
+// the fibre (R), passes through it (TT) or reflects once inside (TRT), each with its own
+// spread along the fibre and around it and absorbed inside according to the color. Directions
+// are measured in a frame with x along the fibre. The hit record needs the tangent, and the
+// normal of a round fibre: where across it the light enters comes from the angle between the
+// normal and the ray
+pub struct Hair {
+    color: Color,
+    sigma_a: Color,
+    // Variances of the longitudinal spread of each path
+    v: [f64; P_MAX + 1],
+    // Scale of the logistic azimuthal spread
+    s: f64,
+    sin_2k_alpha: [f64; 3],
+    cos_2k_alpha: [f64; 3],
+}
+
+impl Hair {
+    // Roughnesses from 0 to 1, along the fibre and around it. The absorption inside is chosen
+    // so the fibre reflects the color overall
+    pub fn new(color: Color, longitudinal: f64, azimuthal: f64) -> Hair {
+        let beta_m = longitudinal.clamp(0.01, 1.0);
+        let beta_n = azimuthal.clamp(0.01, 1.0);
+        let v0 = (0.726 * beta_m + 0.812 * beta_m.powi(2) + 3.7 * beta_m.powi(20)).powi(2);
+        let s =
+            (PI / 8.0).sqrt() * (0.265 * beta_n + 1.194 * beta_n.powi(2) + 5.372 * beta_n.powi(22));
+
+        let mut sin_2k_alpha = [SCALE_ANGLE.sin(), 0.0, 0.0];
+        let mut cos_2k_alpha = [SCALE_ANGLE.cos(), 0.0, 0.0];
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1].powi(2) - sin_2k_alpha[i - 1].powi(2);
+        }
+
+        // Fitted by Chiang et al. from the color of many scattering fibres
+        let denominator = 5.969 - 0.215 * beta_n + 2.532 * beta_n.powi(2) - 10.73 * beta_n.powi(3)
+            + 5.574 * beta_n.powi(4)
+            + 0.245 * beta_n.powi(5);
+        let absorption = |c: f64| (c.clamp(1e-4, 1.0).ln() / denominator).powi(2);
+        Hair {
+            color,
+            sigma_a: Color::new(
+                absorption(color.x),
+                absorption(color.y),
+                absorption(color.z),
+            ),
+            v: [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0],
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    // The longitudinal angle of the outgoing direction, tilted by the scales for each path
+    fn tilted(&self, p: usize, sin_theta: f64, cos_theta: f64) -> (f64, f64) {
+        let (sin, cos) = match p {
+            0 => (
+                sin_theta * self.cos_2k_alpha[1] - cos_theta * self.sin_2k_alpha[1],
+                cos_theta * self.cos_2k_alpha[1] + sin_theta * self.sin_2k_alpha[1],
+            ),
+            1 => (
+                sin_theta * self.cos_2k_alpha[0] + cos_theta * self.sin_2k_alpha[0],
+                cos_theta * self.cos_2k_alpha[0] - sin_theta * self.sin_2k_alpha[0],
+            ),
+            2 => (
+                sin_theta * self.cos_2k_alpha[2] + cos_theta * self.sin_2k_alpha[2],
+                cos_theta * self.cos_2k_alpha[2] - sin_theta * self.sin_2k_alpha[2],
+            ),
+            _ => (sin_theta, cos_theta),
+        };
+        (sin, cos.abs())
+    }
+
+    // The attenuation of each path and the angles inside the fibre, for light leaving along
+    // wo from offset h across it
+    fn paths(&self, wo: Vec3, h: f64) -> ([Color; P_MAX + 1], f64, f64) {
+        let sin_theta_o = wo.x;
+        let cos_theta_o = safe_sqrt(1.0 - sin_theta_o * sin_theta_o);
+        let sin_theta_t = sin_theta_o / ETA;
+        let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+        let eta_p = safe_sqrt(ETA * ETA - sin_theta_o * sin_theta_o) / cos_theta_o;
+        let sin_gamma_t = (h / eta_p).clamp(-1.0, 1.0);
+        let cos_gamma_t = safe_sqrt(1.0 - sin_gamma_t * sin_gamma_t);
+        let gamma_t = sin_gamma_t.asin();
+
+        // Through the fibre once
+        let distance = 2.0 * cos_gamma_t / cos_theta_t;
+        let transmittance = Color::new(
+            (-self.sigma_a.x * distance).exp(),
+            (-self.sigma_a.y * distance).exp(),
+            (-self.sigma_a.z * distance).exp(),
+        );
+
+        let cos_gamma_o = safe_sqrt(1.0 - h * h);
+        let f = fresnel(cos_theta_o * cos_gamma_o);
+        let mut a = [Color::zero(); P_MAX + 1];
+        a[0] = Color::one() * f;
+        a[1] = transmittance * (1.0 - f).powi(2);
+        a[2] = a[1] * transmittance * f;
+        let rest = transmittance * f;
+        a[3] = a[2] * rest;
+        a[3] = Color::new(
+            a[3].x / (1.0 - rest.x),
+            a[3].y / (1.0 - rest.y),
+            a[3].z / (1.0 - rest.z),
+        );
+        (a, h.clamp(-1.0, 1.0).asin(), gamma_t)
+    }
+
+    // The scattered light for light arriving from wi and leaving along wo, times the cosine,
+    // and the probability density of sampling wi
+    fn evaluate(&self, wo: Vec3, wi: Vec3, h: f64) -> (Color, f64) {
+        let (sin_theta_o, sin_theta_i) = (wo.x, wi.x);
+        let cos_theta_o = safe_sqrt(1.0 - sin_theta_o * sin_theta_o);
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+        let phi = wi.z.atan2(wi.y) - wo.z.atan2(wo.y);
+
+        let (a, gamma_o, gamma_t) = self.paths(wo, h);
+        let weights = path_weights(&a);
+        let mut value = Color::zero();
+        let mut pdf = 0.0;
+        for p in 0..=P_MAX {
+            let (sin_theta_op, cos_theta_op) = self.tilted(p, sin_theta_o, cos_theta_o);
+            let m = longitudinal(
+                cos_theta_i,
+                cos_theta_op,
+                sin_theta_i,
+                sin_theta_op,
+                self.v[p],
+            );
+            let n = if p < P_MAX {
+                azimuthal(phi, p, self.s, gamma_o, gamma_t)
+            } else {
+                1.0 / TAU
+            };
+            value += a[p] * (m * n);
+            pdf += weights[p] * m * n;
+        }
+        (value, pdf)
+    }
+
+    // A direction for the light to arrive from, picking a path by how much light it carries
+    // and then its spread along and around the fibre
+    fn sample(&self, wo: Vec3, h: f64, u: (f64, f64), v: (f64, f64)) -> Vec3 {
+        let sin_theta_o = wo.x;
+        let cos_theta_o = safe_sqrt(1.0 - sin_theta_o * sin_theta_o);
+        let (a, gamma_o, gamma_t) = self.paths(wo, h);
+        let weights = path_weights(&a);
+        let mut p = 0;
+        let mut remaining = u.0;
+        while p < P_MAX && remaining >= weights[p] {
+            remaining -= weights[p];
+            p += 1;
+        }
+
+        let (sin_theta_op, cos_theta_op) = self.tilted(p, sin_theta_o, cos_theta_o);
+        let u0 = v.0.max(1e-5);
+        let cos_theta = 1.0 + self.v[p] * (u0 + (1.0 - u0) * (-2.0 / self.v[p]).exp()).ln();
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let cos_phi = (TAU * v.1).cos();
+        let sin_theta_i = -cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op;
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+
+        let dphi = if p < P_MAX {
+            phi(p, gamma_o, gamma_t) + sample_trimmed_logistic(u.1, self.s, -PI, PI)
+        } else {
+            TAU * u.1
+        };
+        let phi_i = wo.z.atan2(wo.y) + dphi;
+        Vec3::new(
+            sin_theta_i,
+            cos_theta_i * phi_i.cos(),
+            cos_theta_i * phi_i.sin(),
+        )
+    }
+}
+
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.0).sqrt()
+}
+
+// Fresnel reflectance of light arriving from outside at the given cosine
+fn fresnel(cos_theta_i: f64) -> f64 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin_theta_t = safe_sqrt(1.0 - cos_theta_i * cos_theta_i) / ETA;
+    if sin_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+    let parallel = (ETA * cos_theta_i - cos_theta_t) / (ETA * cos_theta_i + cos_theta_t);
+    let perpendicular = (cos_theta_i - ETA * cos_theta_t) / (cos_theta_i + ETA * cos_theta_t);
+    (parallel * parallel + perpendicular * perpendicular) * 0.5
+}
+
+fn path_weights(a: &[Color; P_MAX + 1]) -> [f64; P_MAX + 1] {
+    let luminance = a.map(|a| a.luminance().max(0.0));
+    let total: f64 = luminance.iter().sum();
+    if total > 0.0 {
+        luminance.map(|l| l / total)
+    } else {
+        [1.0 / (P_MAX + 1) as f64; P_MAX + 1]
+    }
+}
+
+// Modified Bessel function of the first kind, order zero
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 0.0;
+    let mut term = 1.0;
+    let x2 = x * x / 4.0;
+    for i in 1..12 {
+        sum += term;
+        term *= x2 / (i * i) as f64;
+    }
+    sum
+}
+
+fn log_bessel_i0(x: f64) -> f64 {
+    if x > 12.0 {
+        x + 0.5 * (-(TAU).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        bessel_i0(x).ln()
+    }
+}
+
+// The spread along the fibre, normalized over the sphere
+fn longitudinal(
+    cos_theta_i: f64,
+    cos_theta_o: f64,
+    sin_theta_i: f64,
+    sin_theta_o: f64,
+    v: f64,
+) -> f64 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        // In logarithms, the terms overflow on their own for narrow spreads
+        (log_bessel_i0(a) - b - 1.0 / v + 2.0f64.ln() + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        (-b).exp() * bessel_i0(a) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+// How far around the fibre each path turns the light
+fn phi(p: usize, gamma_o: f64, gamma_t: f64) -> f64 {
+    2.0 * p as f64 * gamma_t - 2.0 * gamma_o + p as f64 * PI
+}
+
+fn logistic(x: f64, s: f64) -> f64 {
+    let x = x.abs();
+    (-x / s).exp() / (s * (1.0 + (-x / s).exp()).powi(2))
+}
+
+fn logistic_cdf(x: f64, s: f64) -> f64 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+fn trimmed_logistic(x: f64, s: f64, a: f64, b: f64) -> f64 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: f64, s: f64, a: f64, b: f64) -> f64 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(a, s)) - 1.0).ln();
+    x.clamp(a, b)
+}
+
+// The spread around the fibre of path p, centred on where the path turns the light
+fn azimuthal(phi_difference: f64, p: usize, s: f64, gamma_o: f64, gamma_t: f64) -> f64 {
+    let dphi = (phi_difference - phi(p, gamma_o, gamma_t) + PI).rem_euclid(TAU) - PI;
+    trimmed_logistic(dphi, s, -PI, PI)
+}
+
+impl Material for Hair {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+        _rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        // The frame of the fibre, x along it and z along the normal
+        let normal = record.normal;
+        let x = if record.tangent.length_squared() > 0.0 {
+            record.tangent.unit_vector()
+        } else {
+            normal.orthonormal_basis().0
+        };
+        let z = normal - x * normal.dot(x);
+        let z = if z.length_squared() > 0.0 {
+            z.unit_vector()
+        } else {
+            x.orthonormal_basis().0
+        };
+        let y = z.cross(x);
+        let local = |w: Vec3| Vec3::new(w.dot(x), w.dot(y), w.dot(z));
+
+        let wo = local(-ray.direction.unit_vector());
+        // Where across the fibre, from -1 to 1: the sine of the angle between the normal
+        // and the outgoing direction around the fibre
+        let around = (wo.y * wo.y + wo.z * wo.z).sqrt();
+        let h = if around > 0.0 { -wo.y / around } else { 0.0 };
+
+        let (u, v) = (sampler.get_2d(), sampler.get_2d());
+        let wi = self.sample(wo, h, u, v);
+        let (value, pdf) = self.evaluate(wo, wi, h);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(Scatter {
+            attenuation: value / pdf,
+            ray: Ray::new(record.point, x * wi.x + y * wi.y + z * wi.z),
+        })
+    }
+
+    fn albedo(&self) -> Color {
+        self.color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::SamplerKind;
+    use crate::vector::Point3;
+    use assert_approx_eq::*;
+    use rand::prelude::*;
+
+    // The average light scattered off a fibre seen from random directions at random offsets
+    fn average(hair: &Hair) -> Color {
+        let mut sampler = SamplerKind::Independent.create(1, 1, 0);
+        let mut rng = StdRng::seed_from_u64(3);
+        let count = 20000;
+        let mut sum = Color::zero();
+        for sample in 0..count {
+            sampler.start_pixel_sample(0, 0, sample);
+            let h: f64 = rng.gen_range(-1.0..1.0);
+            let (along, _) = sampler.get_2d();
+            let direction = Vec3::new(along * 2.0 - 1.0, 0.0, -1.0);
+            let normal = Vec3::new(0.0, h, (1.0 - h * h).sqrt());
+            let record = HitRecord::new(Point3::zero(), normal, 1.0, true, 0)
+                .with_tangent(Vec3::new(1.0, 0.0, 0.0));
+            let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), direction);
+            let scatter = hair
+                .scatter(&ray, &record, sampler.as_mut(), &mut rng)
+                .unwrap();
+            sum += scatter.attenuation;
+        }
+        sum / count as f64
+    }
+
+    #[test]
+    fn hair_white_furnace() {
+        // Nothing absorbed, so all the light comes out again whatever the roughness
+        for roughness in [0.2, 0.5, 0.9] {
+            let white = average(&Hair::new(Color::one(), roughness, roughness));
+            assert!((0.95..1.05).contains(&white.luminance()), "{}", white);
+        }
+    }
+
+    #[test]
+    fn hair_pdf_integrates_to_one() {
+        let hair = Hair::new(Color::new(0.6, 0.3, 0.1), 0.3, 0.4);
+        let wo = Vec3::new(0.3, 0.2, 0.93).unit_vector();
+        let mut rng = StdRng::seed_from_u64(5);
+        let count = 200000;
+        let mut sum = 0.0;
+        for _ in 0..count {
+            let wi = Vec3::random_unit_vector(&mut rng);
+            sum += hair.evaluate(wo, wi, 0.3).1;
+        }
+        assert_approx_eq!(1.0, sum * 4.0 * PI / count as f64, 0.05);
+    }
+
+    #[test]
+    fn hair_colored() {
+        let red = average(&Hair::new(Color::new(0.8, 0.3, 0.1), 0.3, 0.3));
+        assert!(red.x > red.y && red.y > red.z, "{}", red);
+        let dark = average(&Hair::new(Color::one() * 0.05, 0.3, 0.3));
+        assert!(dark.luminance() < red.luminance());
+        // Dark hair still reflects off its surface
+        assert!(dark.luminance() > 0.03);
+    }
+}
@@ -1,24 +1,133 @@
+use rand::RngCore;
+
+use crate::aabb::Aabb;
 use crate::ray::Ray;
-use crate::vector::{Point3, Vec3};
+use crate::vector::{Color, Point3, Vec3};
 
 pub struct HitRecord {
     pub point: Point3,
     pub normal: Vec3,
     pub t: f64,
     pub front_face: bool,
+    // Index of the material in the world
+    pub material: usize,
+    // Index of the object in the world, filled in by the world
+    pub object: usize,
+    // Surface coordinates, for textures
+    pub u: f64,
+    pub v: f64,
+    // Direction along fibres, zero on surfaces without one
+    pub tangent: Vec3,
+    // Color that varies within an object, like the colors of particles, multiplied into
+    // diffuse materials
+    pub tint: Color,
 }
 
 impl HitRecord {
-    pub fn new(p: Point3, normal: Vec3, t: f64, front_face: bool) -> HitRecord {
+    pub fn new(p: Point3, normal: Vec3, t: f64, front_face: bool, material: usize) -> HitRecord {
         HitRecord {
             point: p,
             normal: if front_face { normal } else { -normal },
             t,
             front_face,
+            material,
+            object: 0,
+            u: 0.0,
+            v: 0.0,
+            tangent: Vec3::zero(),
+            tint: Color::one(),
         }
     }
+
+    pub fn with_uv(self, u: f64, v: f64) -> HitRecord {
+        HitRecord { u, v, ..self }
+    }
+
+    pub fn with_tangent(self, tangent: Vec3) -> HitRecord {
+        HitRecord { tangent, ..self }
+    }
+
+    pub fn with_tint(self, tint: Color) -> HitRecord {
+        HitRecord { tint, ..self }
+    }
+}
+
+pub trait Hittable: Send + Sync {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord>;
+
+    // None for objects without finite bounds, which stay outside the BVH
+    fn bounding_box(&self) -> Option<Aabb>;
+}
+
+// Where a ray crosses the surface of a solid, with the normal pointing out of it
+#[derive(Debug, Copy, Clone)]
+pub struct Crossing {
+    pub t: f64,
+    pub normal: Vec3,
+    pub material: usize,
+    pub u: f64,
+    pub v: f64,
 }
 
-pub trait Hittable {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+impl Crossing {
+    pub fn record(&self, ray: &Ray) -> HitRecord {
+        let front_face = ray.direction.dot(self.normal) < 0.0;
+        HitRecord::new(
+            ray.at(self.t),
+            self.normal,
+            self.t,
+            front_face,
+            self.material,
+        )
+        .with_uv(self.u, self.v)
+    }
+}
+
+// A stretch of a ray inside a solid
+#[derive(Debug, Copy, Clone)]
+pub struct Span {
+    pub enter: Crossing,
+    pub exit: Crossing,
+}
+
+// Closed objects with an inside, which constructive solid geometry can combine
+pub trait Solid: Hittable {
+    // Every stretch of the whole line of the ray inside the object, including before its
+    // origin, in order
+    fn spans(&self, r: &Ray, rng: &mut dyn RngCore) -> Vec<Span>;
+}
+
+// The first crossing of the spans within the range
+pub fn hit_spans(spans: &[Span], ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    spans
+        .iter()
+        .flat_map(|span| [span.enter, span.exit])
+        .find(|crossing| crossing.t > t_min && crossing.t < t_max)
+        .map(|crossing| crossing.record(ray))
+}
+
+// Spans from the crossings of a surface in order, each from where the ray goes in to where it
+// next comes out. Crossings that only graze the surface, as at a double root, go neither way
+// and are left out
+pub fn pair_crossings(ray: &Ray, crossings: impl IntoIterator<Item = Crossing>) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut enter = None;
+    for crossing in crossings {
+        let facing = ray.direction.unit_vector().dot(crossing.normal);
+        if facing.abs() < 1e-9 {
+            continue;
+        }
+        match enter {
+            None if facing < 0.0 => enter = Some(crossing),
+            Some(start) if facing > 0.0 => {
+                spans.push(Span {
+                    enter: start,
+                    exit: crossing,
+                });
+                enter = None;
+            }
+            _ => {}
+        }
+    }
+    spans
 }
@@ -1,38 +1,140 @@
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::color::{ColorSpace, Matrix3};
 use crate::hittable::{HitRecord, Hittable};
+use crate::material::{Diffuse, Material};
+use crate::plane::Plane;
 use crate::ray::Ray;
+use crate::spectrum::{xyz_to_rgb, SampledSpectrum, SampledWavelengths};
 use crate::sphere::Sphere;
 use crate::vector::*;
 
 pub struct HittableList {
-    pub objects: Vec<Sphere>,
+    pub objects: Vec<Box<dyn Hittable>>,
+    // Objects refer to their material by index
+    pub materials: Vec<Box<dyn Material>>,
+    // Built once the scene is complete, rays test every object until then
+    bvh: Option<Bvh>,
+    // Converts linear sRGB colors, the space scenes are described in, to the working space
+    from_srgb: Matrix3,
+    to_srgb: Matrix3,
+    // Converts the XYZ colors of spectral rendering to the working space
+    from_xyz: Matrix3,
 }
 
 impl HittableList {
     pub fn new() -> HittableList {
-        let spheres = vec![
-            Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5),
-            Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0),
+        let materials: Vec<Box<dyn Material>> =
+            vec![Box::new(Diffuse::new(Color::new(0.5, 0.5, 0.5)))];
+        let objects: Vec<Box<dyn Hittable>> = vec![
+            Box::new(Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, 0)),
+            Box::new(Plane::new(
+                Point3::new(0.0, -0.5, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                0,
+            )),
         ];
-        HittableList { objects: spheres }
+        HittableList {
+            objects,
+            materials,
+            bvh: None,
+            from_srgb: Matrix3::diagonal(Color::one()),
+            to_srgb: Matrix3::diagonal(Color::one()),
+            from_xyz: xyz_to_rgb(ColorSpace::Srgb),
+        }
+    }
+
+    // Set before adding materials, their colors are converted when they are created. The
+    // default material is grey, which is the same in every working space
+    pub fn set_working_space(&mut self, space: ColorSpace) {
+        self.from_srgb = ColorSpace::Srgb.conversion(space);
+        self.to_srgb = space.conversion(ColorSpace::Srgb);
+        self.from_xyz = xyz_to_rgb(space);
+    }
+
+    // A linear sRGB color in the working space
+    pub fn color(&self, srgb: Color) -> Color {
+        self.from_srgb * srgb
+    }
+
+    // A working space color upsampled to a spectrum, at the wavelengths
+    pub fn spectrum(&self, color: Color, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        SampledSpectrum::from_srgb(self.to_srgb * color, wavelengths)
+    }
+
+    // The working space color of a spectrum, at the end of its path
+    pub fn spectrum_color(
+        &self,
+        spectrum: SampledSpectrum,
+        wavelengths: &SampledWavelengths,
+    ) -> Color {
+        self.from_xyz * spectrum.to_xyz(wavelengths)
+    }
+
+    // The default world starts with its sphere in the middle, scenes put their own object in
+    // its place
+    pub fn set_center(&mut self, object: Box<dyn Hittable>) {
+        self.objects[0] = object;
+        self.bvh = None;
+    }
+
+    pub fn add(&mut self, object: Box<dyn Hittable>) {
+        self.objects.push(object);
+        self.bvh = None;
+    }
+
+    // Call once all objects are in place, objects changed afterwards are not seen by the BVH
+    pub fn build_bvh(&mut self) {
+        let bounds: Vec<Option<Aabb>> = self.objects.iter().map(|o| o.bounding_box()).collect();
+        self.bvh = Some(Bvh::new(&bounds));
+    }
+
+    // Returns the index to give objects with this material
+    pub fn add_material(&mut self, material: Box<dyn Material>) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    pub fn material(&self, index: usize) -> &dyn Material {
+        self.materials[index].as_ref()
     }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut temp_rec = HitRecord::new(Point3::zero(), Vec3::zero(), t_max, true);
-
-        for object in &self.objects {
-            if let Some(record) = object.hit(ray, t_min, t_max) {
-                if record.t < temp_rec.t {
-                    temp_rec = record;
-                }
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let mut hit_object = |index: usize, closest: f64| {
+            let record = self.objects[index].hit(ray, t_min, closest, rng)?;
+            Some((
+                record.t,
+                HitRecord {
+                    object: index,
+                    ..record
+                },
+            ))
+        };
+
+        if let Some(bvh) = &self.bvh {
+            return bvh.hit(ray, t_min, t_max, hit_object);
+        }
+        let mut closest = None;
+        for index in 0..self.objects.len() {
+            let t = closest
+                .as_ref()
+                .map_or(t_max, |record: &HitRecord| record.t);
+            if let Some((_, record)) = hit_object(index, t) {
+                closest = Some(record);
             }
         }
+        closest
+    }
 
-        if temp_rec.t == t_max {
-            None
-        } else {
-            Some(temp_rec)
-        }
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.objects
+            .iter()
+            .map(|o| o.bounding_box())
+            .reduce(|a, b| Some(a?.surrounding(b?)))
+            .flatten()
     }
 }
@@ -1,16 +1,71 @@
+mod aabb;
+mod aov;
+mod bvh;
 mod camera;
+mod checkpoint;
+mod color;
+mod csg;
+mod cuboid;
+mod curve;
+mod denoise;
+mod displacement;
+mod distributed;
+mod exr;
+mod film;
+mod filter;
+mod hair;
 mod hittable;
 mod hittable_list;
+mod material;
+mod mesh;
+mod obj;
+mod particles;
+mod plane;
+mod post;
+mod ppm;
+mod progress;
+mod quadric;
 mod ray;
 mod render;
+mod sampler;
+mod scene;
+mod sdf;
+mod settings;
+mod spectrum;
 mod sphere;
+mod subdivision;
+mod texture;
+mod tile;
+mod tonemap;
+mod torus;
 mod vector;
+mod volume;
 
-use crate::render::render;
+use crate::render::{render, RenderConfig, RenderReport, MAX_DEPTH};
 use camera::Camera;
+use checkpoint::{Checkpoint, Checkpointer};
+use denoise::{Denoiser, Guide};
+use exr::Attribute;
+use film::Film;
 use hittable_list::*;
 use log::error;
+use material::{Dielectric, Diffuse};
+use mesh::Mesh;
+use particles::{ParticleCache, Particles};
 use pixels::{Error, Pixels, SurfaceTexture};
+use post::PostProcess;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use settings::Settings;
+use std::io;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use tile::Tile;
+use tonemap::View;
+use vector::Color;
+use volume::{HeterogeneousMedium, VoxelGrid};
 use winit::dpi::LogicalSize;
 use winit::event::{Event, VirtualKeyCode};
 use winit::event_loop::{ControlFlow, EventLoop};
@@ -21,9 +76,226 @@ const ASPECT_RATIO: f64 = 16.0 / 9.0;
 const IMAGE_WIDTH: u32 = 600;
 const IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as u32;
 const SAMPLES_PER_PIXEL: u32 = 10;
+// Cap for renders that run against a time limit
+const MAX_SAMPLES_PER_PIXEL: u32 = 1 << 16;
+
+// Sent from the render thread to the window
+enum RenderEvent {
+    // A finished tile
+    Tile {
+        tile: Tile,
+        colors: Vec<Color>,
+    },
+    // The whole image with the post effects once the render is done, denoised as well with
+    // --denoise, and the guide to denoise it later otherwise
+    Finished {
+        image: Vec<Color>,
+        denoised: Option<Vec<Color>>,
+        guide: Guide,
+    },
+}
+
+fn parse_settings(args: &[String]) -> Settings {
+    Settings::from_args(args.iter().cloned()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    })
+}
+
+// The scene described by the settings
+fn build_scene(settings: &Settings) -> Result<(Camera, HittableList), String> {
+    let camera = Camera::new(settings.aperture);
+    let mut world = HittableList::new();
+    world.set_working_space(settings.view.working_space());
+    let glass = settings
+        .glass
+        .map(|ior| world.add_material(Box::new(Dielectric::new(ior))));
+    settings.scene.build(&mut world, glass);
+    if let Some(path) = &settings.volume {
+        let grid = VoxelGrid::load(path)
+            .map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+        let albedo = world.color(Color::new(0.5, 0.5, 0.5));
+        let material = world.add_material(Box::new(Diffuse::new(albedo)));
+        let medium = HeterogeneousMedium::new(grid, settings.volume_density, material);
+        let mut rng = StdRng::seed_from_u64(settings.seed);
+        let transmittance = medium.transmittance_from(camera.origin(), &mut rng);
+        eprintln!(
+            "{} lets {:.0}% of the light through its middle",
+            path.display(),
+            transmittance * 100.0
+        );
+        world.add(Box::new(medium));
+    }
+    if let Some(path) = &settings.mesh {
+        let mut cage =
+            obj::load(path).map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+        if let Some(scheme) = settings.subdivision {
+            cage = cage.subdivided(scheme, settings.subdivision_level);
+        }
+        if let Some(source) = &settings.displacement {
+            let texture = source
+                .load()
+                .map_err(|e| format!("Failed to load displacement: {}", e))?;
+            cage = cage
+                .tessellated(settings.displacement_edge)
+                .map_err(|e| format!("Failed to tessellate {}: {}", path.display(), e))?
+                .displaced(texture.as_ref(), settings.displacement_scale);
+        }
+        let albedo = world.color(Color::new(0.5, 0.5, 0.5));
+        let material = world.add_material(Box::new(Diffuse::new(albedo)));
+        world.add(Box::new(Mesh::new(&cage, material)));
+    }
+    if let Some(path) = &settings.particles {
+        let mut cache = ParticleCache::load(path, settings.particle_radius as f32)
+            .map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+        cache.convert_colors(|color| world.color(color));
+        let albedo = world.color(Color::new(0.8, 0.8, 0.8));
+        let material = world.add_material(Box::new(Diffuse::new(albedo)));
+        world.add(Box::new(Particles::new(cache, material)));
+    }
+    world.build_bvh();
+    Ok((camera, world))
+}
+
+fn render_config(settings: &Settings) -> RenderConfig {
+    let samples = match settings.time_limit {
+        Some(_) => settings.samples.unwrap_or(MAX_SAMPLES_PER_PIXEL),
+        None => settings.samples.unwrap_or(SAMPLES_PER_PIXEL),
+    };
+    RenderConfig {
+        image_width: IMAGE_WIDTH,
+        image_height: IMAGE_HEIGHT,
+        samples,
+        sampler: settings.sampler,
+        seed: settings.seed,
+        max_depth: MAX_DEPTH,
+        noise_threshold: settings.noise_threshold,
+        min_samples: settings.min_samples,
+        tile_size: settings.tile_size,
+        tile_order: settings.tile_order,
+        time_limit: settings.time_limit,
+        spectral: settings.spectral,
+        filter: settings.filter,
+    }
+}
+
+// Writes the beauty image of the film as OpenEXR or PPM, depending on the extension, with the
+// report next to it
+fn write_image(
+    path: &Path,
+    settings: &Settings,
+    camera: &Camera,
+    film: &Film,
+    beauty: &[Color],
+    report: &RenderReport,
+) -> io::Result<()> {
+    if path.extension().is_some_and(|extension| extension == "exr") {
+        let channels = exr::film_channels(film, beauty, settings.exr_precision, settings.aovs);
+        let attributes = [
+            (
+                "samplesPerPixel",
+                Attribute::Float(report.mean_samples as f32),
+            ),
+            ("minSamples", Attribute::Int(report.min_samples as i32)),
+            ("maxSamples", Attribute::Int(report.max_samples as i32)),
+            ("renderTime", Attribute::Float(report.elapsed.as_secs_f32())),
+            ("seed", Attribute::String(settings.seed.to_string())),
+            ("cameraPosition", Attribute::V3f(camera.origin())),
+            (
+                "fieldOfView",
+                Attribute::Float(camera.field_of_view() as f32),
+            ),
+            (
+                "focusDistance",
+                Attribute::Float(camera.focus_distance() as f32),
+            ),
+            ("lensAperture", Attribute::Float(camera.aperture() as f32)),
+            // The pixels are linear, in the working space
+            (
+                "chromaticities",
+                Attribute::Chromaticities(
+                    settings
+                        .view
+                        .working_space()
+                        .chromaticities()
+                        .map(|(x, y)| [x as f32, y as f32])
+                        .concat()
+                        .try_into()
+                        .unwrap(),
+                ),
+            ),
+        ];
+        let (width, height) = (film.width, film.height);
+        exr::write_exr(
+            path,
+            width,
+            height,
+            channels,
+            settings.exr_compression,
+            &attributes,
+        )?;
+    } else {
+        let colors: Vec<Color> = beauty.iter().map(|&c| settings.view.apply(c)).collect();
+        ppm::write_ppm(path, film.width, film.height, &colors)?;
+        if settings.aovs {
+            aov::write_aovs(path, film, &settings.view)?;
+        }
+    }
+    std::fs::write(path.with_extension("txt"), report.to_string())
+}
+
+// Applies the post effects, if there are any, to a finished image
+fn post_process(post: &PostProcess, image: Vec<Color>) -> Vec<Color> {
+    if post.is_enabled() {
+        post.apply(IMAGE_WIDTH, IMAGE_HEIGHT, &image)
+    } else {
+        image
+    }
+}
+
+// Redraws the whole frame from a linear image
+fn draw(frame: &mut [u8], colors: &[Color], view: &View) {
+    for (pixel, &color) in frame.chunks_exact_mut(4).zip(colors) {
+        pixel.copy_from_slice(&view.apply(color).to_rgba());
+    }
+}
 
 fn main() -> Result<(), Error> {
-    let event_loop = EventLoop::new();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let settings = parse_settings(&args);
+
+    // A worker renders tiles for a coordinator, with the settings the coordinator sends
+    if let Some(address) = &settings.worker {
+        let setup = |args: &[String]| {
+            let settings = Settings::from_args(args.iter().cloned())?;
+            let (camera, world) = build_scene(&settings)?;
+            Ok((camera, world, render_config(&settings)))
+        };
+        match distributed::work(address, &setup) {
+            Ok(tiles) => eprintln!("Rendered {} tiles", tiles),
+            Err(e) => {
+                eprintln!("Worker failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    // A resumed render takes its settings, and so its scene, from the checkpoint
+    let (args, settings, film) = match &settings.resume {
+        Some(path) => {
+            let checkpoint =
+                Checkpoint::load(path, (IMAGE_WIDTH, IMAGE_HEIGHT)).unwrap_or_else(|e| {
+                    eprintln!("Failed to load {}: {}", path.display(), e);
+                    std::process::exit(1);
+                });
+            let settings = parse_settings(&checkpoint.args);
+            (checkpoint.args, settings, checkpoint.film)
+        }
+        None => (args, settings, Film::new(IMAGE_WIDTH, IMAGE_HEIGHT)),
+    };
+
+    let event_loop = EventLoop::<RenderEvent>::with_user_event();
     let mut input = WinitInputHelper::new();
     let window = {
         let size = LogicalSize::new(IMAGE_WIDTH as f64, IMAGE_HEIGHT as f64);
@@ -41,21 +313,124 @@ fn main() -> Result<(), Error> {
         Pixels::new(IMAGE_WIDTH, IMAGE_HEIGHT, surface_texture)?
     };
 
-    let camera = Camera::new();
-    let world = HittableList::new();
+    let (camera, world) = build_scene(&settings).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let config = render_config(&settings);
 
+    let listener = settings.serve.as_ref().map(|address| {
+        let listener = TcpListener::bind(address).unwrap_or_else(|e| {
+            eprintln!("Failed to listen on {}: {}", address, e);
+            std::process::exit(1);
+        });
+        eprintln!("Waiting for workers on {}", address);
+        listener
+    });
+    let worker_timeout = Duration::from_secs(settings.worker_timeout);
+    let mut view = settings.view;
+    let mut show_denoised = settings.denoise.is_some();
+    let post = settings.post;
+    let coordinator_args = args.clone();
+
+    let checkpointer = settings.checkpoint.as_ref().map(|path| {
+        let interval = Duration::from_secs(settings.checkpoint_interval);
+        Checkpointer::new(path.clone(), interval, args)
+    });
+
+    // Render in the background, the window shows every tile as soon as it is done
+    let proxy = Mutex::new(event_loop.create_proxy());
+    std::thread::spawn(move || {
+        let on_tile = |tile: &Tile, film: &Film| {
+            let event = RenderEvent::Tile {
+                tile: *tile,
+                colors: film.tile_colors(tile),
+            };
+            // The window may already be closed, the render still finishes
+            let _ = proxy.lock().unwrap().send_event(event);
+
+            if let Some(checkpointer) = &checkpointer {
+                checkpointer.tick(film);
+            }
+        };
+        let (film, report) = match listener {
+            Some(listener) => distributed::coordinate(
+                listener,
+                &coordinator_args,
+                &config,
+                film,
+                &on_tile,
+                worker_timeout,
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to coordinate the render: {}", e);
+                std::process::exit(1);
+            }),
+            None => render(&camera, &world, &config, film, &on_tile),
+        };
+        eprint!("{}", report);
+
+        let guide = Guide::from_film(&film);
+        let denoised = settings
+            .denoise
+            .map(|denoiser| post_process(&settings.post, denoiser.denoise(&guide)));
+        let image = post_process(&settings.post, film.colors());
+        let beauty = denoised.clone().unwrap_or_else(|| image.clone());
+        let _ = proxy.lock().unwrap().send_event(RenderEvent::Finished {
+            image,
+            denoised,
+            guide,
+        });
+
+        if let Some(path) = &settings.output {
+            write_image(path, &settings, &camera, &film, &beauty, &report).unwrap_or_else(|e| {
+                eprintln!("Failed to write {}: {}", path.display(), e);
+            });
+        }
+        if let Some(path) = &settings.sample_map {
+            ppm::write_ppm(path, film.width, film.height, &film.sample_map()).unwrap_or_else(|e| {
+                eprintln!("Failed to write {}: {}", path.display(), e);
+            });
+        }
+    });
+
+    // The linear image so far, the frame is redrawn from it when the view changes
+    let mut image = vec![Color::zero(); (IMAGE_WIDTH * IMAGE_HEIGHT) as usize];
+    let mut denoised: Option<Vec<Color>> = None;
+    let mut guide: Option<Guide> = None;
     event_loop.run(move |event, _, control_flow| {
+        match &event {
+            // Copy finished tiles into the frame
+            Event::UserEvent(RenderEvent::Tile { tile, colors }) => {
+                let frame = pixels.get_frame();
+                for ((x, y), &color) in tile.pixels().zip(colors) {
+                    let index = (y * IMAGE_WIDTH + x) as usize;
+                    image[index] = color;
+                    let rgba = view.apply(color).to_rgba();
+                    frame[4 * index..4 * index + 4].copy_from_slice(&rgba);
+                }
+                window.request_redraw();
+            }
+            Event::UserEvent(RenderEvent::Finished {
+                image: finished,
+                denoised: finished_denoised,
+                guide: finished_guide,
+            }) => {
+                image = finished.clone();
+                denoised = finished_denoised.clone();
+                guide = Some(finished_guide.clone());
+                let colors = match &denoised {
+                    Some(denoised) if show_denoised => denoised,
+                    _ => &image,
+                };
+                draw(pixels.get_frame(), colors, &view);
+                window.request_redraw();
+            }
+            _ => {}
+        }
+
         // Draw the current frame
         if let Event::RedrawRequested(_) = event {
-            render(
-                &camera,
-                &world,
-                IMAGE_WIDTH,
-                IMAGE_HEIGHT,
-                SAMPLES_PER_PIXEL,
-                pixels.get_frame(),
-            );
-
             if pixels
                 .render()
                 .map_err(|e| error!("pixels.render() failed: {}", e))
@@ -79,6 +454,45 @@ fn main() -> Result<(), Error> {
                 pixels.resize_surface(size.width, size.height);
             }
 
+            // Change the exposure by half a stop or cycle through the tone maps
+            let previous = view;
+            if input.key_pressed(VirtualKeyCode::Equals)
+                || input.key_pressed(VirtualKeyCode::Plus)
+                || input.key_pressed(VirtualKeyCode::NumpadAdd)
+            {
+                view.exposure += 0.5;
+            }
+            if input.key_pressed(VirtualKeyCode::Minus)
+                || input.key_pressed(VirtualKeyCode::NumpadSubtract)
+            {
+                view.exposure -= 0.5;
+            }
+            if input.key_pressed(VirtualKeyCode::T) {
+                view.tone_map = view.tone_map.next();
+            }
+            // Switch between the noisy and the denoised image
+            let was_denoised = show_denoised;
+            if input.key_pressed(VirtualKeyCode::D) {
+                // Without --denoise the image is only denoised once it is asked for
+                if denoised.is_none() {
+                    denoised = guide
+                        .as_ref()
+                        .map(|guide| post_process(&post, Denoiser::Atrous.denoise(guide)));
+                }
+                match denoised {
+                    Some(_) => show_denoised = !show_denoised,
+                    None => eprintln!("The denoised image is ready when the render is done"),
+                }
+            }
+            if view != previous || show_denoised != was_denoised {
+                eprintln!("View: {}, denoised {}", view, show_denoised);
+                let colors = match &denoised {
+                    Some(denoised) if show_denoised => denoised,
+                    _ => &image,
+                };
+                draw(pixels.get_frame(), colors, &view);
+            }
+
             // Update internal state and request a redraw
             window.request_redraw();
         }
@@ -0,0 +1,353 @@
+use std::str::FromStr;
+
+use rand::RngCore;
+
+use crate::hittable::HitRecord;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::spectrum::SampledWavelengths;
+use crate::vector::*;
+
+pub struct Scatter {
+    pub attenuation: Color,
+    pub ray: Ray,
+}
+
+pub trait Material: Send + Sync {
+    // The bounced ray and the fraction of its light that makes it back, None when the light
+    // is absorbed
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+        rng: &mut dyn RngCore,
+    ) -> Option<Scatter>;
+
+    // Scatter for spectral rendering, the light of the path has the given wavelengths.
+    // Materials that send every wavelength its own way terminate the secondary wavelengths
+    fn scatter_spectral(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        _wavelengths: &mut SampledWavelengths,
+        sampler: &mut dyn Sampler,
+        rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        self.scatter(ray, record, sampler, rng)
+    }
+
+    // Overall reflectance, for the albedo AOV
+    fn albedo(&self) -> Color;
+}
+
+// Bounces light uniformly over the hemisphere around the normal, tinted by the color of the
+// hit
+pub struct Diffuse {
+    albedo: Color,
+}
+
+impl Diffuse {
+    pub fn new(albedo: Color) -> Diffuse {
+        Diffuse { albedo }
+    }
+}
+
+impl Material for Diffuse {
+    fn scatter(
+        &self,
+        _ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+        _rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        let direction = Vec3::in_hemisphere(record.normal, sampler.get_2d());
+        Some(Scatter {
+            attenuation: self.albedo * record.tint,
+            ray: Ray::new(record.point, direction),
+        })
+    }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+}
+
+// Diffuse with a checkerboard of two albedos over the UVs of the surface
+pub struct Checker {
+    even: Color,
+    odd: Color,
+    // Size of the squares in UV units
+    scale: f64,
+}
+
+impl Checker {
+    pub fn new(even: Color, odd: Color, scale: f64) -> Checker {
+        Checker { even, odd, scale }
+    }
+
+    fn albedo_at(&self, u: f64, v: f64) -> Color {
+        let square = (u / self.scale).floor() + (v / self.scale).floor();
+        if square.rem_euclid(2.0) == 0.0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+impl Material for Checker {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+        rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        let albedo = self.albedo_at(record.u, record.v);
+        Diffuse::new(albedo).scatter(ray, record, sampler, rng)
+    }
+
+    fn albedo(&self) -> Color {
+        (self.even + self.odd) / 2.0
+    }
+}
+
+// Wavelength of the sodium d line, where glasses are usually specified and where RGB renders
+// evaluate the index of refraction
+const FRAUNHOFER_D: f64 = 587.56;
+
+// Index of refraction as a function of the wavelength
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ior {
+    Constant(f64),
+    // n = a + b / λ², with λ in micrometers
+    Cauchy { a: f64, b: f64 },
+    // n² = 1 + Σ b λ² / (λ² - c), with λ in micrometers
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Ior {
+    // Schott N-BK7, a common crown glass
+    pub const BK7: Ior = Ior::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+    // Schott SF11, a dense flint glass that spreads colors far apart
+    pub const SF11: Ior = Ior::Sellmeier {
+        b: [1.73759695, 0.313747346, 1.89878101],
+        c: [0.013188707, 0.0623068142, 155.23629],
+    };
+
+    // The index at a wavelength in nanometers
+    pub fn at(&self, lambda: f64) -> f64 {
+        let micrometers = lambda / 1000.0;
+        let squared = micrometers * micrometers;
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / squared,
+            Ior::Sellmeier { b, c } => {
+                let sum: f64 = (0..3).map(|i| b[i] * squared / (squared - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+// A glass by name, a constant index, "cauchy:a:b" or "sellmeier:b1:b2:b3:c1:c2:c3"
+impl FromStr for Ior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Ior, String> {
+        let error = || format!("unknown index of refraction {}", s);
+        let mut parts = s.split(':');
+        let kind = parts.next().unwrap_or_default();
+        let values = parts
+            .map(|part| part.parse::<f64>().map_err(|_| error()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match (kind, values.as_slice()) {
+            ("bk7", []) => Ok(Ior::BK7),
+            ("sf11", []) => Ok(Ior::SF11),
+            ("cauchy", &[a, b]) => Ok(Ior::Cauchy { a, b }),
+            ("sellmeier", &[b1, b2, b3, c1, c2, c3]) => Ok(Ior::Sellmeier {
+                b: [b1, b2, b3],
+                c: [c1, c2, c3],
+            }),
+            (n, []) => n.parse().map(Ior::Constant).map_err(|_| error()),
+            _ => Err(error()),
+        }
+    }
+}
+
+// Glass and other clear materials: reflects or refracts, in proportion to Schlick's
+// approximation of the Fresnel reflectance
+pub struct Dielectric {
+    ior: Ior,
+}
+
+impl Dielectric {
+    pub fn new(ior: Ior) -> Dielectric {
+        Dielectric { ior }
+    }
+
+    fn scatter_with_ior(&self, ray: &Ray, record: &HitRecord, ior: f64, u: f64) -> Scatter {
+        let eta = if record.front_face { 1.0 / ior } else { ior };
+        let direction = ray.direction.unit_vector();
+        let cos_theta = (-direction).dot(record.normal).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+        let r0 = ((1.0 - eta) / (1.0 + eta)).powi(2);
+        let reflectance = r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5);
+        let direction = if eta * sin_theta > 1.0 || u < reflectance {
+            direction.reflect(record.normal)
+        } else {
+            direction.refract(record.normal, eta)
+        };
+        Scatter {
+            attenuation: Color::one(),
+            ray: Ray::new(record.point, direction),
+        }
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+        _rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        let ior = self.ior.at(FRAUNHOFER_D);
+        Some(self.scatter_with_ior(ray, record, ior, sampler.get_2d().0))
+    }
+
+    fn scatter_spectral(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        wavelengths: &mut SampledWavelengths,
+        sampler: &mut dyn Sampler,
+        _rng: &mut dyn RngCore,
+    ) -> Option<Scatter> {
+        if self.ior.is_dispersive() {
+            wavelengths.terminate_secondary();
+        }
+        let ior = self.ior.at(wavelengths.hero());
+        Some(self.scatter_with_ior(ray, record, ior, sampler.get_2d().0))
+    }
+
+    fn albedo(&self) -> Color {
+        Color::one()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::SamplerKind;
+    use crate::vector::Point3;
+    use assert_approx_eq::*;
+    use rand::prelude::*;
+
+    #[test]
+    fn material_checker() {
+        let checker = Checker::new(Color::one(), Color::zero(), 0.5);
+
+        assert_eq!(Color::one(), checker.albedo_at(0.1, 0.1));
+        assert_eq!(Color::zero(), checker.albedo_at(0.6, 0.1));
+        assert_eq!(Color::one(), checker.albedo_at(-0.1, -0.1));
+        assert_eq!(Color::zero(), checker.albedo_at(-0.1, 0.1));
+        assert_eq!(Color::one() * 0.5, checker.albedo());
+    }
+
+    #[test]
+    fn material_ior() {
+        assert_approx_eq!(1.5168, Ior::BK7.at(FRAUNHOFER_D), 1e-4);
+        assert_approx_eq!(1.7847, Ior::SF11.at(FRAUNHOFER_D), 1e-4);
+        let cauchy = Ior::Cauchy {
+            a: 1.5046,
+            b: 0.0042,
+        };
+        assert_approx_eq!(1.5168, cauchy.at(FRAUNHOFER_D), 1e-3);
+        // Blue bends more than red
+        for ior in [Ior::BK7, Ior::SF11, cauchy] {
+            assert!(ior.at(450.0) > ior.at(650.0));
+            assert!(ior.is_dispersive());
+        }
+        assert_eq!(1.33, Ior::Constant(1.33).at(450.0));
+    }
+
+    #[test]
+    fn material_ior_from_str() {
+        assert_eq!(Ok(Ior::BK7), "bk7".parse());
+        assert_eq!(Ok(Ior::Constant(1.5)), "1.5".parse());
+        assert_eq!(
+            Ok(Ior::Cauchy { a: 1.5, b: 0.004 }),
+            "cauchy:1.5:0.004".parse()
+        );
+        assert_eq!(
+            Ok(Ior::SF11),
+            "sellmeier:1.73759695:0.313747346:1.89878101:0.013188707:0.0623068142:155.23629"
+                .parse()
+        );
+        assert!("cauchy:1.5".parse::<Ior>().is_err());
+        assert!("crystal".parse::<Ior>().is_err());
+    }
+
+    #[test]
+    fn material_dielectric_refracts_by_wavelength() {
+        let glass = Dielectric::new(Ior::SF11);
+        let ray = Ray::new(Point3::new(0.0, 1.0, -1.0), Vec3::new(0.0, -1.0, 1.0));
+        let record = HitRecord::new(Point3::zero(), Vec3::new(0.0, 1.0, 0.0), 1.0, true, 0);
+        let mut sampler = SamplerKind::Independent.create(1, 1, 0);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut refract = |u: f64| {
+            let mut wavelengths = SampledWavelengths::sample_uniform(u);
+            // Some samples reflect, take the first that refracts
+            (0..16)
+                .find_map(|sample| {
+                    sampler.start_pixel_sample(0, 0, sample);
+                    let scatter = glass
+                        .scatter_spectral(
+                            &ray,
+                            &record,
+                            &mut wavelengths,
+                            sampler.as_mut(),
+                            &mut rng,
+                        )
+                        .unwrap();
+                    assert!(wavelengths.is_terminated());
+                    Some(scatter.ray.direction).filter(|direction| direction.y < 0.0)
+                })
+                .unwrap()
+        };
+        let blue = refract(0.2);
+        let red = refract(0.6);
+
+        // Both bend towards the normal, blue more so
+        assert!(blue.z / -blue.y < 1.0);
+        assert!(blue.z / -blue.y < red.z / -red.y);
+    }
+
+    #[test]
+    fn material_dielectric_total_internal_reflection() {
+        let glass = Dielectric::new(Ior::Constant(1.5));
+        // Leaving the glass at a grazing angle
+        let ray = Ray::new(Point3::new(0.0, -0.1, -1.0), Vec3::new(0.0, 0.1, 1.0));
+        let record = HitRecord::new(Point3::zero(), Vec3::new(0.0, 1.0, 0.0), 1.0, false, 0);
+        let mut sampler = SamplerKind::Independent.create(1, 1, 0);
+        let mut rng = StdRng::seed_from_u64(0);
+        for sample in 0..8 {
+            sampler.start_pixel_sample(0, 0, sample);
+            let scatter = glass
+                .scatter(&ray, &record, sampler.as_mut(), &mut rng)
+                .unwrap();
+            assert!(scatter.ray.direction.y < 0.0);
+        }
+    }
+}
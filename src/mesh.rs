@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::subdivision::{PolyMesh, Topology};
+use crate::vector::{Point3, Vec3};
+
+// Thickness given to the bounding boxes of triangles that lie flat along an axis
+const FLAT_PADDING: f64 = 1e-6;
+
+// A mesh of triangles with its own BVH. Normals are smooth across the surface but not across
+// creases and the boundary, so vertices on a crease are stored once for each side
+pub struct Mesh {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    triangles: Vec<[usize; 3]>,
+    bvh: Bvh,
+    bounds: Option<Aabb>,
+    material: usize,
+}
+
+impl Mesh {
+    pub fn new(mesh: &PolyMesh, material: usize) -> Mesh {
+        let mesh = mesh.triangulated();
+        let topology = Topology::new(&mesh);
+        let corner = |f: usize, position: usize| {
+            let i = mesh.faces[f].iter().position(|c| c.position == position);
+            f * 3 + i.unwrap()
+        };
+
+        // Corners around a position share a normal when their faces meet at a smooth edge
+        let mut regions: Vec<usize> = (0..mesh.faces.len() * 3).collect();
+        for (e, faces) in topology.edge_faces.iter().enumerate() {
+            if topology.is_sharp(&mesh, e) {
+                continue;
+            }
+            let (a, b) = topology.edges[e];
+            for position in [a, b] {
+                union(
+                    &mut regions,
+                    corner(faces[0], position),
+                    corner(faces[1], position),
+                );
+            }
+        }
+
+        // Area weighted normals of the faces, summed over each region
+        let mut sums = vec![Vec3::zero(); regions.len()];
+        for (f, face) in mesh.faces.iter().enumerate() {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.positions[face[i].position]);
+            let normal = (b - a).cross(c - a);
+            for i in 0..3 {
+                let region = find(&mut regions, f * 3 + i);
+                sums[region] += normal;
+            }
+        }
+
+        let mut vertices = HashMap::new();
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut triangles = Vec::with_capacity(mesh.faces.len());
+        for (f, face) in mesh.faces.iter().enumerate() {
+            let mut triangle = [0; 3];
+            for (i, c) in face.iter().enumerate() {
+                let region = find(&mut regions, f * 3 + i);
+                triangle[i] = *vertices
+                    .entry((c.position, c.uv, region))
+                    .or_insert_with(|| {
+                        positions.push(mesh.positions[c.position]);
+                        normals.push(sums[region].unit_vector());
+                        uvs.push(c.uv.map_or((0.0, 0.0), |uv| mesh.uvs[uv]));
+                        positions.len() - 1
+                    });
+            }
+            triangles.push(triangle);
+        }
+
+        let boxes: Vec<Option<Aabb>> = triangles
+            .iter()
+            .map(|t| {
+                let [a, b, c] = t.map(|v| positions[v]);
+                Some(
+                    Aabb::new(a, a)
+                        .surrounding(Aabb::new(b, b))
+                        .surrounding(Aabb::new(c, c))
+                        .padded(FLAT_PADDING),
+                )
+            })
+            .collect();
+        let bounds = boxes.iter().flatten().copied().reduce(Aabb::surrounding);
+        Mesh {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            bvh: Bvh::new(&boxes),
+            bounds,
+            material,
+        }
+    }
+
+    // Möller-Trumbore, returns t and the barycentric coordinates of the second and third
+    // vertices
+    fn intersect(
+        &self,
+        index: usize,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<(f64, f64, f64)> {
+        let [a, b, c] = self.triangles[index].map(|v| self.positions[v]);
+        let (ab, ac) = (b - a, c - a);
+        let p = ray.direction.cross(ac);
+        let determinant = ab.dot(p);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let inverse = 1.0 / determinant;
+        let s = ray.origin - a;
+        let u = s.dot(p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(ab);
+        let v = ray.direction.dot(q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = ac.dot(q) * inverse;
+        if t > t_min && t < t_max {
+            Some((t, u, v))
+        } else {
+            None
+        }
+    }
+}
+
+fn find(regions: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while regions[root] != root {
+        root = regions[root];
+    }
+    regions[i] = root;
+    root
+}
+
+fn union(regions: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(regions, a), find(regions, b));
+    // The lower index wins so regions come out the same whatever the order
+    regions[a.max(b)] = a.min(b);
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let (index, t, u, v) = self.bvh.hit(ray, t_min, t_max, |index, closest| {
+            let (t, u, v) = self.intersect(index, ray, t_min, closest)?;
+            Some((t, (index, t, u, v)))
+        })?;
+
+        let [a, b, c] = self.triangles[index];
+        let w = 1.0 - u - v;
+        let geometric =
+            (self.positions[b] - self.positions[a]).cross(self.positions[c] - self.positions[a]);
+        let front_face = ray.direction.dot(geometric) < 0.0;
+        let mut normal =
+            (self.normals[a] * w + self.normals[b] * u + self.normals[c] * v).unit_vector();
+        // Keep the smooth normal on the same side as the triangle
+        if normal.dot(geometric) < 0.0 {
+            normal = -normal;
+        }
+        let uv = |i: usize| self.uvs[i];
+        let (tu, tv) = (
+            uv(a).0 * w + uv(b).0 * u + uv(c).0 * v,
+            uv(a).1 * w + uv(b).1 * u + uv(c).1 * v,
+        );
+        Some(HitRecord::new(ray.at(t), normal, t, front_face, self.material).with_uv(tu, tv))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subdivision::tests::cube;
+    use crate::subdivision::Scheme;
+    use assert_approx_eq::*;
+    use rand::prelude::*;
+
+    fn toward_center(origin: Point3) -> Ray {
+        Ray::new(origin, -origin)
+    }
+
+    #[test]
+    fn mesh_hit() {
+        let mesh = Mesh::new(&cube(), 0);
+        let rng = &mut thread_rng();
+
+        let record = mesh
+            .hit(
+                &toward_center(Point3::new(0.2, 0.3, 4.0)),
+                0.001,
+                f64::MAX,
+                rng,
+            )
+            .unwrap();
+        assert_approx_eq!(0.75, record.t, 1e-12);
+        assert!(record.front_face);
+        // Smooth across the whole cube, so not the normal of the face
+        assert!(record.normal.z < 0.99);
+
+        let ray = Ray::new(Point3::zero(), Vec3::new(1.0, 0.0, 0.0));
+        let record = mesh.hit(&ray, 0.001, f64::MAX, rng).unwrap();
+        assert!(!record.front_face);
+        assert_approx_eq!(1.0, record.t, 1e-12);
+
+        let ray = Ray::new(Point3::new(3.0, 3.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(mesh.hit(&ray, 0.001, f64::MAX, rng).is_none());
+    }
+
+    #[test]
+    fn mesh_creases_split_normals() {
+        let mut creased = cube();
+        creased.creases = Topology::new(&creased).edges.into_iter().collect();
+        let mesh = Mesh::new(&creased, 0);
+        let rng = &mut thread_rng();
+
+        // Each face its own normal, so corners are stored once for each of their three faces
+        assert_eq!(24, mesh.positions.len());
+        let record = mesh
+            .hit(
+                &toward_center(Point3::new(0.2, 0.3, 4.0)),
+                0.001,
+                f64::MAX,
+                rng,
+            )
+            .unwrap();
+        assert_approx_eq!(1.0, record.normal.z, 1e-12);
+    }
+
+    #[test]
+    fn mesh_subdivided_is_smooth() {
+        let mesh = Mesh::new(&cube().subdivided(Scheme::CatmullClark, 3), 0);
+        let mut rng = StdRng::seed_from_u64(6);
+
+        // Close to a sphere, the normals point away from the center
+        for _ in 0..100 {
+            let origin = Vec3::random_unit_vector(&mut rng) * 3.0;
+            let record = mesh
+                .hit(&toward_center(origin), 0.001, f64::MAX, &mut rng)
+                .unwrap();
+            assert!(record.normal.dot(record.point.unit_vector()) > 0.95);
+        }
+    }
+}
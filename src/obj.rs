@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use crate::subdivision::{Corner, PolyMesh, Topology};
+use crate::vector::Point3;
+
+// Wavefront OBJ, reading positions, texture coordinates, faces and smoothing groups. Normals
+// in the file are ignored, they are worked out from the surface. Edges between faces in
+// different smoothing groups become creases
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<PolyMesh> {
+    parse(BufReader::new(File::open(path)?))
+}
+
+pub fn parse(reader: impl BufRead) -> io::Result<PolyMesh> {
+    let mut mesh = PolyMesh::default();
+    let mut groups = Vec::new();
+    let mut group = 0;
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let invalid = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", number + 1, what),
+            )
+        };
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let xyz = numbers(words).ok_or_else(|| invalid("bad vertex"))?;
+                if xyz.len() < 3 {
+                    return Err(invalid("bad vertex"));
+                }
+                mesh.positions.push(Point3::new(xyz[0], xyz[1], xyz[2]));
+            }
+            Some("vt") => {
+                let uv = numbers(words).ok_or_else(|| invalid("bad texture coordinate"))?;
+                if uv.len() < 2 {
+                    return Err(invalid("bad texture coordinate"));
+                }
+                mesh.uvs.push((uv[0], uv[1]));
+            }
+            Some("f") => {
+                let face = words
+                    .map(|word| corner(word, &mesh))
+                    .collect::<Option<Vec<Corner>>>()
+                    .ok_or_else(|| invalid("bad face"))?;
+                if face.len() < 3 {
+                    return Err(invalid("face with fewer than three corners"));
+                }
+                mesh.faces.push(face);
+                groups.push(group);
+            }
+            Some("s") => {
+                group = match words.next() {
+                    Some("off") => 0,
+                    Some(word) => word.parse().map_err(|_| invalid("bad smoothing group"))?,
+                    None => return Err(invalid("bad smoothing group")),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let topology = Topology::new(&mesh);
+    mesh.creases = topology
+        .edges
+        .iter()
+        .zip(&topology.edge_faces)
+        .filter(|(_, faces)| faces.iter().any(|&f| groups[f] != groups[faces[0]]))
+        .map(|(&edge, _)| edge)
+        .collect();
+    Ok(mesh)
+}
+
+fn numbers<'a>(words: impl Iterator<Item = &'a str>) -> Option<Vec<f64>> {
+    words.map(|word| word.parse().ok()).collect()
+}
+
+// "v", "v/vt", "v/vt/vn" or "v//vn", counting from 1 or back from the end when negative
+fn corner(word: &str, mesh: &PolyMesh) -> Option<Corner> {
+    let mut indices = word.split('/');
+    let position = index(indices.next()?, mesh.positions.len())?;
+    let uv = match indices.next() {
+        Some("") | None => None,
+        Some(uv) => Some(index(uv, mesh.uvs.len())?),
+    };
+    Some(Corner { position, uv })
+}
+
+fn index(word: &str, count: usize) -> Option<usize> {
+    let index: i64 = word.parse().ok()?;
+    let index = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if index >= 0 && (index as usize) < count {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn obj_parse() {
+        let obj = "# a quad and a triangle\n\
+                   v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 2 0 0\n\
+                   vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+                   vn 0 0 1\n\
+                   s 1\n\
+                   f 1/1/1 2/2/1 3/3/1 4/4/1\n\
+                   s off\n\
+                   f -4//1 5//1 -3//1\n";
+        let mesh = parse(Cursor::new(obj)).unwrap();
+
+        assert_eq!(5, mesh.positions.len());
+        assert_eq!(4, mesh.uvs.len());
+        assert_eq!(2, mesh.faces.len());
+        assert_eq!(
+            Corner {
+                position: 2,
+                uv: Some(2)
+            },
+            mesh.faces[0][2]
+        );
+        assert_eq!(
+            vec![1, 4, 2],
+            mesh.faces[1].iter().map(|c| c.position).collect::<Vec<_>>()
+        );
+        assert!(mesh.faces[1].iter().all(|c| c.uv.is_none()));
+        // The edge between the two smoothing groups
+        assert_eq!(1, mesh.creases.len());
+        assert!(mesh.creases.contains(&(1, 2)));
+    }
+
+    #[test]
+    fn obj_errors() {
+        assert!(parse(Cursor::new("v 1 2\n")).is_err());
+        assert!(parse(Cursor::new("v 0 0 0\nv 1 0 0\nf 1 2\n")).is_err());
+        let error = parse(Cursor::new("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n")).unwrap_err();
+        assert_eq!("line 4: bad face", error.to_string());
+    }
+}
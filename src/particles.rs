@@ -0,0 +1,521 @@
+use std::io;
+use std::path::Path;
+
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::sphere::Sphere;
+use crate::vector::{Color, Point3, Vec3};
+
+const PARTICLE_MAGIC: &[u8; 4] = b"PRTC";
+
+// Particles as stored in a cache file, each a sphere with its own radius and color
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParticleCache {
+    pub positions: Vec<[f32; 3]>,
+    pub radii: Vec<f32>,
+    pub colors: Vec<[f32; 3]>,
+}
+
+impl ParticleCache {
+    // A binary cache or a PLY file of vertices. Particles without a radius in the file get the
+    // given one and without a color white
+    pub fn load<P: AsRef<Path>>(path: P, radius: f32) -> io::Result<ParticleCache> {
+        let bytes = std::fs::read(path)?;
+        if bytes.starts_with(PARTICLE_MAGIC) {
+            parse_binary(&bytes)
+        } else if bytes.starts_with(b"ply") {
+            parse_ply(&bytes, radius)
+        } else {
+            Err(invalid("not a particle file"))
+        }
+    }
+
+    // Particles that would make no sphere, or no bounding box, fail the file
+    fn checked(self) -> io::Result<ParticleCache> {
+        if !self.positions.iter().flatten().all(|p| p.is_finite()) {
+            return Err(invalid("particle positions must be finite"));
+        }
+        if !self.radii.iter().all(|r| r.is_finite() && *r > 0.0) {
+            return Err(invalid("particle radii must be finite and above 0"));
+        }
+        Ok(self)
+    }
+
+    // Colors in files are linear sRGB, the world may work in another space
+    pub fn convert_colors(&mut self, convert: impl Fn(Color) -> Color) {
+        for color in &mut self.colors {
+            let c = convert(Color::new(
+                color[0] as f64,
+                color[1] as f64,
+                color[2] as f64,
+            ));
+            *color = [c.x as f32, c.y as f32, c.z as f32];
+        }
+    }
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what.to_string())
+}
+
+fn f32s(bytes: &[u8]) -> impl Iterator<Item = f32> + '_ {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+// File layout, all little endian:
+//   "PRTC", the count n as u32, n positions as three f32, n radii as f32, n colors as three
+//   f32
+fn parse_binary(bytes: &[u8]) -> io::Result<ParticleCache> {
+    let count = bytes
+        .get(4..8)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        .ok_or_else(|| invalid("truncated particle file"))?;
+    let end = count
+        .checked_mul(7 * 4)
+        .and_then(|length| length.checked_add(8))
+        .ok_or_else(|| invalid("too many particles"))?;
+    let data = bytes
+        .get(8..end)
+        .ok_or_else(|| invalid("truncated particle file"))?;
+    let (positions, rest) = data.split_at(count * 12);
+    let (radii, colors) = rest.split_at(count * 4);
+    let triples = |bytes: &[u8]| {
+        let values: Vec<f32> = f32s(bytes).collect();
+        values.chunks_exact(3).map(|v| [v[0], v[1], v[2]]).collect()
+    };
+    ParticleCache {
+        positions: triples(positions),
+        radii: f32s(radii).collect(),
+        colors: triples(colors),
+    }
+    .checked()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        match name {
+            "char" | "int8" => Some(Scalar::I8),
+            "uchar" | "uint8" => Some(Scalar::U8),
+            "short" | "int16" => Some(Scalar::I16),
+            "ushort" | "uint16" => Some(Scalar::U16),
+            "int" | "int32" => Some(Scalar::I32),
+            "uint" | "uint32" => Some(Scalar::U32),
+            "float" | "float32" => Some(Scalar::F32),
+            "double" | "float64" => Some(Scalar::F64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    fn read(self, bytes: &[u8], big_endian: bool) -> f64 {
+        let mut b = [0u8; 8];
+        b[..bytes.len()].copy_from_slice(bytes);
+        if big_endian {
+            b[..bytes.len()].reverse();
+        }
+        match self {
+            Scalar::I8 => b[0] as i8 as f64,
+            Scalar::U8 => b[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(b),
+        }
+    }
+
+    // Colors stored as integers run up to the largest value of the type
+    fn color_scale(self) -> f64 {
+        match self {
+            Scalar::U8 => 1.0 / 255.0,
+            Scalar::U16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+// A PLY file of vertices with x, y and z and optionally radius (or pscale) and red, green and
+// blue. Other properties are skipped, other elements have to be empty
+fn parse_ply(bytes: &[u8], radius: f32) -> io::Result<ParticleCache> {
+    let end = b"end_header";
+    let header_end = bytes
+        .windows(end.len())
+        .position(|w| w == end)
+        .ok_or_else(|| invalid("no end_header"))?;
+    let header = String::from_utf8_lossy(&bytes[..header_end]);
+    // The body starts after the line ending of end_header
+    let mut body = header_end + end.len();
+    if bytes.get(body) == Some(&b'\r') {
+        body += 1;
+    }
+    body += 1;
+
+    let mut format = None;
+    let mut count = None;
+    let mut in_vertices = false;
+    let mut properties = Vec::new();
+    for line in header.lines().skip(1) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::LittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(Format::BigEndian),
+            ["format", ..] => return Err(invalid("unknown PLY format")),
+            ["element", "vertex", n] if count.is_none() => {
+                count = Some(
+                    n.parse::<usize>()
+                        .map_err(|_| invalid("bad vertex count"))?,
+                );
+                in_vertices = true;
+            }
+            // Other elements are fine as long as they are empty
+            ["element", _, "0"] => in_vertices = false,
+            ["element", ..] => return Err(invalid("only vertices are supported")),
+            ["property", ..] if !in_vertices => {}
+            ["property", "list", ..] => return Err(invalid("list properties are not supported")),
+            ["property", scalar, name] => {
+                let scalar = Scalar::parse(scalar).ok_or_else(|| invalid("bad property"))?;
+                properties.push((name.to_string(), scalar));
+            }
+            _ => {}
+        }
+    }
+    let format = format.ok_or_else(|| invalid("no PLY format"))?;
+    let count = count.ok_or_else(|| invalid("no vertices"))?;
+    let find = |names: &[&str]| {
+        properties
+            .iter()
+            .position(|(n, _)| names.contains(&n.as_str()))
+    };
+    let xyz = [find(&["x"]), find(&["y"]), find(&["z"])];
+    if xyz.contains(&None) {
+        return Err(invalid("vertices without positions"));
+    }
+    let rgb = [
+        find(&["red", "r"]),
+        find(&["green", "g"]),
+        find(&["blue", "b"]),
+    ];
+    let size = find(&["radius", "pscale"]);
+    let total = count
+        .checked_mul(properties.len())
+        .ok_or_else(|| invalid("too many vertices"))?;
+
+    // Every vertex as its values in the order of the properties
+    let values: Vec<f64> = match format {
+        Format::Ascii => String::from_utf8_lossy(bytes.get(body..).unwrap_or(&[]))
+            .split_whitespace()
+            .take(total)
+            .map(|word| word.parse().map_err(|_| invalid("bad vertex")))
+            .collect::<io::Result<_>>()?,
+        _ => {
+            let stride: usize = properties.iter().map(|(_, s)| s.size()).sum();
+            let end = count
+                .checked_mul(stride)
+                .and_then(|length| length.checked_add(body))
+                .ok_or_else(|| invalid("too many vertices"))?;
+            let data = bytes
+                .get(body..end)
+                .ok_or_else(|| invalid("truncated vertices"))?;
+            let big_endian = format == Format::BigEndian;
+            let mut values = Vec::with_capacity(total);
+            for vertex in data.chunks_exact(stride) {
+                let mut at = 0;
+                for (_, scalar) in &properties {
+                    values.push(scalar.read(&vertex[at..at + scalar.size()], big_endian));
+                    at += scalar.size();
+                }
+            }
+            values
+        }
+    };
+    if values.len() < total {
+        return Err(invalid("truncated vertices"));
+    }
+
+    let mut cache = ParticleCache::default();
+    for vertex in values.chunks_exact(properties.len().max(1)).take(count) {
+        let value = |index: Option<usize>| index.map(|i| vertex[i]);
+        let [x, y, z] = xyz.map(|i| value(i).unwrap() as f32);
+        cache.positions.push([x, y, z]);
+        cache.radii.push(value(size).map_or(radius, |r| r as f32));
+        cache.colors.push(rgb.map(|i| match i {
+            Some(i) => (vertex[i] * properties[i].1.color_scale()) as f32,
+            None => 1.0,
+        }));
+    }
+    cache.checked()
+}
+
+// Many spheres in flat arrays, a few bytes each rather than an object apiece, in a BVH of their
+// own. They share a material, tinted by the color of each
+pub struct Particles {
+    cache: ParticleCache,
+    bvh: Bvh,
+    bounds: Option<Aabb>,
+    material: usize,
+}
+
+impl Particles {
+    pub fn new(cache: ParticleCache, material: usize) -> Particles {
+        assert_eq!(cache.positions.len(), cache.radii.len());
+        assert_eq!(cache.positions.len(), cache.colors.len());
+        let boxes: Vec<Option<Aabb>> = (0..cache.positions.len())
+            .map(|i| {
+                let (center, radius) = Particles::sphere(&cache, i);
+                let extent = Vec3::one() * radius.abs();
+                Some(Aabb::new(center - extent, center + extent))
+            })
+            .collect();
+        let bounds = boxes.iter().flatten().copied().reduce(Aabb::surrounding);
+        Particles {
+            bvh: Bvh::new(&boxes),
+            cache,
+            bounds,
+            material,
+        }
+    }
+
+    fn sphere(cache: &ParticleCache, index: usize) -> (Point3, f64) {
+        let [x, y, z] = cache.positions[index];
+        (
+            Point3::new(x as f64, y as f64, z as f64),
+            cache.radii[index] as f64,
+        )
+    }
+
+    fn intersect(&self, index: usize, ray: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let (center, radius) = Particles::sphere(&self.cache, index);
+        let oc = ray.origin - center;
+        let a = ray.direction.length_squared();
+        let half_b = oc.dot(ray.direction);
+        let c = oc.length_squared() - radius * radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant <= 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        [(-half_b - root) / a, (-half_b + root) / a]
+            .into_iter()
+            .find(|&t| t > t_min && t < t_max)
+    }
+}
+
+impl Hittable for Particles {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let (index, t) = self.bvh.hit(ray, t_min, t_max, |index, closest| {
+            let t = self.intersect(index, ray, t_min, closest)?;
+            Some((t, (index, t)))
+        })?;
+
+        let (center, radius) = Particles::sphere(&self.cache, index);
+        let p = ray.at(t);
+        let outward_normal = (p - center) / radius;
+        let front_face = ray.direction.dot(outward_normal) < 0.0;
+        let (u, v) = Sphere::uv(outward_normal);
+        let [r, g, b] = self.cache.colors[index];
+        Some(
+            HitRecord::new(p, outward_normal, t, front_face, self.material)
+                .with_uv(u, v)
+                .with_tint(Color::new(r as f64, g as f64, b as f64)),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+    use rand::prelude::*;
+
+    fn cache(particles: &[([f32; 3], f32, [f32; 3])]) -> ParticleCache {
+        ParticleCache {
+            positions: particles.iter().map(|p| p.0).collect(),
+            radii: particles.iter().map(|p| p.1).collect(),
+            colors: particles.iter().map(|p| p.2).collect(),
+        }
+    }
+
+    #[test]
+    fn particles_hit() {
+        let particles = Particles::new(
+            cache(&[
+                ([0.0, 0.0, -1.0], 0.5, [1.0, 0.0, 0.0]),
+                ([0.0, 0.0, -3.0], 0.5, [0.0, 1.0, 0.0]),
+                ([2.0, 0.0, -1.0], 0.25, [0.0, 0.0, 1.0]),
+            ]),
+            3,
+        );
+        let rng = &mut thread_rng();
+
+        let ray = Ray::new(Point3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        let record = particles.hit(&ray, 0.001, f64::MAX, rng).unwrap();
+        assert_approx_eq!(0.5, record.t, 1e-6);
+        assert_eq!(Color::new(1.0, 0.0, 0.0), record.tint);
+        assert_eq!(3, record.material);
+        assert!(record.front_face);
+
+        // Past the first, on to the second
+        let record = particles.hit(&ray, 2.0, f64::MAX, rng).unwrap();
+        assert_approx_eq!(2.5, record.t, 1e-6);
+        assert_eq!(Color::new(0.0, 1.0, 0.0), record.tint);
+
+        let ray = Ray::new(Point3::new(2.0, 0.3, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(particles.hit(&ray, 0.001, f64::MAX, rng).is_none());
+        let bounds = particles.bounding_box().unwrap();
+        assert_eq!(Point3::new(-0.5, -0.5, -3.5), bounds.min);
+        assert_eq!(Point3::new(2.25, 0.5, -0.5), bounds.max);
+    }
+
+    #[test]
+    fn particles_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(8);
+        let mut random = || -> [f32; 3] { [0, 1, 2].map(|_| rng.gen_range(-1.0..1.0)) };
+        let list: Vec<_> = (0..500).map(|_| (random(), 0.05, random())).collect();
+        let particles = Particles::new(cache(&list), 0);
+
+        let mut rng = StdRng::seed_from_u64(9);
+        for _ in 0..200 {
+            let origin = Vec3::random_unit_vector(&mut rng) * 3.0;
+            let target = Vec3::random_in_unit_sphere(&mut rng);
+            let ray = Ray::new(origin, target - origin);
+            let closest = (0..list.len())
+                .filter_map(|i| particles.intersect(i, &ray, 0.001, f64::MAX))
+                .reduce(f64::min);
+            let record = particles.hit(&ray, 0.001, f64::MAX, &mut rng);
+            assert_eq!(closest, record.map(|r| r.t));
+        }
+    }
+
+    #[test]
+    fn particles_load_binary() {
+        let path = std::env::temp_dir().join("raytracing_weekend_particles_load.prtc");
+        let mut bytes = PARTICLE_MAGIC.to_vec();
+        bytes.extend(2u32.to_le_bytes());
+        for value in [
+            1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0, 0.1, 0.2, 1.0, 0.5, 0.0, 0.0, 0.5, 1.0,
+        ] {
+            bytes.extend(value.to_le_bytes());
+        }
+        std::fs::write(&path, &bytes).unwrap();
+        let loaded = ParticleCache::load(&path, 0.01);
+        bytes.pop();
+        std::fs::write(&path, &bytes).unwrap();
+        let truncated = ParticleCache::load(&path, 0.01);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            cache(&[
+                ([1.0, 2.0, 3.0], 0.1, [1.0, 0.5, 0.0]),
+                ([4.0, 5.0, 6.0], 0.2, [0.0, 0.5, 1.0]),
+            ]),
+            loaded.unwrap()
+        );
+        assert!(truncated.is_err());
+
+        // A particle with no radius is refused
+        let mut bytes = PARTICLE_MAGIC.to_vec();
+        bytes.extend(1u32.to_le_bytes());
+        for value in [1.0f32, 2.0, 3.0, 0.0, 1.0, 1.0, 1.0] {
+            bytes.extend(value.to_le_bytes());
+        }
+        let error = parse_binary(&bytes).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+
+    #[test]
+    fn particles_parse_ply() {
+        let ascii = "ply\nformat ascii 1.0\ncomment two points\nelement vertex 2\n\
+                     property float x\nproperty float y\nproperty float z\n\
+                     property float nx\nproperty uchar red\nproperty uchar green\n\
+                     property uchar blue\nelement face 0\nproperty list uchar int vertex_indices\n\
+                     end_header\n1 2 3 0 255 0 51\n-1 -2 -3 0 0 255 0\n";
+        let parsed = parse_ply(ascii.as_bytes(), 0.25).unwrap();
+        assert_eq!(
+            cache(&[
+                ([1.0, 2.0, 3.0], 0.25, [1.0, 0.0, 0.2]),
+                ([-1.0, -2.0, -3.0], 0.25, [0.0, 1.0, 0.0]),
+            ]),
+            parsed
+        );
+
+        let mut binary = b"ply\r\nformat binary_big_endian 1.0\r\nelement vertex 1\r\n\
+                           property double x\r\nproperty double y\r\nproperty double z\r\n\
+                           property float pscale\r\nend_header\r\n"
+            .to_vec();
+        for value in [1.0f64, 2.0, 3.0] {
+            binary.extend(value.to_be_bytes());
+        }
+        binary.extend(0.5f32.to_be_bytes());
+        let parsed = parse_ply(&binary, 0.25).unwrap();
+        assert_eq!(cache(&[([1.0, 2.0, 3.0], 0.5, [1.0, 1.0, 1.0])]), parsed);
+
+        let faces = "ply\nformat ascii 1.0\nelement vertex 0\nelement face 2\nend_header\n";
+        assert!(parse_ply(faces.as_bytes(), 0.25).is_err());
+        let short = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\n\
+                     property float y\nproperty float z\nend_header\n1 2 3\n";
+        assert!(parse_ply(short.as_bytes(), 0.25).is_err());
+
+        // Positions that are not finite and radii that are not positive fail
+        let header = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n\
+                      property float y\nproperty float z\nproperty float radius\nend_header\n";
+        for vertex in ["nan 0 0 1", "0 inf 0 1", "0 0 0 0", "0 0 0 -1", "0 0 0 nan"] {
+            let error = parse_ply(format!("{}{}\n", header, vertex).as_bytes(), 0.25);
+            assert_eq!(
+                io::ErrorKind::InvalidData,
+                error.err().unwrap().kind(),
+                "{}",
+                vertex
+            );
+        }
+
+        // Counts too large to address fail rather than overflow
+        for format in ["ascii", "binary_little_endian"] {
+            let huge = format!(
+                "ply\nformat {} 1.0\nelement vertex {}\nproperty double x\n\
+                 property double y\nproperty double z\nend_header\n",
+                format,
+                usize::MAX / 8
+            );
+            assert!(parse_ply(huge.as_bytes(), 0.25).is_err());
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::f64::consts::PI;
+
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::vector::{Point3, Vec3};
+
+// Thickness given to the bounding boxes of flat shapes
+const FLAT_PADDING: f64 = 1e-4;
+
+// Where a ray meets the plane through the point with the unit normal, if within range
+fn intersect(ray: &Ray, point: Point3, normal: Vec3, t_min: f64, t_max: f64) -> Option<f64> {
+    let denominator = normal.dot(ray.direction);
+    if denominator.abs() < 1e-12 {
+        return None;
+    }
+    let t = normal.dot(point - ray.origin) / denominator;
+    if t > t_min && t < t_max {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+// An infinite plane, with no bounds. Its UVs are distances along two directions in the
+// plane, so textures repeat across it
+pub struct Plane {
+    point: Point3,
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    material: usize,
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vec3, material: usize) -> Plane {
+        let normal = normal.unit_vector();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        Plane {
+            point,
+            normal,
+            tangent,
+            bitangent,
+            material,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let t = intersect(ray, self.point, self.normal, t_min, t_max)?;
+        let p = ray.at(t);
+        let front_face = ray.direction.dot(self.normal) < 0.0;
+        let offset = p - self.point;
+        Some(
+            HitRecord::new(p, self.normal, t, front_face, self.material)
+                .with_uv(offset.dot(self.tangent), offset.dot(self.bitangent)),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+// A disk, or an annulus when the inner radius is above zero. U goes around the center from
+// the tangent of the normal, v from the outer edge inwards
+pub struct Disk {
+    center: Point3,
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    inner_radius: f64,
+    radius: f64,
+    material: usize,
+}
+
+impl Disk {
+    pub fn new(center: Point3, normal: Vec3, radius: f64, material: usize) -> Disk {
+        Disk::annulus(center, normal, 0.0, radius, material)
+    }
+
+    pub fn annulus(
+        center: Point3,
+        normal: Vec3,
+        inner_radius: f64,
+        radius: f64,
+        material: usize,
+    ) -> Disk {
+        let normal = normal.unit_vector();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        Disk {
+            center,
+            normal,
+            tangent,
+            bitangent,
+            inner_radius,
+            radius,
+            material,
+        }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let t = intersect(ray, self.center, self.normal, t_min, t_max)?;
+        let p = ray.at(t);
+        let offset = p - self.center;
+        let distance_squared = offset.length_squared();
+        if distance_squared > self.radius * self.radius
+            || distance_squared < self.inner_radius * self.inner_radius
+        {
+            return None;
+        }
+
+        let phi = offset.dot(self.bitangent).atan2(offset.dot(self.tangent));
+        let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+        let u = phi / (2.0 * PI);
+        let v = (self.radius - distance_squared.sqrt()) / (self.radius - self.inner_radius);
+        let front_face = ray.direction.dot(self.normal) < 0.0;
+        Some(HitRecord::new(p, self.normal, t, front_face, self.material).with_uv(u, v))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // How far the rim reaches along every axis
+        let n = self.normal;
+        let extent = Vec3::new(
+            (1.0 - n.x * n.x).max(0.0).sqrt(),
+            (1.0 - n.y * n.y).max(0.0).sqrt(),
+            (1.0 - n.z * n.z).max(0.0).sqrt(),
+        ) * self.radius;
+        Some(Aabb::new(self.center - extent, self.center + extent).padded(FLAT_PADDING))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+    use rand::prelude::*;
+
+    fn down(x: f64, z: f64) -> Ray {
+        Ray::new(Point3::new(x, 2.0, z), Vec3::new(0.0, -1.0, 0.0))
+    }
+
+    #[test]
+    fn plane_hit() {
+        let plane = Plane::new(Point3::new(0.0, -0.5, 0.0), Vec3::new(0.0, 2.0, 0.0), 3);
+        let rng = &mut StdRng::seed_from_u64(1);
+
+        let record = plane
+            .hit(&down(100.0, -40.0), 0.001, f64::MAX, rng)
+            .unwrap();
+        assert_approx_eq!(2.5, record.t, 1e-12);
+        assert!(record.front_face);
+        assert_eq!(3, record.material);
+        assert_eq!(Vec3::new(0.0, 1.0, 0.0), record.normal);
+        // The UVs are the position in the plane
+        assert_approx_eq!(100.0f64.hypot(40.0), record.u.hypot(record.v), 1e-9);
+
+        let from_below = Ray::new(Point3::new(0.0, -2.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let record = plane.hit(&from_below, 0.001, f64::MAX, rng).unwrap();
+        assert!(!record.front_face);
+        assert_eq!(Vec3::new(0.0, -1.0, 0.0), record.normal);
+
+        let parallel = Ray::new(Point3::zero(), Vec3::new(1.0, 0.0, 0.0));
+        assert!(plane.hit(&parallel, 0.001, f64::MAX, rng).is_none());
+        assert!(plane.hit(&down(0.0, 0.0), 0.001, 2.0, rng).is_none());
+        assert!(plane.bounding_box().is_none());
+    }
+
+    #[test]
+    fn disk_hit() {
+        let disk = Disk::new(Point3::zero(), Vec3::new(0.0, 1.0, 0.0), 1.0, 0);
+        let rng = &mut StdRng::seed_from_u64(1);
+
+        let center = disk.hit(&down(0.0, 0.0), 0.001, f64::MAX, rng).unwrap();
+        assert_approx_eq!(2.0, center.t, 1e-12);
+        assert_approx_eq!(1.0, center.v, 1e-12);
+        let edge = disk.hit(&down(0.0, 0.99), 0.001, f64::MAX, rng).unwrap();
+        assert_approx_eq!(0.01, edge.v, 1e-9);
+        assert!(disk.hit(&down(0.8, 0.8), 0.001, f64::MAX, rng).is_none());
+
+        // U goes once around
+        let us: Vec<f64> = [(0.5, 0.0), (0.0, 0.5), (-0.5, 0.0), (0.0, -0.5)]
+            .iter()
+            .map(|&(x, z)| disk.hit(&down(x, z), 0.001, f64::MAX, rng).unwrap().u)
+            .collect();
+        assert!(us.iter().all(|u| (0.0..1.0).contains(u)));
+        let mut sorted = us.clone();
+        sorted.sort_by(f64::total_cmp);
+        for pair in sorted.windows(2) {
+            assert_approx_eq!(0.25, pair[1] - pair[0], 1e-9);
+        }
+    }
+
+    #[test]
+    fn annulus_hit() {
+        let annulus = Disk::annulus(Point3::zero(), Vec3::new(0.0, 1.0, 0.0), 0.5, 1.0, 0);
+        let rng = &mut StdRng::seed_from_u64(1);
+
+        assert!(annulus.hit(&down(0.0, 0.0), 0.001, f64::MAX, rng).is_none());
+        assert!(annulus.hit(&down(0.3, 0.3), 0.001, f64::MAX, rng).is_none());
+        let record = annulus.hit(&down(0.75, 0.0), 0.001, f64::MAX, rng).unwrap();
+        assert_approx_eq!(0.5, record.v, 1e-12);
+    }
+
+    #[test]
+    fn disk_bounding_box() {
+        let tilted = Vec3::new(0.0, 1.0, 1.0);
+        let disk = Disk::new(Point3::new(1.0, 0.0, 0.0), tilted, 2.0, 0);
+        let bounds = disk.bounding_box().unwrap();
+
+        assert_approx_eq!(-1.0, bounds.min.x, 1e-3);
+        assert_approx_eq!(3.0, bounds.max.x, 1e-3);
+        assert_approx_eq!(2.0f64.sqrt(), bounds.max.y, 1e-3);
+        // Flat disks still get some thickness
+        let flat = Disk::new(Point3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, 0);
+        let bounds = flat.bounding_box().unwrap();
+        assert!(bounds.max.z > bounds.min.z);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(bounds.hit(&ray, 0.0, f64::MAX).is_some());
+    }
+}
@@ -0,0 +1,348 @@
+use std::f64::consts::PI;
+
+use rayon::prelude::*;
+
+use crate::vector::Color;
+
+// Blurs that make up the bloom, each twice as wide as the one before
+pub const BLOOM_SCALES: u32 = 4;
+
+// Lens and camera effects on the linear image, applied after denoising and before the view
+// transform. Bloom and glare spread the light above the threshold, all effects are off at
+// zero
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PostProcess {
+    // Luminance above which pixels bloom and glare
+    pub threshold: f64,
+    // How much of the light above the threshold spreads out as bloom
+    pub bloom: f64,
+    // Standard deviation of the narrowest blur, in pixels
+    pub bloom_radius: f64,
+    // How much of the light above the threshold goes into the starburst
+    pub glare: f64,
+    // Aperture blades, the starburst has a spike for every blade edge
+    pub glare_blades: u32,
+    // Length of the spikes, in pixels
+    pub glare_length: f64,
+    // Darkening towards the corners, by the cos⁴ law
+    pub vignette: f64,
+    // How far red and blue drift apart in the corners, in pixels
+    pub chromatic_aberration: f64,
+}
+
+impl PostProcess {
+    pub fn is_enabled(&self) -> bool {
+        self.bloom > 0.0
+            || self.glare > 0.0
+            || self.vignette > 0.0
+            || self.chromatic_aberration > 0.0
+    }
+
+    // Applies the effects in the order light meets them: the lens splits the colors, the
+    // glow scatters in the lens and the aperture, and the corners get less light
+    pub fn apply(&self, width: u32, height: u32, image: &[Color]) -> Vec<Color> {
+        let image = Image {
+            width: width as usize,
+            height: height as usize,
+            colors: image.to_vec(),
+        };
+        let mut image = if self.chromatic_aberration > 0.0 {
+            chromatic_aberration(&image, self.chromatic_aberration)
+        } else {
+            image
+        };
+
+        if self.bloom > 0.0 || self.glare > 0.0 {
+            let bright = image.map(|c| {
+                let luminance = c.luminance();
+                if luminance > self.threshold {
+                    c * ((luminance - self.threshold) / luminance)
+                } else {
+                    Color::zero()
+                }
+            });
+            if self.bloom > 0.0 {
+                let bloom = bloom(&bright, self.bloom_radius);
+                image.add(&bloom, self.bloom);
+            }
+            if self.glare > 0.0 {
+                let glare = glare(&bright, self.glare_blades, self.glare_length);
+                image.add(&glare, self.glare);
+            }
+        }
+
+        if self.vignette > 0.0 {
+            vignette(&mut image, self.vignette);
+        }
+        image.colors
+    }
+}
+
+struct Image {
+    width: usize,
+    height: usize,
+    colors: Vec<Color>,
+}
+
+impl Image {
+    fn map(&self, f: impl Fn(Color) -> Color + Sync) -> Image {
+        Image {
+            width: self.width,
+            height: self.height,
+            colors: self.colors.par_iter().map(|&c| f(c)).collect(),
+        }
+    }
+
+    fn add(&mut self, other: &Image, scale: f64) {
+        for (color, &other) in self.colors.iter_mut().zip(&other.colors) {
+            *color += other * scale;
+        }
+    }
+
+    fn get(&self, x: i64, y: i64) -> Color {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            Color::zero()
+        } else {
+            self.colors[y as usize * self.width + x as usize]
+        }
+    }
+
+    // Bilinear lookup at a position in pixels, pixel centers are at half pixels. Black
+    // outside the image
+    fn sample(&self, x: f64, y: f64) -> Color {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        (self.get(x0, y0) * (1.0 - fx) + self.get(x0 + 1, y0) * fx) * (1.0 - fy)
+            + (self.get(x0, y0 + 1) * (1.0 - fx) + self.get(x0 + 1, y0 + 1) * fx) * fy
+    }
+
+    // Every pixel from its position relative to the center of the image
+    fn around_center(&self, f: impl Fn(f64, f64) -> Color + Sync) -> Image {
+        let (cx, cy) = (self.width as f64 / 2.0, self.height as f64 / 2.0);
+        let colors = (0..self.colors.len())
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (i % self.width, i / self.width);
+                f(x as f64 + 0.5 - cx, y as f64 + 0.5 - cy)
+            })
+            .collect();
+        Image {
+            width: self.width,
+            height: self.height,
+            colors,
+        }
+    }
+
+    fn half_diagonal(&self) -> f64 {
+        (self.width as f64).hypot(self.height as f64) / 2.0
+    }
+}
+
+// Lateral chromatic aberration: the lens magnifies red a little more than green and blue a
+// little less
+fn chromatic_aberration(image: &Image, pixels: f64) -> Image {
+    let (cx, cy) = (image.width as f64 / 2.0, image.height as f64 / 2.0);
+    let k = pixels / image.half_diagonal();
+    image.around_center(|dx, dy| {
+        let red = image.sample(cx + dx / (1.0 + k), cy + dy / (1.0 + k));
+        let blue = image.sample(cx + dx / (1.0 - k), cy + dy / (1.0 - k));
+        let green = image.sample(cx + dx, cy + dy);
+        Color::new(red.x, green.y, blue.z)
+    })
+}
+
+// Sum of Gaussian blurs at several widths: a tight glow around bright spots with a wide faint
+// haze. Light blurred off the image is lost
+fn bloom(bright: &Image, radius: f64) -> Image {
+    let mut result = bright.map(|_| Color::zero());
+    for scale in 0..BLOOM_SCALES {
+        let sigma = radius * (1 << scale) as f64;
+        result.add(&blur(bright, sigma), 1.0 / BLOOM_SCALES as f64);
+    }
+    result
+}
+
+// Separable Gaussian blur, cut off at three standard deviations
+fn blur(image: &Image, sigma: f64) -> Image {
+    let radius = (3.0 * sigma).ceil().max(1.0) as i64;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f64 = kernel.iter().sum();
+    let kernel: Vec<f64> = kernel.iter().map(|k| k / total).collect();
+
+    let pass = |image: &Image, dx: i64, dy: i64| {
+        let colors = (0..image.colors.len())
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = ((i % image.width) as i64, (i / image.width) as i64);
+                kernel
+                    .iter()
+                    .enumerate()
+                    .fold(Color::zero(), |sum, (j, k)| {
+                        let offset = j as i64 - radius;
+                        sum + image.get(x + offset * dx, y + offset * dy) * *k
+                    })
+            })
+            .collect();
+        Image {
+            width: image.width,
+            height: image.height,
+            colors,
+        }
+    };
+    pass(&pass(image, 1, 0), 0, 1)
+}
+
+// Diffraction spikes of a polygonal aperture, one perpendicular to every blade edge. An even
+// number of blades has its edges in parallel pairs, so their spikes overlap
+fn glare(bright: &Image, blades: u32, length: f64) -> Image {
+    let blades = blades.max(2);
+    let spikes = if blades % 2 == 1 { 2 * blades } else { blades };
+    let steps = length.ceil().max(1.0) as usize;
+    // Fades out quadratically along the spike, every spike gets an equal share of the light
+    let weights: Vec<f64> = (1..=steps)
+        .map(|t| (1.0 - t as f64 / (steps + 1) as f64).powi(2))
+        .collect();
+    let total = weights.iter().sum::<f64>() * spikes as f64;
+    let directions: Vec<(f64, f64)> = (0..spikes)
+        .map(|i| {
+            let angle = PI / 2.0 + 2.0 * PI * i as f64 / spikes as f64;
+            (
+                angle.cos() * length / steps as f64,
+                angle.sin() * length / steps as f64,
+            )
+        })
+        .collect();
+
+    let (cx, cy) = (bright.width as f64 / 2.0, bright.height as f64 / 2.0);
+    bright.around_center(|dx, dy| {
+        let (x, y) = (cx + dx, cy + dy);
+        let mut sum = Color::zero();
+        for &(step_x, step_y) in &directions {
+            for (t, weight) in weights.iter().enumerate() {
+                let t = (t + 1) as f64;
+                sum += bright.sample(x - t * step_x, y - t * step_y) * *weight;
+            }
+        }
+        sum / total
+    })
+}
+
+// Natural vignetting: light reaching the film at an angle falls off with cos⁴. The strength
+// is the squared tangent of that angle in the corners
+fn vignette(image: &mut Image, strength: f64) {
+    let falloff = image.around_center(|dx, dy| {
+        let r2 = (dx * dx + dy * dy) / image.half_diagonal().powi(2);
+        Color::one() / (1.0 + strength * r2).powi(2)
+    });
+    for (color, falloff) in image.colors.iter_mut().zip(falloff.colors) {
+        *color *= falloff.x;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+
+    const WIDTH: u32 = 33;
+    const HEIGHT: u32 = 21;
+
+    fn off() -> PostProcess {
+        PostProcess {
+            threshold: 1.0,
+            bloom: 0.0,
+            bloom_radius: 1.0,
+            glare: 0.0,
+            glare_blades: 6,
+            glare_length: 8.0,
+            vignette: 0.0,
+            chromatic_aberration: 0.0,
+        }
+    }
+
+    // A dim grey image with a bright spot in the middle
+    fn spot(brightness: f64) -> Vec<Color> {
+        let mut image = vec![Color::one() * 0.25; (WIDTH * HEIGHT) as usize];
+        image[(HEIGHT / 2 * WIDTH + WIDTH / 2) as usize] = Color::one() * brightness;
+        image
+    }
+
+    fn at(image: &[Color], x: u32, y: u32) -> Color {
+        image[(y * WIDTH + x) as usize]
+    }
+
+    #[test]
+    fn post_off_is_identity() {
+        let post = off();
+        assert!(!post.is_enabled());
+        assert_eq!(spot(50.0), post.apply(WIDTH, HEIGHT, &spot(50.0)));
+    }
+
+    #[test]
+    fn post_bloom_spreads_bright_light() {
+        let mut post = off();
+        post.bloom = 0.5;
+        let result = post.apply(WIDTH, HEIGHT, &spot(50.0));
+
+        let (cx, cy) = (WIDTH / 2, HEIGHT / 2);
+        assert!(at(&result, cx + 1, cy).x > 0.3);
+        assert!(at(&result, cx + 1, cy).x > at(&result, cx + 4, cy).x);
+        assert!(at(&result, cx + 4, cy).x > 0.25);
+        // The glow adds half the light above the threshold, less what spreads off the image
+        let added: f64 = result.iter().zip(spot(50.0)).map(|(a, b)| (*a - b).x).sum();
+        assert!(added > 0.3 * 49.0 && added <= 0.5 * 49.0, "{}", added);
+
+        // Nothing above the threshold, nothing to bloom
+        assert_eq!(spot(0.9), post.apply(WIDTH, HEIGHT, &spot(0.9)));
+    }
+
+    #[test]
+    fn post_glare_has_spikes() {
+        let mut post = off();
+        post.glare = 1.0;
+        post.glare_blades = 6;
+        let result = post.apply(WIDTH, HEIGHT, &spot(1000.0));
+
+        // Six blades give six spikes, straight up and down among them
+        let (cx, cy) = (WIDTH / 2, HEIGHT / 2);
+        let spike = at(&result, cx, cy + 4).x;
+        let between = at(&result, cx + 4, cy).x;
+        assert!(spike > 1.0, "{}", spike);
+        assert_approx_eq!(0.25, between, 1e-9);
+    }
+
+    #[test]
+    fn post_vignette_darkens_corners() {
+        let mut post = off();
+        post.vignette = 1.0;
+        let image = vec![Color::one(); (WIDTH * HEIGHT) as usize];
+        let result = post.apply(WIDTH, HEIGHT, &image);
+
+        assert_approx_eq!(1.0, at(&result, WIDTH / 2, HEIGHT / 2).x, 1e-2);
+        // cos⁴ of 45 degrees
+        assert_approx_eq!(0.25, at(&result, 0, 0).x, 0.02);
+    }
+
+    #[test]
+    fn post_chromatic_aberration_splits_colors() {
+        let mut post = off();
+        post.chromatic_aberration = 2.0;
+        // A white column right of the center
+        let mut image = vec![Color::zero(); (WIDTH * HEIGHT) as usize];
+        for y in 0..HEIGHT {
+            image[(y * WIDTH + WIDTH / 2 + 8) as usize] = Color::one();
+        }
+        let result = post.apply(WIDTH, HEIGHT, &image);
+
+        // Red moves outwards, blue inwards, green stays
+        let row = HEIGHT / 2;
+        assert_eq!(1.0, at(&result, WIDTH / 2 + 8, row).y);
+        assert!(at(&result, WIDTH / 2 + 9, row).x > 0.0);
+        assert!(at(&result, WIDTH / 2 + 7, row).z > 0.0);
+        assert_eq!(0.0, at(&result, WIDTH / 2 + 7, row).x);
+    }
+}
@@ -0,0 +1,126 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::vector::Color;
+
+// Binary PPM, colors are expected to be display ready and are clipped to [0, 1]
+pub fn write_ppm<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    colors: &[Color],
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+    for color in colors {
+        writer.write_all(&color.to_rgba()[..3])?;
+    }
+    writer.flush()
+}
+
+// Binary PGM or PPM as grey levels from 0 to 1, top row first. PPM colors are reduced to
+// their luminance, taking the stored values as they are
+pub fn read_grey<P: AsRef<Path>>(path: P) -> io::Result<(u32, u32, Vec<f64>)> {
+    parse_grey(&std::fs::read(path)?)
+}
+
+pub fn parse_grey(bytes: &[u8]) -> io::Result<(u32, u32, Vec<f64>)> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    // Four whitespace separated fields, with comments from '#' to the end of the line
+    let mut fields = Vec::new();
+    let mut at = 0;
+    while fields.len() < 4 {
+        while at < bytes.len() && (bytes[at].is_ascii_whitespace() || bytes[at] == b'#') {
+            if bytes[at] == b'#' {
+                while at < bytes.len() && bytes[at] != b'\n' {
+                    at += 1;
+                }
+            } else {
+                at += 1;
+            }
+        }
+        let start = at;
+        while at < bytes.len() && !bytes[at].is_ascii_whitespace() {
+            at += 1;
+        }
+        if start == at {
+            return Err(invalid("truncated header"));
+        }
+        fields.push(String::from_utf8_lossy(&bytes[start..at]).into_owned());
+    }
+    // A single whitespace character ends the header
+    at += 1;
+
+    let channels = match fields[0].as_str() {
+        "P5" => 1,
+        "P6" => 3,
+        _ => return Err(invalid("not a binary PGM or PPM file")),
+    };
+    let number = |field: &str| field.parse::<u32>().map_err(|_| invalid("bad header"));
+    let (width, height, max) = (
+        number(&fields[1])?,
+        number(&fields[2])?,
+        number(&fields[3])?,
+    );
+    if max == 0 || max > 65535 {
+        return Err(invalid("bad maximum value"));
+    }
+    let size = if max < 256 { 1 } else { 2 };
+    let end = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(channels * size))
+        .and_then(|length| length.checked_add(at))
+        .ok_or_else(|| invalid("image too large"))?;
+    let data = bytes
+        .get(at..end)
+        .ok_or_else(|| invalid("truncated pixels"))?;
+    let samples: Vec<f64> = data
+        .chunks_exact(size)
+        .map(|s| s.iter().fold(0u32, |value, &b| value << 8 | b as u32) as f64 / max as f64)
+        .collect();
+    let values = match channels {
+        1 => samples,
+        _ => samples
+            .chunks_exact(3)
+            .map(|rgb| Color::new(rgb[0], rgb[1], rgb[2]).luminance())
+            .collect(),
+    };
+    Ok((width, height, values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ppm_write() {
+        let path = std::env::temp_dir().join("raytracing_weekend_ppm_write.ppm");
+        write_ppm(&path, 2, 1, &[Color::one(), Color::new(0.0, 0.5, 2.0)]).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let header = b"P6\n2 1\n255\n";
+        assert_eq!(header, &bytes[..header.len()]);
+        assert_eq!([255, 255, 255, 0, 127, 255], bytes[header.len()..]);
+    }
+
+    #[test]
+    fn ppm_read_grey() {
+        let mut pgm = b"P5\n# made by hand\n2 2\n255\n".to_vec();
+        pgm.extend([0, 51, 255, 10]);
+        let (width, height, values) = parse_grey(&pgm).unwrap();
+        assert_eq!((2, 2), (width, height));
+        assert_eq!(vec![0.0, 0.2, 1.0, 10.0 / 255.0], values);
+
+        // Sixteen bits, big endian
+        let mut ppm = b"P6 1 1 65535\n".to_vec();
+        ppm.extend([255, 255, 255, 255, 255, 255]);
+        let (_, _, values) = parse_grey(&ppm).unwrap();
+        assert!((values[0] - 1.0).abs() < 1e-6);
+
+        assert!(parse_grey(b"P5\n2 2\n255\n\0").is_err());
+        assert!(parse_grey(b"P3\n1 1\n255\n0 0 0").is_err());
+        assert!(parse_grey(b"P6\n4294967295 4294967295 65535\n\0").is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+pub struct Progress {
+    label: String,
+    total: usize,
+    done: AtomicUsize,
+    start: Instant,
+}
+
+impl Progress {
+    pub fn new(label: String, total: usize) -> Progress {
+        Progress {
+            label,
+            total,
+            done: AtomicUsize::new(0),
+            start: Instant::now(),
+        }
+    }
+
+    // Marks one more tile done and reports on stderr, ending the line after the last one
+    pub fn tick(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        let eta = eta(self.start.elapsed(), done, self.total);
+        eprint!(
+            "\r{}: {}/{} tiles ({:.0}%), ETA {:.1}s   ",
+            self.label,
+            done,
+            self.total,
+            100.0 * done as f64 / self.total as f64,
+            eta.as_secs_f64()
+        );
+        if done == self.total {
+            eprintln!();
+        }
+    }
+
+    // Ends the line of a pass that stopped before its last tile
+    pub fn finish(&self) {
+        let done = self.done.load(Ordering::Relaxed);
+        if done > 0 && done < self.total {
+            eprintln!();
+        }
+    }
+}
+
+// Remaining time assuming the rest of the work goes at the average pace so far
+pub fn eta(elapsed: Duration, done: usize, total: usize) -> Duration {
+    if done == 0 {
+        return Duration::ZERO;
+    }
+    elapsed.mul_f64(total.saturating_sub(done) as f64 / done as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_eta() {
+        assert_eq!(
+            Duration::from_secs(30),
+            eta(Duration::from_secs(10), 25, 100)
+        );
+        assert_eq!(Duration::ZERO, eta(Duration::from_secs(10), 100, 100));
+        assert_eq!(Duration::ZERO, eta(Duration::from_secs(10), 0, 100));
+    }
+}
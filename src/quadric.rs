@@ -0,0 +1,524 @@
+use std::f64::consts::PI;
+
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::{pair_crossings, Crossing, HitRecord, Hittable, Solid, Span};
+use crate::ray::Ray;
+use crate::vector::{Point3, Vec3};
+
+// An orthonormal frame, shapes are described in its coordinates with z along their axis
+#[derive(Debug, Copy, Clone)]
+pub struct Frame {
+    origin: Point3,
+    x: Vec3,
+    y: Vec3,
+    z: Vec3,
+}
+
+impl Frame {
+    pub fn new(origin: Point3, axis: Vec3) -> Frame {
+        let z = axis.unit_vector();
+        let (x, y) = z.orthonormal_basis();
+        Frame { origin, x, y, z }
+    }
+
+    pub fn local_point(&self, p: Point3) -> Point3 {
+        self.local_vector(p - self.origin)
+    }
+
+    pub fn local_vector(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.x), v.dot(self.y), v.dot(self.z))
+    }
+
+    pub fn world_point(&self, p: Point3) -> Point3 {
+        self.origin + self.world_vector(p)
+    }
+
+    pub fn world_vector(&self, v: Vec3) -> Vec3 {
+        self.x * v.x + self.y * v.y + self.z * v.z
+    }
+
+    // World bounds of a box in the frame
+    pub fn bounds(&self, min: Point3, max: Point3) -> Aabb {
+        (0..8)
+            .map(|corner| {
+                let pick =
+                    |bit: usize, min: f64, max: f64| if corner & bit == 0 { min } else { max };
+                let p = self.world_point(Point3::new(
+                    pick(1, min.x, max.x),
+                    pick(2, min.y, max.y),
+                    pick(4, min.z, max.z),
+                ));
+                Aabb::new(p, p)
+            })
+            .reduce(Aabb::surrounding)
+            .unwrap()
+    }
+}
+
+// Angle around the z axis, from 0 to 2π
+fn phi(p: Point3) -> f64 {
+    let phi = p.y.atan2(p.x);
+    if phi < 0.0 {
+        phi + 2.0 * PI
+    } else {
+        phi
+    }
+}
+
+// Roots of a t² + b t + c in increasing order, computed without cancellation
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a == 0.0 {
+        if b == 0.0 {
+            return None;
+        }
+        let t = -c / b;
+        return Some((t, t));
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (t0, t1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+// Surfaces of revolution x² + y² + b z² + c z + d = 0 around the z axis of their frame,
+// between two heights and swept from 0 up to phi max like in PBRT. U goes around the axis
+// up to phi max, v from the bottom to the top. Caps close the ends with disks
+pub struct Quadric {
+    frame: Frame,
+    b: f64,
+    c: f64,
+    d: f64,
+    z_min: f64,
+    z_max: f64,
+    phi_max: f64,
+    capped: bool,
+    material: usize,
+}
+
+impl Quadric {
+    // Radius and height along the axis from the center of the base
+    pub fn cylinder(base: Point3, axis: Vec3, radius: f64, height: f64, material: usize) -> Self {
+        Quadric::new(base, axis, (0.0, 0.0, -radius * radius), height, material)
+    }
+
+    // Narrows from the radius at the base to a point at the height
+    pub fn cone(base: Point3, axis: Vec3, radius: f64, height: f64, material: usize) -> Self {
+        Quadric::truncated_cone(base, axis, radius, 0.0, height, material)
+    }
+
+    // A cone cut off where it has narrowed to the top radius. With a wider top the cone widens
+    // instead, from an apex below the base, and with equal radii it is a cylinder
+    pub fn truncated_cone(
+        base: Point3,
+        axis: Vec3,
+        radius: f64,
+        top_radius: f64,
+        height: f64,
+        material: usize,
+    ) -> Self {
+        if radius == top_radius {
+            return Quadric::cylinder(base, axis, radius, height, material);
+        }
+        // x² + y² = k² (apex - z)², with the apex where the sides meet
+        let apex = height * radius / (radius - top_radius);
+        let k2 = (radius / apex).powi(2);
+        let coefficients = (-k2, 2.0 * k2 * apex, -k2 * apex * apex);
+        Quadric::new(base, axis, coefficients, height, material)
+    }
+
+    // A bowl from its tip at the base to the radius at the height
+    pub fn paraboloid(base: Point3, axis: Vec3, radius: f64, height: f64, material: usize) -> Self {
+        Quadric::new(
+            base,
+            axis,
+            (0.0, -radius * radius / height, 0.0),
+            height,
+            material,
+        )
+    }
+
+    // Hyperboloid of one sheet, centered on the waist and widening to the top radius at half
+    // the height above and below it
+    pub fn hyperboloid(
+        center: Point3,
+        axis: Vec3,
+        waist_radius: f64,
+        top_radius: f64,
+        height: f64,
+        material: usize,
+    ) -> Self {
+        let half = height / 2.0;
+        let b = -(top_radius * top_radius - waist_radius * waist_radius) / (half * half);
+        let base = center - axis.unit_vector() * half;
+        // In the frame of the base: x² + y² = a² + (top² - a²) (z - half)² / half²
+        let coefficients = (
+            b,
+            -2.0 * b * half,
+            b * half * half - waist_radius * waist_radius,
+        );
+        Quadric::new(base, axis, coefficients, height, material)
+    }
+
+    fn new(
+        base: Point3,
+        axis: Vec3,
+        (b, c, d): (f64, f64, f64),
+        height: f64,
+        material: usize,
+    ) -> Self {
+        Quadric {
+            frame: Frame::new(base, axis),
+            b,
+            c,
+            d,
+            z_min: 0.0,
+            z_max: height,
+            phi_max: 2.0 * PI,
+            capped: false,
+            material,
+        }
+    }
+
+    // Sweeps the surface only part of the way around, in degrees
+    pub fn with_phi_max(self, degrees: f64) -> Self {
+        Quadric {
+            phi_max: degrees.clamp(0.0, 360.0).to_radians(),
+            ..self
+        }
+    }
+
+    // Closes the ends with disks
+    pub fn capped(self) -> Self {
+        Quadric {
+            capped: true,
+            ..self
+        }
+    }
+
+    fn radius_squared(&self, z: f64) -> f64 {
+        -(self.b * z * z + self.c * z + self.d)
+    }
+
+    fn in_range(&self, p: Point3) -> bool {
+        p.z >= self.z_min && p.z <= self.z_max && phi(p) <= self.phi_max
+    }
+
+    // Where the ray crosses the side and the caps within the range, in order
+    fn crossings(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<Crossing> {
+        let o = self.frame.local_point(ray.origin);
+        let d = self.frame.local_vector(ray.direction);
+        let mut crossings = Vec::new();
+        let mut add = |t: f64, normal: Vec3, u: f64, v: f64| {
+            if t > t_min && t < t_max {
+                crossings.push(Crossing {
+                    t,
+                    normal: self.frame.world_vector(normal).unit_vector(),
+                    material: self.material,
+                    u,
+                    v,
+                });
+            }
+        };
+
+        let a = d.x * d.x + d.y * d.y + self.b * d.z * d.z;
+        let b = 2.0 * (o.x * d.x + o.y * d.y + self.b * o.z * d.z) + self.c * d.z;
+        let c = o.x * o.x + o.y * o.y + self.b * o.z * o.z + self.c * o.z + self.d;
+        if let Some((t0, t1)) = solve_quadratic(a, b, c) {
+            // A single root from a ray along the side of a cylinder or cone is counted once
+            let roots = if t0 == t1 { &[t0][..] } else { &[t0, t1][..] };
+            for &t in roots {
+                let p = o + d * t;
+                if self.in_range(p) {
+                    let normal = Vec3::new(2.0 * p.x, 2.0 * p.y, 2.0 * self.b * p.z + self.c);
+                    let v = (p.z - self.z_min) / (self.z_max - self.z_min);
+                    add(t, normal, phi(p) / self.phi_max, v);
+                }
+            }
+        }
+
+        // V goes from the rim of the caps to the center
+        if self.capped && d.z != 0.0 {
+            for (z, side) in [(self.z_min, -1.0), (self.z_max, 1.0)] {
+                let t = (z - o.z) / d.z;
+                let p = o + d * t;
+                let radius = self.radius_squared(z).max(0.0).sqrt();
+                let distance = p.x.hypot(p.y);
+                // Nothing to close at the tip of a cone
+                if radius > 0.0 && distance <= radius && phi(p) <= self.phi_max {
+                    let normal = Vec3::new(0.0, 0.0, side);
+                    add(t, normal, phi(p) / self.phi_max, 1.0 - distance / radius);
+                }
+            }
+        }
+        crossings.sort_by(|a, b| a.t.total_cmp(&b.t));
+        crossings
+    }
+}
+
+impl Hittable for Quadric {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let crossings = self.crossings(ray, t_min, t_max);
+        crossings.first().map(|crossing| crossing.record(ray))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // The radius is largest at one of the ends for all of these shapes
+        let radius = self
+            .radius_squared(self.z_min)
+            .max(self.radius_squared(self.z_max))
+            .max(0.0)
+            .sqrt();
+        Some(self.frame.bounds(
+            Point3::new(-radius, -radius, self.z_min),
+            Point3::new(radius, radius, self.z_max),
+        ))
+    }
+}
+
+// Only closed quadrics have an inside: those capped and swept all the way around
+impl Solid for Quadric {
+    fn spans(&self, ray: &Ray, _rng: &mut dyn RngCore) -> Vec<Span> {
+        if !self.capped || self.phi_max < 2.0 * PI {
+            return Vec::new();
+        }
+        pair_crossings(ray, self.crossings(ray, f64::NEG_INFINITY, f64::INFINITY))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+    use rand::prelude::*;
+
+    const UP: Vec3 = Vec3 {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+    };
+
+    fn hit(shape: &Quadric, origin: Point3, direction: Vec3) -> Option<HitRecord> {
+        shape.hit(
+            &Ray::new(origin, direction),
+            0.001,
+            f64::MAX,
+            &mut StdRng::seed_from_u64(1),
+        )
+    }
+
+    fn assert_vectors_equal(expected: Vec3, actual: Vec3) {
+        assert_approx_eq!(expected.x, actual.x, 1e-9);
+        assert_approx_eq!(expected.y, actual.y, 1e-9);
+        assert_approx_eq!(expected.z, actual.z, 1e-9);
+    }
+
+    #[test]
+    fn quadric_solve_quadratic() {
+        let (t0, t1) = solve_quadratic(1.0, -3.0, 2.0).unwrap();
+        assert_eq!((1.0, 2.0), (t0, t1));
+        // Tiny roots next to big ones keep their precision
+        let (t0, _) = solve_quadratic(1.0, -1e8, 1.0).unwrap();
+        assert_approx_eq!(1e-8, t0, 1e-20);
+        assert!(solve_quadratic(1.0, 0.0, 1.0).is_none());
+        assert_eq!(Some((-2.0, -2.0)), solve_quadratic(0.0, 1.0, 2.0));
+    }
+
+    #[test]
+    fn quadric_cylinder() {
+        let cylinder = Quadric::cylinder(Point3::zero(), UP, 0.5, 2.0, 0);
+
+        let record = hit(
+            &cylinder,
+            Point3::new(0.0, 1.0, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        )
+        .unwrap();
+        assert_approx_eq!(4.5, record.t, 1e-9);
+        assert_vectors_equal(Vec3::new(0.0, 0.0, 1.0), record.normal);
+        assert_approx_eq!(0.5, record.v, 1e-9);
+
+        // Open at the ends, rays down the middle pass through
+        assert!(hit(&cylinder, Point3::new(0.0, 5.0, 0.0), -UP).is_none());
+        // Above and below the ends
+        assert!(hit(
+            &cylinder,
+            Point3::new(0.0, 2.5, 5.0),
+            Vec3::new(0.0, 0.0, -1.0)
+        )
+        .is_none());
+        // From inside, the far wall faces away
+        let inside = hit(
+            &cylinder,
+            Point3::new(0.0, 1.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+        )
+        .unwrap();
+        assert!(!inside.front_face);
+        assert_vectors_equal(Vec3::new(-1.0, 0.0, 0.0), inside.normal);
+    }
+
+    #[test]
+    fn quadric_capped_cylinder() {
+        let cylinder = Quadric::cylinder(Point3::zero(), UP, 0.5, 2.0, 0).capped();
+
+        let top = hit(&cylinder, Point3::new(0.1, 5.0, 0.0), -UP).unwrap();
+        assert_approx_eq!(3.0, top.t, 1e-9);
+        assert_vectors_equal(UP, top.normal);
+        assert_approx_eq!(0.8, top.v, 1e-9);
+        let bottom = hit(&cylinder, Point3::new(0.1, -5.0, 0.0), UP).unwrap();
+        assert_approx_eq!(5.0, bottom.t, 1e-9);
+        assert_vectors_equal(-UP, bottom.normal);
+    }
+
+    #[test]
+    fn quadric_phi_max() {
+        let half = Quadric::cylinder(Point3::zero(), UP, 0.5, 2.0, 0).with_phi_max(180.0);
+        let full = Quadric::cylinder(Point3::zero(), UP, 0.5, 2.0, 0);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        // Half of the rays through the axis see the front wall, the rest hit the far wall
+        let (mut front, mut hits) = (0, 0);
+        for _ in 0..200 {
+            let angle = rng.gen_range(0.0..2.0 * PI);
+            let origin = Point3::new(angle.cos() * 3.0, 1.0, angle.sin() * 3.0);
+            let direction = Point3::new(0.0, 1.0, 0.0) - origin;
+            let record = hit(&half, origin, direction).unwrap();
+            let first = hit(&full, origin, direction).unwrap();
+            hits += 1;
+            if (record.t - first.t).abs() < 1e-9 {
+                front += 1;
+                assert!((0.0..=1.0).contains(&record.u));
+            }
+        }
+        assert!((70..130).contains(&front), "{} of {}", front, hits);
+    }
+
+    #[test]
+    fn quadric_cone() {
+        let cone = Quadric::cone(Point3::zero(), UP, 1.0, 1.0, 0);
+
+        // Halfway up the radius is halved, the side slopes at 45 degrees
+        let record = hit(&cone, Point3::new(0.0, 0.5, 5.0), Vec3::new(0.0, 0.0, -1.0)).unwrap();
+        assert_approx_eq!(4.5, record.t, 1e-9);
+        let slope = 0.5f64.sqrt();
+        assert_vectors_equal(Vec3::new(0.0, slope, slope), record.normal);
+
+        // A capped cone has no top cap at its tip
+        let capped = Quadric::cone(Point3::zero(), UP, 1.0, 1.0, 0).capped();
+        let tip = hit(&capped, Point3::new(0.0, 5.0, 0.0), -UP).unwrap();
+        assert_approx_eq!(4.0, tip.t, 1e-9);
+        assert!(tip.v.is_finite() && tip.u.is_finite());
+
+        let truncated = Quadric::truncated_cone(Point3::zero(), UP, 1.0, 0.5, 0.5, 0).capped();
+        let top = hit(&truncated, Point3::new(0.45, 5.0, 0.0), -UP).unwrap();
+        assert_approx_eq!(4.5, top.t, 1e-9);
+        assert!(hit(
+            &truncated,
+            Point3::new(0.55, 0.6, 5.0),
+            Vec3::new(0.0, 0.0, -1.0)
+        )
+        .is_none());
+
+        // Widening from the base, the side slopes outwards and faces down
+        let widening = Quadric::truncated_cone(Point3::zero(), UP, 0.5, 1.0, 0.5, 0);
+        let side = hit(
+            &widening,
+            Point3::new(0.0, 0.25, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        )
+        .unwrap();
+        assert_approx_eq!(4.25, side.t, 1e-9);
+        assert_vectors_equal(Vec3::new(0.0, -slope, slope), side.normal);
+        // Equal radii make a cylinder
+        let straight = Quadric::truncated_cone(Point3::zero(), UP, 0.5, 0.5, 2.0, 0);
+        let cylinder = Quadric::cylinder(Point3::zero(), UP, 0.5, 2.0, 0);
+        let origin = Point3::new(0.1, 1.0, 5.0);
+        let direction = Vec3::new(0.0, 0.1, -1.0);
+        assert_eq!(
+            hit(&cylinder, origin, direction).unwrap().t,
+            hit(&straight, origin, direction).unwrap().t
+        );
+    }
+
+    #[test]
+    fn quadric_paraboloid_and_hyperboloid() {
+        let bowl = Quadric::paraboloid(Point3::zero(), UP, 1.0, 1.0, 0);
+        // x² = z, so at x = 0.5 the surface is at a height of 0.25
+        let record = hit(&bowl, Point3::new(0.5, 5.0, 0.0), -UP).unwrap();
+        assert_approx_eq!(4.75, record.t, 1e-9);
+        // The inside of the bowl faces up
+        assert!(!record.front_face);
+
+        let tower = Quadric::hyperboloid(Point3::zero(), UP, 0.5, 1.0, 2.0, 0);
+        let waist = hit(
+            &tower,
+            Point3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        )
+        .unwrap();
+        assert_approx_eq!(4.5, waist.t, 1e-9);
+        assert_approx_eq!(0.5, waist.v, 1e-9);
+        let top = hit(
+            &tower,
+            Point3::new(0.0, 0.999, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        )
+        .unwrap();
+        assert_approx_eq!(4.0, top.t, 1e-2);
+    }
+
+    #[test]
+    fn quadric_bounds_contain_surface() {
+        let axis = Vec3::new(1.0, 2.0, -0.5);
+        let base = Point3::new(0.3, -1.0, 2.0);
+        let shapes = [
+            Quadric::cylinder(base, axis, 0.5, 2.0, 0).capped(),
+            Quadric::truncated_cone(base, axis, 1.0, 0.3, 1.0, 0).capped(),
+            Quadric::paraboloid(base, axis, 1.0, 2.0, 0),
+            Quadric::hyperboloid(base, axis, 0.4, 1.0, 2.0, 0),
+        ];
+        let mut rng = StdRng::seed_from_u64(2);
+        for shape in &shapes {
+            let bounds = shape.bounding_box().unwrap();
+            for _ in 0..100 {
+                let origin = Point3::random(-5.0..5.0, &mut rng);
+                let target = base + Vec3::random(-1.0..1.0, &mut rng);
+                if let Some(record) = hit(shape, origin, target - origin) {
+                    let p = record.point;
+                    let inside = |v: f64, min: f64, max: f64| v >= min - 1e-9 && v <= max + 1e-9;
+                    assert!(inside(p.x, bounds.min.x, bounds.max.x));
+                    assert!(inside(p.y, bounds.min.y, bounds.max.y));
+                    assert!(inside(p.z, bounds.min.z, bounds.max.z));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn quadric_spans() {
+        let cylinder = Quadric::cylinder(Point3::zero(), UP, 0.5, 2.0, 0).capped();
+        let rng = &mut StdRng::seed_from_u64(1);
+
+        // In through the top cap and out through the side
+        let ray = Ray::new(Point3::new(0.0, 3.0, 0.0), Vec3::new(0.2, -1.0, 0.0));
+        let spans = cylinder.spans(&ray, rng);
+        assert_eq!(1, spans.len());
+        assert_approx_eq!(1.0, spans[0].enter.t, 1e-9);
+        assert_vectors_equal(UP, spans[0].enter.normal);
+        assert_approx_eq!(2.5, spans[0].exit.t, 1e-9);
+        assert_vectors_equal(Vec3::new(1.0, 0.0, 0.0), spans[0].exit.normal);
+
+        // Open quadrics have no inside
+        let open = Quadric::cylinder(Point3::zero(), UP, 0.5, 2.0, 0);
+        assert!(open.spans(&ray, rng).is_empty());
+        let half = Quadric::cylinder(Point3::zero(), UP, 0.5, 2.0, 0)
+            .with_phi_max(180.0)
+            .capped();
+        assert!(half.spans(&ray, rng).is_empty());
+    }
+}
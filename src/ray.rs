@@ -1,7 +1,10 @@
-use crate::hittable::Hittable;
+use rand::RngCore;
+
+use crate::hittable::{HitRecord, Hittable};
 use crate::hittable_list::HittableList;
+use crate::sampler::Sampler;
+use crate::spectrum::{SampledSpectrum, SampledWavelengths};
 use crate::vector::*;
-use rand::prelude::ThreadRng;
 
 const T_MIN: f64 = 0.0001;
 const T_MAX: f64 = f64::MAX;
@@ -31,27 +34,99 @@ impl Ray {
         self.origin + self.direction * t
     }
 
-    pub fn color(&self, world: &HittableList, rng: &mut ThreadRng, depth: i32) -> Color {
+    pub fn hit(&self, world: &HittableList, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        world.hit(self, T_MIN, T_MAX, rng)
+    }
+
+    pub fn color(
+        &self,
+        world: &HittableList,
+        sampler: &mut dyn Sampler,
+        rng: &mut dyn RngCore,
+        depth: i32,
+    ) -> Color {
         if depth <= 0 {
             return Color::new(0.0, 0.0, 0.0);
         }
 
-        let t = world.hit(self, T_MIN, T_MAX);
-        match t {
-            Some(record) => {
-                let target = record.point + Vec3::random_in_hemisphere(record.normal);
-                //let target = record.point + record.normal + Vec3::random_unit_vector(rng);
-                0.5 * Ray::new(record.point, target - record.point).color(world, rng, depth - 1)
-                //(COLOR_WHITE + record.normal.unit_vector())
+        match self.hit(world, rng) {
+            Some(record) => self.shade(&record, world, sampler, rng, depth),
+            None => self.background(world),
+        }
+    }
+
+    // Light leaving the hit towards the origin of the ray
+    pub fn shade(
+        &self,
+        record: &HitRecord,
+        world: &HittableList,
+        sampler: &mut dyn Sampler,
+        rng: &mut dyn RngCore,
+        depth: i32,
+    ) -> Color {
+        match world
+            .material(record.material)
+            .scatter(self, record, sampler, rng)
+        {
+            Some(scatter) => {
+                scatter.attenuation * scatter.ray.color(world, sampler, rng, depth - 1)
             }
+            None => Color::zero(),
+        }
+    }
 
-            None => {
-                let unit_direction = self.direction.unit_vector();
-                let t = 0.5 * (unit_direction.y + 1.0);
-                (1.0 - t) * COLOR_WHITE + t * COLOR_SKYBLUE
+    // Spectral version of color, the light of the path at its wavelengths
+    pub fn spectral_color(
+        &self,
+        world: &HittableList,
+        wavelengths: &mut SampledWavelengths,
+        sampler: &mut dyn Sampler,
+        rng: &mut dyn RngCore,
+        depth: i32,
+    ) -> SampledSpectrum {
+        if depth <= 0 {
+            return SampledSpectrum::zero();
+        }
+
+        match self.hit(world, rng) {
+            Some(record) => self.spectral_shade(&record, world, wavelengths, sampler, rng, depth),
+            None => world.spectrum(self.background(world), wavelengths),
+        }
+    }
+
+    pub fn spectral_shade(
+        &self,
+        record: &HitRecord,
+        world: &HittableList,
+        wavelengths: &mut SampledWavelengths,
+        sampler: &mut dyn Sampler,
+        rng: &mut dyn RngCore,
+        depth: i32,
+    ) -> SampledSpectrum {
+        match world.material(record.material).scatter_spectral(
+            self,
+            record,
+            wavelengths,
+            sampler,
+            rng,
+        ) {
+            Some(scatter) => {
+                let attenuation = world.spectrum(scatter.attenuation, wavelengths);
+                let light = scatter
+                    .ray
+                    .spectral_color(world, wavelengths, sampler, rng, depth - 1);
+                attenuation * light
             }
+            None => SampledSpectrum::zero(),
         }
     }
+
+    // The sky, seen by rays that hit nothing
+    pub fn background(&self, world: &HittableList) -> Color {
+        let unit_direction = self.direction.unit_vector();
+        let t = 0.5 * (unit_direction.y + 1.0);
+        world.color((1.0 - t) * COLOR_WHITE + t * COLOR_SKYBLUE)
+    }
 }
 
 #[cfg(test)]
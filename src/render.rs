@@ -1,43 +1,618 @@
-use rand::prelude::*;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
+
+use crate::aov::{trace, trace_spectral, AovSample};
 use crate::camera::Camera;
+use crate::film::{Film, FilmPixel, Splats};
+use crate::filter::Filter;
 use crate::hittable_list::HittableList;
+use crate::progress::Progress;
+use crate::sampler::{pixel_rng, Sampler, SamplerKind};
+use crate::spectrum::SampledWavelengths;
+use crate::tile::{tiles, Tile, TileOrder};
 use crate::vector::Color;
-const MAX_DEPTH: i32 = 50;
 
+pub const MAX_DEPTH: i32 = 50;
+
+#[derive(Debug, Copy, Clone)]
+pub struct RenderConfig {
+    pub image_width: u32,
+    pub image_height: u32,
+    // The maximum when sampling adaptively or against a time limit
+    pub samples: u32,
+    pub sampler: SamplerKind,
+    pub seed: u64,
+    pub max_depth: i32,
+    // Pixels stop sampling once their relative error drops below the threshold
+    pub noise_threshold: Option<f64>,
+    // Samples every pixel gets before its error is trusted, also the size of later passes
+    pub min_samples: u32,
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    // Passes stop being added once the time is up
+    pub time_limit: Option<Duration>,
+    // Traces wavelengths instead of RGB
+    pub spectral: bool,
+    pub filter: Filter,
+}
+
+// Called from the render threads for every finished tile, while holding the film. Gets the
+// pixels that changed, the tile and the neighbours its samples splatted into
+pub type TileCallback<'a> = &'a (dyn Fn(&Tile, &Film) + Sync);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StopReason {
+    // Every pixel has all its samples
+    SampleLimit,
+    // Every pixel dropped below the noise threshold
+    Converged,
+    TimeLimit,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::SampleLimit => write!(f, "sample limit reached"),
+            StopReason::Converged => write!(f, "noise threshold reached"),
+            StopReason::TimeLimit => write!(f, "time limit reached"),
+        }
+    }
+}
+
+// What a render achieved, printed when it is done and saved next to the image
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderReport {
+    pub elapsed: Duration,
+    pub passes: u32,
+    pub stop_reason: StopReason,
+    pub min_samples: u32,
+    pub mean_samples: f64,
+    pub max_samples: u32,
+    // Over the pixels with enough samples to tell
+    pub mean_relative_error: f64,
+}
+
+impl RenderReport {
+    pub fn new(film: &Film, elapsed: Duration, passes: u32, stop_reason: StopReason) -> Self {
+        let samples = film.pixels.iter().map(|p| p.samples);
+        let errors: Vec<f64> = film
+            .pixels
+            .iter()
+            .map(|p| p.relative_error())
+            .filter(|e| e.is_finite())
+            .collect();
+        RenderReport {
+            elapsed,
+            passes,
+            stop_reason,
+            min_samples: samples.clone().min().unwrap_or(0),
+            mean_samples: film.total_samples() as f64 / film.pixels.len().max(1) as f64,
+            max_samples: samples.max().unwrap_or(0),
+            mean_relative_error: errors.iter().sum::<f64>() / errors.len().max(1) as f64,
+        }
+    }
+}
+
+impl fmt::Display for RenderReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Rendered {} passes in {:.2}s, {}",
+            self.passes,
+            self.elapsed.as_secs_f64(),
+            self.stop_reason
+        )?;
+        writeln!(
+            f,
+            "Samples per pixel: min {}, mean {:.1}, max {}",
+            self.min_samples, self.mean_samples, self.max_samples
+        )?;
+        writeln!(f, "Mean relative error: {:.4}", self.mean_relative_error)
+    }
+}
+
+// Continues sampling the given film, a new one or one resumed from a checkpoint. Pixels pick
+// up at their own sample count, so resuming gives the same film as an uninterrupted render
 pub fn render(
     camera: &Camera,
     world: &HittableList,
-    image_width: u32,
-    image_height: u32,
+    config: &RenderConfig,
+    film: Film,
+    on_tile: TileCallback,
+) -> (Film, RenderReport) {
+    let film = Mutex::new(film);
+    let report = render_passes(config, &film, Instant::now, |samples, label| {
+        render_pass(camera, world, config, &film, samples, on_tile, label)
+    });
+    (film.into_inner().unwrap(), report)
+}
+
+// Whether the pixel needs more samples
+pub fn is_active(config: &RenderConfig, pixel: &FilmPixel) -> bool {
+    pixel.samples < config.samples
+        && match config.noise_threshold {
+            Some(threshold) => {
+                pixel.samples < config.min_samples || pixel.relative_error() > threshold
+            }
+            None => true,
+        }
+}
+
+// Runs passes over the film until no pixel is active or the time is up. With a noise
+// threshold every pass adds `min_samples`, otherwise a single pass adds them all. With a time
+// limit the passes double the samples, starting from `min_samples` rounded up to a power of
+// two, and the render only stops between them: every pixel ends on a power of two, where the
+// Sobol sampler is evenly spread and the stratified one has filled the grid of each pass. A
+// pass that would not finish in time, going by the one before, is not started. The pass gets
+// the samples to add and a label for its progress, the clock tells the time
+pub fn render_passes(
+    config: &RenderConfig,
+    film: &Mutex<Film>,
+    clock: impl Fn() -> Instant,
+    mut render_pass: impl FnMut(u32, String),
+) -> RenderReport {
+    {
+        let film = film.lock().unwrap();
+        assert_eq!(
+            (config.image_width, config.image_height),
+            (film.width, film.height),
+            "film size does not match the render"
+        );
+    }
+    let start = clock();
+    let deadline = config.time_limit.map(|limit| start + limit);
+    let progressive = config.noise_threshold.is_some() || deadline.is_some();
+    let mut pass_samples = first_pass_samples(config);
+
+    let mut passes = 0;
+    let mut pass_time = Duration::ZERO;
+    let stop_reason = loop {
+        let (active, converged) = {
+            let pixels = &film.lock().unwrap().pixels;
+            let active = pixels.iter().any(|p| is_active(config, p));
+            let converged = config.noise_threshold.is_some()
+                && pixels.iter().any(|p| p.samples < config.samples);
+            (active, converged)
+        };
+        if !active {
+            break if converged {
+                StopReason::Converged
+            } else {
+                StopReason::SampleLimit
+            };
+        }
+        let now = clock();
+        // Twice the samples take about twice as long as the last pass
+        if deadline.is_some_and(|deadline| now + pass_time * 2 > deadline) {
+            break StopReason::TimeLimit;
+        }
+
+        passes += 1;
+        let label = if progressive {
+            format!("Pass {}", passes)
+        } else {
+            String::from("Rendering")
+        };
+        render_pass(pass_samples, label);
+        pass_time = clock() - now;
+        if deadline.is_some() && passes > 1 {
+            pass_samples = pass_samples.saturating_mul(2);
+        }
+    };
+
+    let film = film.lock().unwrap();
+    RenderReport::new(&film, clock() - start, passes, stop_reason)
+}
+
+fn first_pass_samples(config: &RenderConfig) -> u32 {
+    if config.time_limit.is_some() {
+        config.min_samples.max(1).next_power_of_two()
+    } else if config.noise_threshold.is_some() {
+        config.min_samples.max(1)
+    } else {
+        config.samples
+    }
+}
+
+// Adds up to `samples` more samples to every pixel that is still active
+fn render_pass(
+    camera: &Camera,
+    world: &HittableList,
+    config: &RenderConfig,
+    film: &Mutex<Film>,
     samples: u32,
-    screen: &mut [u8],
+    on_tile: TileCallback,
+    label: String,
 ) {
-    let start = std::time::Instant::now();
+    let tiles = tiles(
+        config.image_width,
+        config.image_height,
+        config.tile_size,
+        config.tile_order,
+    );
+    let progress = Progress::new(label, tiles.len());
+    let next_tile = AtomicUsize::new(0);
 
-    let colors: Vec<Color> = (0..image_height * image_width)
+    // Every worker takes the next tile in line, so tiles finish roughly in render order
+    (0..rayon::current_num_threads())
         .into_par_iter()
-        .map(|index| {
-            let mut rng = thread_rng();
-            let x = index % image_width;
-            let y = index / image_width;
-            let mut color: Color = Color::zero();
-            for _sample in 0..samples {
-                let rand_x: f64 = rng.gen();
-                let rand_y: f64 = rng.gen();
-                let u = (rand_x + x as f64) / (image_width - 1) as f64;
-                let v = 1.0 - (rand_y + y as f64) / (image_height - 1) as f64;
-                let ray = camera.get_ray(u, v);
-                color += ray.color(world, &mut rng, MAX_DEPTH);
+        .for_each(|_| {
+            while let Some(tile) = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                let mut pixels = film.lock().unwrap().tile_pixels(tile);
+                let splats = render_tile(camera, world, config, tile, &mut pixels, samples);
+                let mut film = film.lock().unwrap();
+                film.set_tile_pixels(tile, &pixels);
+                film.add_splats(&splats);
+                on_tile(&splats.region, &film);
+                drop(film);
+                progress.tick();
+            }
+        });
+    progress.finish();
+}
+
+// Adds up to `samples` more samples to the active pixels of the tile, returns their splats
+// through the filter. Every sample draws its random numbers from the seed, its pixel and its
+// index only, so the result is the same for any thread count, tiling, number of passes or
+// machine that renders it
+pub fn render_tile(
+    camera: &Camera,
+    world: &HittableList,
+    config: &RenderConfig,
+    tile: &Tile,
+    pixels: &mut [FilmPixel],
+    samples: u32,
+) -> Splats {
+    let region = config
+        .filter
+        .region(tile, config.image_width, config.image_height);
+    let coordinates: Vec<(u32, u32)> = tile.pixels().collect();
+    pixels
+        .par_iter_mut()
+        .zip(coordinates)
+        .filter(|(pixel, _)| is_active(config, pixel))
+        .fold(
+            // Samplers start over at every sample, so each thread keeps one for the whole tile
+            || {
+                (
+                    Splats::new(region),
+                    config
+                        .sampler
+                        .create(first_pass_samples(config), config.samples, config.seed),
+                )
+            },
+            |(mut splats, mut sampler), (pixel, (x, y))| {
+                let first = pixel.samples;
+                let last = (first + samples).min(config.samples);
+                for sample in first..last {
+                    let (position, color, aovs) =
+                        sample_pixel(camera, world, config, sampler.as_mut(), (x, y), sample);
+                    pixel.add_sample(color);
+                    pixel.aovs.add_sample(&aovs);
+                    splat(&mut splats, &config.filter, position, color);
+                }
+                (splats, sampler)
+            },
+        )
+        .map(|(splats, _)| splats)
+        .reduce(|| Splats::new(region), Splats::merge)
+}
+
+// Adds the sample at the film position to the pixels around it
+fn splat(splats: &mut Splats, filter: &Filter, (x, y): (f64, f64), color: Color) {
+    let reach = filter.reach() as i64;
+    let (column, row) = (x.floor() as i64, y.floor() as i64);
+    for py in row - reach..=row + reach {
+        for px in column - reach..=column + reach {
+            let weight = filter.weight(x - (px as f64 + 0.5), y - (py as f64 + 0.5));
+            if weight != 0.0 {
+                splats.add(px, py, color, weight);
+            }
+        }
+    }
+}
+
+// Traces one sample of the pixel, returns where on the film it landed, in pixels, with its
+// color and AOVs
+fn sample_pixel(
+    camera: &Camera,
+    world: &HittableList,
+    config: &RenderConfig,
+    sampler: &mut dyn Sampler,
+    (x, y): (u32, u32),
+    sample: u32,
+) -> ((f64, f64), Color, AovSample) {
+    sampler.start_pixel_sample(x, y, sample);
+    let mut rng = pixel_rng(config.seed, x, y, sample);
+
+    let (rand_x, rand_y) = sampler.get_pixel_2d();
+    let u = (rand_x + x as f64) / (config.image_width - 1) as f64;
+    let v = 1.0 - (rand_y + y as f64) / (config.image_height - 1) as f64;
+    let ray = camera.get_ray(u, v, sampler.get_2d());
+    let (color, aovs) = if config.spectral {
+        let mut wavelengths = SampledWavelengths::sample_uniform(sampler.get_2d().0);
+        let depth = config.max_depth;
+        trace_spectral(&ray, world, &mut wavelengths, sampler, &mut rng, depth)
+    } else {
+        trace(&ray, world, sampler, &mut rng, config.max_depth)
+    };
+    ((x as f64 + rand_x, y as f64 + rand_y), color, aovs)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::aabb::Aabb;
+    use crate::filter::FilterKind;
+    use crate::vector::Point3;
+    use crate::volume::{HeterogeneousMedium, VoxelGrid};
+
+    pub fn config(samples: u32, sampler: SamplerKind, seed: u64, max_depth: i32) -> RenderConfig {
+        RenderConfig {
+            image_width: 16,
+            image_height: 9,
+            samples,
+            sampler,
+            seed,
+            max_depth,
+            noise_threshold: None,
+            min_samples: 16,
+            tile_size: 4,
+            tile_order: TileOrder::Spiral,
+            time_limit: None,
+            spectral: false,
+            filter: Filter::new(FilterKind::Box),
+        }
+    }
+
+    pub fn render_new(camera: &Camera, world: &HittableList, config: &RenderConfig) -> Film {
+        let film = Film::new(config.image_width, config.image_height);
+        render(camera, world, config, film, &|_, _| {}).0
+    }
+
+    pub fn foggy_world() -> HittableList {
+        let mut world = HittableList::new();
+        let bounds = Aabb::new(Point3::new(-1.0, -0.5, -2.0), Point3::new(1.0, 0.5, -0.5));
+        let grid = VoxelGrid::new(
+            2,
+            2,
+            2,
+            bounds,
+            vec![0.0, 1.0, 2.0, 3.0, 3.0, 2.0, 1.0, 0.0],
+        );
+        world.add(Box::new(HeterogeneousMedium::new(grid, 1.0, 0)));
+        world
+    }
+
+    fn render_with_threads(threads: usize, sampler: SamplerKind, seed: u64) -> Vec<Color> {
+        let camera = Camera::new(0.1);
+        let world = foggy_world();
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(|| render_new(&camera, &world, &config(4, sampler, seed, 8)).colors())
+    }
+
+    #[test]
+    fn render_is_deterministic_across_thread_counts() {
+        for sampler in [SamplerKind::Independent, SamplerKind::Stratified] {
+            assert_eq!(
+                render_with_threads(1, sampler, 42),
+                render_with_threads(4, sampler, 42)
+            );
+        }
+    }
+
+    #[test]
+    fn render_depends_on_seed() {
+        assert_ne!(
+            render_with_threads(2, SamplerKind::Sobol, 1),
+            render_with_threads(2, SamplerKind::Sobol, 2)
+        );
+    }
+
+    #[test]
+    fn render_adaptive_concentrates_samples() {
+        let camera = Camera::new(0.0);
+        let world = HittableList::new();
+        let mut config = config(256, SamplerKind::Sobol, 0, 8);
+        config.noise_threshold = Some(0.02);
+
+        let film = render_new(&camera, &world, &config);
+        let samples: Vec<u32> = film.pixels.iter().map(|p| p.samples).collect();
+
+        assert!(samples.iter().all(|&n| (16..=256).contains(&n)));
+        // The top row only sees the sky and converges at once, the ground stays noisy
+        assert_eq!(16, samples[0]);
+        assert!(samples[samples.len() - 1] > 16);
+    }
+
+    #[test]
+    fn render_adaptive_matches_fixed_when_nothing_converges() {
+        let camera = Camera::new(0.0);
+        let world = foggy_world();
+        let fixed = config(32, SamplerKind::Halton, 3, 8);
+        let mut adaptive = fixed;
+        adaptive.noise_threshold = Some(0.0);
+
+        assert_eq!(
+            render_new(&camera, &world, &fixed).colors(),
+            render_new(&camera, &world, &adaptive).colors()
+        );
+    }
+
+    #[test]
+    fn render_spectral_matches_rgb() {
+        let camera = Camera::new(0.0);
+        let world = HittableList::new();
+        let rgb = config(64, SamplerKind::Sobol, 0, 8);
+        let mut spectral = rgb;
+        spectral.spectral = true;
+        let mean = |config: &RenderConfig| {
+            let colors = render_new(&camera, &world, config).colors();
+            colors.iter().fold(Color::zero(), |sum, &c| sum + c) / colors.len() as f64
+        };
+
+        // Grey objects under a sky of smooth spectra look the same either way
+        let (rgb, spectral) = (mean(&rgb), mean(&spectral));
+        assert!((rgb - spectral).length() < 0.02, "{} {}", rgb, spectral);
+    }
+
+    #[test]
+    fn render_reports_every_tile_once() {
+        let camera = Camera::new(0.0);
+        let world = HittableList::new();
+        let mut config = config(2, SamplerKind::Sobol, 0, 4);
+        config.tile_order = TileOrder::Hilbert;
+        let covered = Mutex::new(vec![0; 16 * 9]);
+
+        let film = Film::new(16, 9);
+        let (film, _) = render(&camera, &world, &config, film, &|tile, film| {
+            let mut covered = covered.lock().unwrap();
+            for ((x, y), pixel) in tile.pixels().zip(film.tile_pixels(tile)) {
+                assert_eq!(2, pixel.samples);
+                covered[(y * 16 + x) as usize] += 1;
+            }
+        });
+
+        assert!(covered.into_inner().unwrap().iter().all(|&c| c == 1));
+        assert!(film.pixels.iter().all(|p| p.samples == 2));
+    }
+
+    #[test]
+    fn render_is_independent_of_tiling() {
+        let camera = Camera::new(0.0);
+        let world = foggy_world();
+        for kind in [FilterKind::Box, FilterKind::Lanczos] {
+            let mut spiral = config(4, SamplerKind::Stratified, 5, 8);
+            spiral.filter = Filter::new(kind);
+            let mut hilbert = spiral;
+            hilbert.tile_order = TileOrder::Hilbert;
+            hilbert.tile_size = 3;
+
+            assert_eq!(
+                render_new(&camera, &world, &spiral).colors(),
+                render_new(&camera, &world, &hilbert).colors()
+            );
+        }
+    }
+
+    #[test]
+    fn render_filters_splat_into_neighbours() {
+        let camera = Camera::new(0.0);
+        let world = foggy_world();
+        let render_with = |kind| {
+            let mut config = config(4, SamplerKind::Independent, 7, 8);
+            config.filter = Filter::new(kind);
+            render_new(&camera, &world, &config)
+        };
+        let roughness = |colors: &[Color]| {
+            colors
+                .windows(2)
+                .map(|pair| (pair[0] - pair[1]).length_squared())
+                .sum::<f64>()
+        };
+
+        // One pixel wide, the box is the mean of the own samples of every pixel
+        let boxed = render_with(FilterKind::Box);
+        for (color, pixel) in boxed.colors().iter().zip(&boxed.pixels) {
+            assert!((*color - pixel.color()).length() < 1e-9);
+        }
+        let gaussian = render_with(FilterKind::Gaussian);
+        assert_eq!(boxed.pixels, gaussian.pixels);
+        assert!(roughness(&gaussian.colors()) < roughness(&boxed.colors()));
+    }
+
+    // Keeps the film as it was after the given number of tiles, like a checkpoint taken
+    // just before the render got killed
+    fn interrupted_render(config: &RenderConfig, world: &HittableList, tiles: usize) -> Film {
+        let camera = Camera::new(0.0);
+        let finished = AtomicUsize::new(0);
+        let snapshot = Mutex::new(None);
+        let film = Film::new(config.image_width, config.image_height);
+        render(&camera, world, config, film, &|_, film| {
+            if finished.fetch_add(1, Ordering::Relaxed) + 1 == tiles {
+                *snapshot.lock().unwrap() = Some(film.clone());
             }
-            (color / samples as f64).sqrt()
-        })
-        .collect();
+        });
+        snapshot.into_inner().unwrap().unwrap()
+    }
+
+    #[test]
+    fn render_resume_matches_uninterrupted() {
+        let camera = Camera::new(0.0);
+        let world = foggy_world();
+        let mut fixed = config(8, SamplerKind::Sobol, 11, 8);
+        fixed.filter = Filter::new(FilterKind::Tent);
+        let mut adaptive = config(64, SamplerKind::Halton, 11, 8);
+        adaptive.noise_threshold = Some(0.05);
+        adaptive.min_samples = 8;
+
+        for config in [fixed, adaptive] {
+            let partial = interrupted_render(&config, &world, 7);
+            assert!(partial.total_samples() > 0);
+            let (resumed, _) = render(&camera, &world, &config, partial, &|_, _| {});
+
+            let uninterrupted = render_new(&camera, &world, &config);
+            assert_eq!(uninterrupted.pixels, resumed.pixels);
+            assert_eq!(uninterrupted.splats, resumed.splats);
+        }
+    }
+
+    #[test]
+    fn render_reports_stop_reason() {
+        let camera = Camera::new(0.0);
+        let world = HittableList::new();
+        let fixed = config(4, SamplerKind::Sobol, 0, 4);
+        let mut adaptive = config(1024, SamplerKind::Sobol, 0, 4);
+        adaptive.noise_threshold = Some(0.5);
+
+        let (_, report) = render(&camera, &world, &fixed, Film::new(16, 9), &|_, _| {});
+        assert_eq!(StopReason::SampleLimit, report.stop_reason);
+        assert_eq!(
+            (1, 4, 4),
+            (report.passes, report.min_samples, report.max_samples)
+        );
+
+        let (_, report) = render(&camera, &world, &adaptive, Film::new(16, 9), &|_, _| {});
+        assert_eq!(StopReason::Converged, report.stop_reason);
+        assert!(report.mean_relative_error <= 0.5);
+    }
+
+    #[test]
+    fn render_stops_at_time_limit() {
+        let camera = Camera::new(0.0);
+        let world = foggy_world();
+        let mut config = config(1 << 16, SamplerKind::Sobol, 0, 8);
+        config.min_samples = 12;
+        config.time_limit = Some(Duration::from_millis(100));
+
+        // Every sample takes a millisecond on this clock
+        let now = std::cell::Cell::new(Instant::now());
+        let film = Mutex::new(Film::new(16, 9));
+        let report = render_passes(
+            &config,
+            &film,
+            || now.get(),
+            |samples, label| {
+                render_pass(&camera, &world, &config, &film, samples, &|_, _| {}, label);
+                now.set(now.get() + Duration::from_millis(samples as u64));
+            },
+        );
+        let film = film.into_inner().unwrap();
 
-    for (i, pixel) in screen.chunks_exact_mut(4).enumerate() {
-        let color = colors.get(i).unwrap();
-        pixel.copy_from_slice(&color.to_rgba());
+        // Passes of 16, 16 and 32 samples, one of 64 more would end after the limit
+        assert_eq!(StopReason::TimeLimit, report.stop_reason);
+        assert_eq!(3, report.passes);
+        assert_eq!(Duration::from_millis(64), report.elapsed);
+        assert!(film.pixels.iter().all(|p| p.samples == 64));
+        assert_eq!((64, 64), (report.min_samples, report.max_samples));
     }
-    eprintln!("Elapsed: {:?}", start.elapsed());
 }
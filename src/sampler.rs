@@ -0,0 +1,522 @@
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use rand::prelude::*;
+
+pub trait Sampler: Send {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32);
+    fn get_2d(&mut self) -> (f64, f64);
+
+    // The pixel offsets are always the first two dimensions of a sample
+    fn get_pixel_2d(&mut self) -> (f64, f64) {
+        self.get_2d()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    // The first pass of a progressive render takes `first_pass` samples, or all of them
+    pub fn create(self, first_pass: u32, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => {
+                Box::new(StratifiedSampler::new(first_pass, samples_per_pixel, seed))
+            }
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SamplerKind, String> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!("unknown sampler {}", s)),
+        }
+    }
+}
+
+// Largest f64 below one, samples are kept in [0, 1)
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+// Random numbers for everything the sampler does not cover, such as free flights in media.
+// Derived only from the seed and the sample's identity so the result does not depend on
+// which thread renders it
+pub fn pixel_rng(seed: u64, x: u32, y: u32, sample_index: u32) -> StdRng {
+    StdRng::seed_from_u64(hash(&[seed, x as u64, y as u64, sample_index as u64]))
+}
+
+// The jitter of a sample, kept apart from the pixel_rng stream of the same sample
+fn jitter_rng(seed: u64, x: u32, y: u32, sample_index: u32) -> StdRng {
+    StdRng::seed_from_u64(hash(&[seed, x as u64, y as u64, sample_index as u64, 1]))
+}
+
+pub struct IndependentSampler {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            seed,
+            rng: jitter_rng(seed, 0, 0, 0),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.rng = jitter_rng(self.seed, x, y, sample_index);
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.gen(), self.rng.gen())
+    }
+}
+
+// Jittered sampling, every 2D dimension visits the strata of a grid in a per pixel shuffled order.
+// So that a render stopped after any pass is still stratified, the samples come in blocks with
+// a grid each: the first pass, then blocks doubling in size like the passes of a time limited
+// render, the last one cut off at the samples per pixel
+pub struct StratifiedSampler {
+    first_block: u32,
+    samples_per_pixel: u32,
+    pixel: (u32, u32),
+    sample_index: u32,
+    dimension: u64,
+    seed: u64,
+    rng: StdRng,
+}
+
+impl StratifiedSampler {
+    pub fn new(first_pass: u32, samples_per_pixel: u32, seed: u64) -> StratifiedSampler {
+        StratifiedSampler {
+            first_block: first_pass.max(1),
+            samples_per_pixel: samples_per_pixel.max(1),
+            pixel: (0, 0),
+            sample_index: 0,
+            dimension: 0,
+            seed,
+            rng: jitter_rng(seed, 0, 0, 0),
+        }
+    }
+
+    // Where the block of the sample starts and how many samples it has
+    fn block(&self, sample_index: u32) -> (u32, u32) {
+        let (first, cap) = (self.first_block as u64, self.samples_per_pixel as u64);
+        let index = sample_index as u64;
+        let start = if index < first {
+            0
+        } else {
+            let mut start = first;
+            while start * 2 <= index {
+                start *= 2;
+            }
+            start
+        };
+        let size = if start == 0 { first } else { start };
+        let size = size.min(cap.saturating_sub(start)).max(1);
+        (start as u32, size as u32)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel = (x, y);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+        self.rng = jitter_rng(self.seed, x, y, sample_index);
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (start, size) = self.block(self.sample_index);
+        let x_strata = (size as f64).sqrt().ceil() as u32;
+        let y_strata = size.div_ceil(x_strata);
+        let strata = x_strata * y_strata;
+        let seed = hash(&[
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension,
+            self.seed,
+            start as u64,
+        ]);
+        let offset = (self.sample_index - start) % strata;
+        let stratum = permutation_element(offset, strata, seed as u32);
+        self.dimension += 2;
+
+        let x = (stratum % x_strata) as f64 + self.rng.gen::<f64>();
+        let y = (stratum / x_strata) as f64 + self.rng.gen::<f64>();
+        (x / x_strata as f64, y / y_strata as f64)
+    }
+}
+
+// Halton sequence with a prime base per dimension, Owen scrambled per pixel
+pub struct HaltonSampler {
+    pixel: (u32, u32),
+    sample_index: u32,
+    dimension: u64,
+    seed: u64,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler {
+            pixel: (0, 0),
+            sample_index: 0,
+            dimension: 0,
+            seed,
+        }
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let seed = hash(&[
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension,
+            self.seed,
+        ]);
+        let primes = primes();
+        let base = primes[self.dimension as usize % primes.len()];
+        self.dimension += 1;
+
+        owen_scrambled_radical_inverse(base, self.sample_index as u64, seed)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel = (x, y);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// The first two Sobol dimensions, Owen scrambled and padded: every pair of dimensions
+// shuffles the sample order independently so consecutive pairs stay decorrelated. The shuffle
+// is itself a nested scramble of the bits of the index, which takes the first 2^k samples to
+// an aligned block of 2^k Sobol points, so every power of two prefix is still evenly spread
+pub struct SobolSampler {
+    pixel: (u32, u32),
+    sample_index: u32,
+    dimension: u64,
+    seed: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler {
+            pixel: (0, 0),
+            sample_index: 0,
+            dimension: 0,
+            seed,
+        }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel = (x, y);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let seed = hash(&[
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension,
+            self.seed,
+        ]);
+        let index = owen_scramble(self.sample_index, seed as u32);
+        self.dimension += 2;
+
+        (
+            sobol_sample(index, 0, (seed >> 16) as u32),
+            sobol_sample(index, 1, (seed >> 32) as u32),
+        )
+    }
+}
+
+fn sobol_sample(index: u32, dimension: usize, seed: u32) -> f64 {
+    let mut v = 0u32;
+    let mut a = index;
+    let mut i = 0;
+    while a != 0 {
+        if a & 1 != 0 {
+            v ^= sobol_direction(dimension, i);
+        }
+        a >>= 1;
+        i += 1;
+    }
+    (owen_scramble(v, seed) as f64 / 4294967296.0).min(ONE_MINUS_EPSILON)
+}
+
+// Dimension 0 is the van der Corput sequence, dimension 1 has all m_i = 1
+fn sobol_direction(dimension: usize, bit: u32) -> u32 {
+    let mut v = 1u32 << 31;
+    if dimension == 0 {
+        return v >> bit;
+    }
+    for _ in 0..bit {
+        v ^= v >> 1;
+    }
+    v
+}
+
+// Nested uniform scramble of the bits of v, most significant bit first
+fn owen_scramble(mut v: u32, seed: u32) -> u32 {
+    if seed & 1 != 0 {
+        v ^= 1 << 31;
+    }
+    for b in 1..32 {
+        let mask = !0u32 << (32 - b);
+        if (mix_bits(((v & mask) ^ seed) as u64) as u32) & (1 << b) != 0 {
+            v ^= 1 << (31 - b);
+        }
+    }
+    v
+}
+
+fn owen_scrambled_radical_inverse(base: u64, mut a: u64, seed: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut value = 0.0;
+    // The digits seen so far select the permutation of the next one
+    let mut prefix = 0u64;
+    // Keep scrambling (zero) digits until they no longer change the result
+    while 1.0 - (base - 1) as f64 * inv_base_m < 1.0 {
+        let next = a / base;
+        let digit = a - next * base;
+        let digit_seed = mix_bits(seed ^ prefix) as u32;
+        let digit = permutation_element(digit as u32, base as u32, digit_seed) as u64;
+        prefix = prefix.wrapping_mul(base).wrapping_add(digit);
+        inv_base_m *= inv_base;
+        value += digit as f64 * inv_base_m;
+        a = next;
+    }
+    value.min(ONE_MINUS_EPSILON)
+}
+
+fn primes() -> &'static [u64] {
+    static PRIMES: OnceLock<Vec<u64>> = OnceLock::new();
+    PRIMES.get_or_init(|| {
+        let limit = 8192;
+        let mut composite = vec![false; limit];
+        (2..limit)
+            .filter(|&n| {
+                if composite[n] {
+                    return false;
+                }
+                for multiple in (n * n..limit).step_by(n) {
+                    composite[multiple] = true;
+                }
+                true
+            })
+            .map(|n| n as u64)
+            .collect()
+    })
+}
+
+pub fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e3779b97f4a7c15, |h, &v| {
+        mix_bits(h ^ v.wrapping_add(0x9e3779b97f4a7c15))
+    })
+}
+
+// Element i of a pseudo random permutation of 0..n selected by seed (Kensler 2013)
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Range;
+
+    use crate::camera::Camera;
+    use crate::hittable_list::HittableList;
+    use crate::render::tests::{config, render_new};
+
+    #[test]
+    fn sampler_kind_from_str() {
+        assert_eq!(Ok(SamplerKind::Sobol), "sobol".parse());
+        assert_eq!(Ok(SamplerKind::Halton), "halton".parse());
+        assert!("random".parse::<SamplerKind>().is_err());
+    }
+
+    #[test]
+    fn permutation_is_bijective() {
+        for n in [1, 2, 7, 16, 100] {
+            let mut seen = vec![false; n as usize];
+            for i in 0..n {
+                seen[permutation_element(i, n, 0xdeadbeef) as usize] = true;
+            }
+            assert!(seen.iter().all(|&s| s));
+        }
+    }
+
+    #[test]
+    fn samples_in_unit_square() {
+        for kind in [
+            SamplerKind::Independent,
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ] {
+            let mut sampler = kind.create(4, 10, 0);
+            for index in 0..10 {
+                sampler.start_pixel_sample(3, 4, index);
+                for _ in 0..8 {
+                    let (u, v) = sampler.get_2d();
+                    assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v));
+                }
+            }
+        }
+    }
+
+    // Whether the samples put one point in each of the n by n squares
+    fn fills_grid(
+        sampler: &mut dyn Sampler,
+        pixel: (u32, u32),
+        samples: Range<u32>,
+        n: usize,
+    ) -> bool {
+        let mut seen = vec![false; n * n];
+        for index in samples {
+            sampler.start_pixel_sample(pixel.0, pixel.1, index);
+            let (u, v) = sampler.get_2d();
+            seen[(u * n as f64) as usize + n * (v * n as f64) as usize] = true;
+        }
+        seen.iter().all(|&s| s)
+    }
+
+    #[test]
+    fn stratified_covers_every_stratum() {
+        let mut sampler = StratifiedSampler::new(16, 16, 0);
+        assert!(fills_grid(&mut sampler, (0, 0), 0..16, 4));
+
+        // Stopped after any pass of a progressive render, each pass is stratified on its own
+        let mut sampler = StratifiedSampler::new(16, 1 << 16, 0);
+        for pixel in 0..20 {
+            assert!(fills_grid(&mut sampler, (pixel, 3), 0..16, 4));
+            assert!(fills_grid(&mut sampler, (pixel, 3), 16..32, 4));
+            assert!(fills_grid(&mut sampler, (pixel, 3), 64..128, 8));
+        }
+        // The last block is cut off at the samples per pixel
+        let sampler = StratifiedSampler::new(4, 20, 0);
+        assert_eq!((0, 4), sampler.block(3));
+        assert_eq!((8, 8), sampler.block(15));
+        assert_eq!((16, 4), sampler.block(19));
+    }
+
+    #[test]
+    fn sobol_is_elementary_interval_stratified() {
+        let mut sampler = SobolSampler::new(0);
+        assert!(fills_grid(&mut sampler, (5, 1), 0..16, 4));
+
+        // Every power of two prefix and every pass after it, whatever the samples per pixel
+        for pixel in 0..100 {
+            assert!(fills_grid(&mut sampler, (pixel, 7), 0..16, 4));
+            assert!(fills_grid(&mut sampler, (pixel, 7), 16..32, 4));
+            assert!(fills_grid(&mut sampler, (pixel, 7), 0..64, 8));
+            assert!(fills_grid(&mut sampler, (pixel, 7), 64..128, 8));
+            assert!(fills_grid(&mut sampler, (pixel, 7), 0..256, 16));
+        }
+    }
+
+    fn mean_squared_error(kind: SamplerKind, reference: &[crate::vector::Color]) -> f64 {
+        let camera = Camera::new(0.0);
+        let world = HittableList::new();
+        let colors = render_new(&camera, &world, &config(16, kind, 1, 4)).colors();
+        colors
+            .iter()
+            .zip(reference)
+            .map(|(c, r)| (*c - *r).length_squared())
+            .sum::<f64>()
+            / colors.len() as f64
+    }
+
+    #[test]
+    fn low_discrepancy_beats_independent() {
+        let camera = Camera::new(0.0);
+        let world = HittableList::new();
+        let reference = render_new(
+            &camera,
+            &world,
+            &config(1024, SamplerKind::Independent, 2, 4),
+        )
+        .colors();
+
+        let independent = mean_squared_error(SamplerKind::Independent, &reference);
+        for kind in [
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ] {
+            let error = mean_squared_error(kind, &reference);
+            assert!(
+                error < independent,
+                "{:?} error {} is not below independent error {}",
+                kind,
+                error,
+                independent
+            );
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use std::str::FromStr;
+
+use rand::prelude::*;
+
+use crate::aabb::Aabb;
+use crate::csg::{Csg, Operation};
+use crate::cuboid::Cuboid;
+use crate::curve::Curve;
+use crate::hair::Hair;
+use crate::hittable_list::HittableList;
+use crate::material::{Checker, Diffuse};
+use crate::plane::{Disk, Plane};
+use crate::quadric::Quadric;
+use crate::sdf::{self, Sdf};
+use crate::sphere::Sphere;
+use crate::torus::Torus;
+use crate::vector::{Color, Point3, Vec3};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Scene {
+    // A sphere on the ground
+    Spheres,
+    // The sphere among the other kinds of shapes, on a checkered ground
+    Shapes,
+    // Shapes made from distance functions
+    Sdf,
+    // Solids cut and combined with constructive solid geometry
+    Csg,
+    // A furry sphere in the grass
+    Hair,
+}
+
+impl FromStr for Scene {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Scene, String> {
+        match s {
+            "spheres" => Ok(Scene::Spheres),
+            "shapes" => Ok(Scene::Shapes),
+            "sdf" => Ok(Scene::Sdf),
+            "csg" => Ok(Scene::Csg),
+            "hair" => Ok(Scene::Hair),
+            _ => Err(format!("unknown scene {}", s)),
+        }
+    }
+}
+
+impl Scene {
+    // Adds the objects of the scene to the default world of a sphere on the ground. Every
+    // scene has an object in the middle, the sphere unless the scene makes its own, which is
+    // given the center material when there is one
+    pub fn build(self, world: &mut HittableList, center: Option<usize>) {
+        match self {
+            Scene::Spheres => sphere(world, center),
+            Scene::Shapes => {
+                shapes(world);
+                sphere(world, center);
+            }
+            Scene::Sdf => distance_fields(world, center),
+            Scene::Csg => solids(world, center),
+            Scene::Hair => fibres(world, center),
+        }
+    }
+}
+
+fn sphere(world: &mut HittableList, material: Option<usize>) {
+    if let Some(material) = material {
+        world.set_center(Box::new(Sphere::new(
+            Point3::new(0.0, 0.0, -1.0),
+            0.5,
+            material,
+        )));
+    }
+}
+
+fn shapes(world: &mut HittableList) {
+    let checker = Checker::new(
+        world.color(Color::new(0.8, 0.8, 0.8)),
+        world.color(Color::new(0.2, 0.3, 0.1)),
+        0.5,
+    );
+    let ground = world.add_material(Box::new(checker));
+    world.objects[1] = Box::new(Plane::new(
+        Point3::new(0.0, -0.5, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        ground,
+    ));
+
+    let red = world.add_material(Box::new(Diffuse::new(
+        world.color(Color::new(0.7, 0.2, 0.1)),
+    )));
+    let blue = world.add_material(Box::new(Diffuse::new(
+        world.color(Color::new(0.1, 0.3, 0.7)),
+    )));
+    world.add(Box::new(Disk::new(
+        Point3::new(-1.3, 0.0, -1.5),
+        Vec3::new(1.0, 0.0, 0.6),
+        0.45,
+        red,
+    )));
+    world.add(Box::new(Disk::annulus(
+        Point3::new(1.3, 0.0, -1.5),
+        Vec3::new(-1.0, 0.0, 0.6),
+        0.2,
+        0.45,
+        blue,
+    )));
+
+    // A row of quadrics behind, standing on the ground
+    let green = world.add_material(Box::new(Diffuse::new(
+        world.color(Color::new(0.2, 0.6, 0.3)),
+    )));
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    let quadrics = [
+        Quadric::cylinder(Point3::new(-2.1, -0.5, -3.0), up, 0.35, 0.9, red).capped(),
+        // A roof on the cylinder
+        Quadric::cone(Point3::new(-2.1, 0.4, -3.0), up, 0.45, 0.5, blue).capped(),
+        Quadric::truncated_cone(Point3::new(-0.7, -0.5, -3.0), up, 0.4, 0.15, 0.8, green).capped(),
+        Quadric::paraboloid(Point3::new(0.7, -0.5, -3.0), up, 0.4, 0.8, blue),
+        Quadric::hyperboloid(Point3::new(2.1, 0.0, -3.0), up, 0.2, 0.4, 1.0, green)
+            .with_phi_max(270.0),
+    ];
+    for quadric in quadrics {
+        world.add(Box::new(quadric));
+    }
+
+    // A ring leaning on the ground in front
+    world.add(Box::new(Torus::new(
+        Point3::new(-0.9, -0.33, -0.6),
+        Vec3::new(0.3, 1.0, 0.2),
+        0.25,
+        0.1,
+        blue,
+    )));
+}
+
+fn distance_fields(world: &mut HittableList, center: Option<usize>) {
+    let orange = world.add_material(Box::new(Diffuse::new(
+        world.color(Color::new(0.8, 0.4, 0.1)),
+    )));
+    let teal = world.add_material(Box::new(Diffuse::new(
+        world.color(Color::new(0.1, 0.5, 0.5)),
+    )));
+
+    // In place of the sphere, a rounded box melted into a sphere with a tunnel through both
+    let blob = sdf::smooth_union(
+        sdf::rounded_box(Vec3::new(0.35, 0.25, 0.35), 0.08),
+        sdf::translate(Vec3::new(0.0, 0.25, 0.0), sdf::sphere(0.28)),
+        0.15,
+    );
+    let tunnel = sdf::capsule(
+        Point3::new(-1.0, 0.0, 0.0),
+        Point3::new(1.0, 0.0, 0.0),
+        0.12,
+    );
+    let carved = sdf::smooth_subtraction(blob, tunnel, 0.05);
+    world.set_center(Box::new(Sdf::new(
+        sdf::translate(Point3::new(0.0, -0.2, -1.0), carved),
+        Aabb::new(Point3::new(-0.5, -0.5, -1.5), Point3::new(0.5, 0.4, -0.5)),
+        center.unwrap_or(orange),
+    )));
+
+    world.add(Box::new(Sdf::new(
+        sdf::translate(Point3::new(-1.2, -0.38, -1.2), sdf::torus(0.3, 0.12)),
+        Aabb::new(
+            Point3::new(-1.6, -0.5, -1.6),
+            Point3::new(-0.8, -0.26, -0.8),
+        ),
+        teal,
+    )));
+
+    // A row of spheres, cut from an endless grid of them by a box, rounded where they meet it
+    let grid = sdf::repeat(Vec3::new(0.3, 0.0, 0.3), sdf::sphere(0.12));
+    let row = sdf::smooth_intersection(grid, sdf::cuboid(Vec3::new(0.6, 0.2, 0.15)), 0.02);
+    world.add(Box::new(Sdf::new(
+        sdf::translate(Point3::new(1.2, -0.38, -1.2), row),
+        Aabb::new(Point3::new(0.5, -0.6, -1.4), Point3::new(1.9, -0.2, -1.0)),
+        teal,
+    )));
+}
+
+fn solids(world: &mut HittableList, center: Option<usize>) {
+    let red = world.add_material(Box::new(Diffuse::new(
+        world.color(Color::new(0.7, 0.2, 0.1)),
+    )));
+    let cream = world.add_material(Box::new(Diffuse::new(
+        world.color(Color::new(0.8, 0.75, 0.6)),
+    )));
+    let blue = world.add_material(Box::new(Diffuse::new(
+        world.color(Color::new(0.1, 0.3, 0.7)),
+    )));
+
+    // The sphere with a hole drilled through towards the camera, the walls of the hole in
+    // the center material as well
+    let drill = Quadric::cylinder(
+        Point3::new(0.0, 0.0, -2.0),
+        Vec3::new(0.0, 0.0, 1.0),
+        0.2,
+        2.0,
+        center.unwrap_or(cream),
+    )
+    .capped();
+    world.set_center(Box::new(Csg::new(
+        Operation::Difference,
+        Box::new(Sphere::new(
+            Point3::new(0.0, 0.0, -1.0),
+            0.5,
+            center.unwrap_or(red),
+        )),
+        Box::new(drill),
+    )));
+
+    // A die: the corners of a box rounded off by a sphere
+    let center = Point3::new(-1.2, -0.15, -1.2);
+    world.add(Box::new(Csg::new(
+        Operation::Intersection,
+        Box::new(Cuboid::new(
+            center - Vec3::one() * 0.35,
+            center + Vec3::one() * 0.35,
+            cream,
+        )),
+        Box::new(Sphere::new(center, 0.48, cream)),
+    )));
+
+    // A box with a bowl scooped out of its top and a ring around it
+    let center = Point3::new(1.2, -0.3, -1.2);
+    let bowl = Csg::new(
+        Operation::Difference,
+        Box::new(Cuboid::new(
+            center - Vec3::new(0.35, 0.2, 0.35),
+            center + Vec3::new(0.35, 0.2, 0.35),
+            blue,
+        )),
+        Box::new(Sphere::new(center + Vec3::new(0.0, 0.2, 0.0), 0.28, cream)),
+    );
+    let ring = Torus::new(center, Vec3::new(0.0, 1.0, 0.0), 0.35, 0.06, red);
+    world.add(Box::new(Csg::new(
+        Operation::Union,
+        Box::new(bowl),
+        Box::new(ring),
+    )));
+}
+
+fn fibres(world: &mut HittableList, center: Option<usize>) {
+    // The same strands every time
+    let mut rng = StdRng::seed_from_u64(7);
+    let skin = world.add_material(Box::new(Diffuse::new(
+        world.color(Color::new(0.3, 0.15, 0.05)),
+    )));
+    let fur = world.add_material(Box::new(Hair::new(
+        world.color(Color::new(0.6, 0.35, 0.15)),
+        0.3,
+        0.4,
+    )));
+    let grass = world.add_material(Box::new(Diffuse::new(
+        world.color(Color::new(0.2, 0.5, 0.1)),
+    )));
+
+    // Fur growing out of the sphere and combed down over it, curling a little
+    let middle = Point3::new(0.0, 0.0, -1.0);
+    world.set_center(Box::new(Sphere::new(middle, 0.45, center.unwrap_or(skin))));
+    for _ in 0..12000 {
+        let normal = Vec3::random_unit_vector(&mut rng);
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let combed = down - normal * down.dot(normal) + Vec3::random_in_unit_sphere(&mut rng) * 0.5;
+        let direction = (normal * 0.6 + combed.unit_vector()).unit_vector();
+        let curl = Vec3::random_in_unit_sphere(&mut rng) * 0.03;
+        let root = middle + normal * 0.44;
+        let length = rng.gen_range(0.12..0.18);
+        let points = [
+            root,
+            root + normal * length * 0.3,
+            root + direction * length * 0.7 + curl,
+            root + (direction + combed * 0.2).unit_vector() * length + curl * 1.5,
+        ];
+        world.add(Box::new(Curve::cylinder(points, [0.008, 0.001], fur)));
+    }
+
+    // Blades of grass on the ground around it, turning as they lean over
+    for _ in 0..1500 {
+        let (x, z) = (rng.gen_range(-2.5..2.5), rng.gen_range(-3.5..0.0));
+        let root = Point3::new(x, -0.5, z);
+        if (root - Point3::new(0.0, -0.5, -1.0)).length() < 0.4 {
+            continue;
+        }
+        let height = rng.gen_range(0.15..0.35);
+        let angle: f64 = rng.gen_range(0.0..std::f64::consts::TAU);
+        let lean = Vec3::new(angle.cos(), 0.0, angle.sin()) * height * rng.gen_range(0.2..0.6);
+        let up = Vec3::new(0.0, height, 0.0);
+        let points = [
+            root,
+            root + up * 0.4,
+            root + up * 0.8 + lean * 0.5,
+            root + up + lean,
+        ];
+        // Flat across the direction it leans in, so the normals are across the tangents
+        let side = Vec3::new(-angle.sin(), 0.0, angle.cos());
+        let tip = points[3] - points[2];
+        world.add(Box::new(Curve::ribbon(
+            points,
+            [0.03, 0.0],
+            [up.cross(side), tip.cross(side)],
+            grass,
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittable;
+    use crate::material::{Dielectric, Ior};
+    use crate::ray::Ray;
+    use rand::thread_rng;
+
+    #[test]
+    fn scene_from_str() {
+        assert_eq!(Ok(Scene::Shapes), "shapes".parse());
+        assert!("cornell".parse::<Scene>().is_err());
+    }
+
+    #[test]
+    fn scene_shapes() {
+        let mut world = HittableList::new();
+        Scene::Shapes.build(&mut world, None);
+
+        assert_eq!(10, world.objects.len());
+    }
+
+    #[test]
+    fn scene_sdf() {
+        let mut world = HittableList::new();
+        Scene::Sdf.build(&mut world, None);
+        world.build_bvh();
+
+        // The tunnel goes through the middle of the blob
+        let ray = Ray::new(Point3::new(-2.0, -0.2, -1.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(world
+            .hit(&ray, 0.001, f64::MAX, &mut thread_rng())
+            .is_none());
+        let ray = Ray::new(Point3::new(-2.0, 0.0, -1.0), Vec3::new(1.0, 0.0, 0.0));
+        let record = world.hit(&ray, 0.001, f64::MAX, &mut thread_rng()).unwrap();
+        assert!(record.point.x < 0.0, "{}", record.point);
+        assert_eq!(4, world.objects.len());
+
+        // Glass, or any other center material, goes to the blob
+        let mut world = HittableList::new();
+        let glass = world.add_material(Box::new(Dielectric::new(Ior::Constant(1.5))));
+        Scene::Sdf.build(&mut world, Some(glass));
+        let record = world.hit(&ray, 0.001, f64::MAX, &mut thread_rng()).unwrap();
+        assert!(record.point.x < 0.0, "{}", record.point);
+        assert_eq!(glass, record.material);
+    }
+
+    #[test]
+    fn scene_csg() {
+        let mut world = HittableList::new();
+        Scene::Csg.build(&mut world, None);
+        world.build_bvh();
+
+        // Through the hole in the sphere to the ground behind
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, -0.02, -1.0));
+        let record = world.hit(&ray, 0.001, f64::MAX, &mut thread_rng()).unwrap();
+        assert_eq!(1, record.object);
+        // Beside it the sphere is whole
+        let ray = Ray::new(Point3::new(0.3, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let record = world.hit(&ray, 0.001, f64::MAX, &mut thread_rng()).unwrap();
+        assert_eq!(0, record.object);
+
+        // A glass center is still drilled through
+        let mut world = HittableList::new();
+        let glass = world.add_material(Box::new(Dielectric::new(Ior::Constant(1.5))));
+        Scene::Csg.build(&mut world, Some(glass));
+        let record = world.hit(&ray, 0.001, f64::MAX, &mut thread_rng()).unwrap();
+        assert_eq!((0, glass), (record.object, record.material));
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, -0.02, -1.0));
+        let record = world.hit(&ray, 0.001, f64::MAX, &mut thread_rng()).unwrap();
+        assert_eq!(1, record.object);
+    }
+
+    #[test]
+    fn scene_hair() {
+        let mut world = HittableList::new();
+        Scene::Hair.build(&mut world, None);
+        world.build_bvh();
+
+        // The fur hides the sphere, the camera sees strands first
+        let mut rng = StdRng::seed_from_u64(1);
+        let strands = (0..100)
+            .filter(|_| {
+                let target = Point3::new(rng.gen_range(-0.2..0.2), rng.gen_range(-0.2..0.2), -1.0);
+                let ray = Ray::new(Point3::zero(), target);
+                let record = world.hit(&ray, 0.001, f64::MAX, &mut rng).unwrap();
+                record.tangent.length() > 0.0
+            })
+            .count();
+        assert!(strands > 80, "{}", strands);
+
+        // Under the fur the skin is glass with --glass
+        let mut world = HittableList::new();
+        let glass = world.add_material(Box::new(Dielectric::new(Ior::Constant(1.5))));
+        Scene::Hair.build(&mut world, Some(glass));
+        let ray = Ray::new(Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0));
+        let record = world.objects[0]
+            .hit(&ray, 0.001, f64::MAX, &mut rng)
+            .unwrap();
+        assert_eq!(glass, record.material);
+    }
+}
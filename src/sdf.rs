@@ -0,0 +1,310 @@
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::vector::{Point3, Vec3};
+
+// Closer than this counts as on the surface
+const EPSILON: f64 = 1e-6;
+// Step for the finite differences of the normal
+const NORMAL_STEP: f64 = 1e-5;
+const MAX_STEPS: usize = 512;
+
+// Signed distance to a surface, negative inside. Functions that only give a lower bound on the
+// distance, like the smooth combinations, still trace correctly if more slowly
+pub trait Distance: Fn(Point3) -> f64 + Send + Sync {}
+
+impl<F: Fn(Point3) -> f64 + Send + Sync> Distance for F {}
+
+fn map(v: Vec3, f: impl Fn(f64) -> f64) -> Vec3 {
+    Vec3::new(f(v.x), f(v.y), f(v.z))
+}
+
+pub fn sphere(radius: f64) -> impl Distance {
+    move |p: Point3| p.length() - radius
+}
+
+// Box with the given distances from its center to the faces
+pub fn cuboid(half_size: Vec3) -> impl Distance {
+    move |p: Point3| {
+        let q = map(p, f64::abs) - half_size;
+        map(q, |c| c.max(0.0)).length() + q.x.max(q.y).max(q.z).min(0.0)
+    }
+}
+
+// Box with edges rounded off by the radius, within the same half size
+pub fn rounded_box(half_size: Vec3, radius: f64) -> impl Distance {
+    let inner = cuboid(half_size - Vec3::one() * radius);
+    move |p: Point3| inner(p) - radius
+}
+
+// Around the y axis
+pub fn torus(major_radius: f64, minor_radius: f64) -> impl Distance {
+    move |p: Point3| (p.x.hypot(p.z) - major_radius).hypot(p.y) - minor_radius
+}
+
+// Everything within the radius of the segment between the ends
+pub fn capsule(a: Point3, b: Point3, radius: f64) -> impl Distance {
+    move |p: Point3| {
+        let (pa, ba) = (p - a, b - a);
+        let h = (pa.dot(ba) / ba.dot(ba)).clamp(0.0, 1.0);
+        (pa - ba * h).length() - radius
+    }
+}
+
+pub fn translate(offset: Vec3, shape: impl Distance) -> impl Distance {
+    move |p: Point3| shape(p - offset)
+}
+
+// Copies of the shape every period along each axis, axes with a period of zero are not
+// repeated. The shape should fit within half a period of the origin
+pub fn repeat(period: Vec3, shape: impl Distance) -> impl Distance {
+    move |p: Point3| {
+        let wrap = |c: f64, period: f64| {
+            if period > 0.0 {
+                c - period * (c / period).round()
+            } else {
+                c
+            }
+        };
+        shape(Vec3::new(
+            wrap(p.x, period.x),
+            wrap(p.y, period.y),
+            wrap(p.z, period.z),
+        ))
+    }
+}
+
+// How much of b blends in, and how far the blend bulges out, for a smoothing radius k
+fn blend(difference: f64, k: f64) -> (f64, f64) {
+    if k <= 0.0 {
+        let h = if difference > 0.0 { 1.0 } else { 0.0 };
+        return (h, 0.0);
+    }
+    let h = (0.5 + 0.5 * difference / k).clamp(0.0, 1.0);
+    (h, k * h * (1.0 - h))
+}
+
+// Union with the seam rounded over the smoothing radius k, as in Inigo Quilez's polynomial
+// smooth minimum. With k of zero these are the sharp combinations
+pub fn smooth_union(a: impl Distance, b: impl Distance, k: f64) -> impl Distance {
+    move |p: Point3| {
+        let (da, db) = (a(p), b(p));
+        let (h, bulge) = blend(da - db, k);
+        da + (db - da) * h - bulge
+    }
+}
+
+// A with b cut away
+pub fn smooth_subtraction(a: impl Distance, b: impl Distance, k: f64) -> impl Distance {
+    move |p: Point3| {
+        let (da, db) = (a(p), -b(p));
+        let (h, bulge) = blend(db - da, k);
+        da + (db - da) * h + bulge
+    }
+}
+
+pub fn smooth_intersection(a: impl Distance, b: impl Distance, k: f64) -> impl Distance {
+    move |p: Point3| {
+        let (da, db) = (a(p), b(p));
+        let (h, bulge) = blend(db - da, k);
+        da + (db - da) * h + bulge
+    }
+}
+
+// A shape given by its distance function, found by sphere tracing: stepping along the ray by
+// the distance to the surface, which can never step through it. Tracing is limited to the
+// bounds, which must contain the surface
+pub struct Sdf {
+    distance: Box<dyn Distance>,
+    bounds: Aabb,
+    material: usize,
+}
+
+impl Sdf {
+    pub fn new(distance: impl Distance + 'static, bounds: Aabb, material: usize) -> Sdf {
+        Sdf {
+            distance: Box::new(distance),
+            // Surfaces touching the bounds are still found
+            bounds: bounds.padded(NORMAL_STEP),
+            material,
+        }
+    }
+
+    // Central differences of the distance
+    fn normal(&self, p: Point3) -> Vec3 {
+        let difference = |axis: Vec3| {
+            (self.distance)(p + axis * NORMAL_STEP) - (self.distance)(p - axis * NORMAL_STEP)
+        };
+        Vec3::new(
+            difference(Vec3::new(1.0, 0.0, 0.0)),
+            difference(Vec3::new(0.0, 1.0, 0.0)),
+            difference(Vec3::new(0.0, 0.0, 1.0)),
+        )
+        .unit_vector()
+    }
+}
+
+impl Hittable for Sdf {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let (start, end) = self.bounds.hit(ray, t_min, t_max)?;
+        let speed = ray.direction.length();
+
+        let mut t = start;
+        for _ in 0..MAX_STEPS {
+            if t >= end {
+                return None;
+            }
+            // Rays from inside march out through the negative distances
+            let distance = (self.distance)(ray.at(t)).abs();
+            if distance < EPSILON {
+                if t <= t_min {
+                    // Leaving the surface the ray starts on
+                    t += 2.0 * EPSILON / speed;
+                    continue;
+                }
+                let p = ray.at(t);
+                let normal = self.normal(p);
+                let front_face = ray.direction.dot(normal) < 0.0;
+                return Some(HitRecord::new(p, normal, t, front_face, self.material));
+            }
+            t += distance / speed;
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    use assert_approx_eq::*;
+    use rand::prelude::*;
+
+    fn around(radius: f64) -> Aabb {
+        Aabb::new(Point3::one() * -radius, Point3::one() * radius)
+    }
+
+    #[test]
+    fn sdf_matches_sphere() {
+        let center = Point3::new(0.2, -0.1, 0.3);
+        let sdf = Sdf::new(translate(center, sphere(0.5)), around(1.0), 0);
+        let analytic = Sphere::new(center, 0.5, 0);
+        let mut rng = StdRng::seed_from_u64(4);
+
+        let mut hits = 0;
+        for _ in 0..300 {
+            let origin = Point3::random(-3.0..3.0, &mut rng);
+            let target = center + Vec3::random(-0.6..0.6, &mut rng);
+            let ray = Ray::new(origin, target - origin);
+            let expected = analytic.hit(&ray, 0.001, f64::MAX, &mut rng);
+            let found = sdf.hit(&ray, 0.001, f64::MAX, &mut rng);
+            match (expected, found) {
+                (Some(expected), Some(found)) => {
+                    hits += 1;
+                    assert!((expected.point - found.point).length() < 1e-4);
+                    assert!(expected.normal.dot(found.normal) > 0.9999);
+                    assert_eq!(expected.front_face, found.front_face);
+                }
+                (None, None) => {}
+                // Grazing rays may go either way
+                (expected, found) => {
+                    let t = expected.or(found).unwrap().t;
+                    assert!(sphere(0.5)(ray.at(t) - center).abs() < 1e-3);
+                }
+            }
+        }
+        assert!(hits > 100);
+    }
+
+    #[test]
+    fn sdf_from_inside() {
+        let sdf = Sdf::new(sphere(1.0), around(1.0), 0);
+        let rng = &mut StdRng::seed_from_u64(1);
+        let ray = Ray::new(Point3::zero(), Vec3::new(0.0, 0.0, 2.0));
+
+        let record = sdf.hit(&ray, 0.001, f64::MAX, rng).unwrap();
+        assert_approx_eq!(0.5, record.t, 1e-6);
+        assert!(!record.front_face);
+        // Starting on the surface it leaves, the ray finds nothing else
+        let out = Ray::new(record.point, ray.direction);
+        assert!(sdf.hit(&out, 0.0, f64::MAX, rng).is_none());
+    }
+
+    #[test]
+    fn sdf_primitives() {
+        let p = Point3::new(2.0, 0.0, 0.0);
+        assert_approx_eq!(1.0, cuboid(Vec3::one())(p), 1e-12);
+        assert_approx_eq!(-0.5, cuboid(Vec3::one())(Point3::new(0.5, 0.0, 0.0)), 1e-12);
+        // Past a corner the distance is to the corner
+        let corner = cuboid(Vec3::one())(Point3::new(2.0, 2.0, 1.0));
+        assert_approx_eq!(2.0f64.sqrt(), corner, 1e-12);
+        let rounded = rounded_box(Vec3::one(), 0.5)(Point3::new(2.0, 2.0, 0.0));
+        assert_approx_eq!(2.0f64.sqrt() * 1.5 - 0.5, rounded, 1e-12);
+        assert_approx_eq!(0.75, torus(1.0, 0.25)(Point3::new(0.0, 0.0, 0.0)), 1e-12);
+        assert_approx_eq!(-0.25, torus(1.0, 0.25)(Point3::new(0.0, 0.0, 1.0)), 1e-12);
+        let capsule = capsule(Point3::zero(), Point3::new(0.0, 2.0, 0.0), 0.5);
+        assert_approx_eq!(0.5, capsule(Point3::new(1.0, 1.0, 0.0)), 1e-12);
+        assert_approx_eq!(0.5, capsule(Point3::new(0.0, 3.0, 0.0)), 1e-12);
+    }
+
+    #[test]
+    fn sdf_smooth_combinations() {
+        let a = translate(Vec3::new(-0.8, 0.0, 0.0), sphere(1.0));
+        let b = translate(Vec3::new(0.8, 0.0, 0.0), sphere(1.0));
+        let between = Point3::new(0.0, 1.0, 0.0);
+
+        // Sharp combinations are the minimum and maximum
+        let (da, db) = (a(between), b(between));
+        let union = smooth_union(&a, &b, 0.0);
+        assert_approx_eq!(da.min(db), union(between), 1e-12);
+        let far = Point3::new(5.0, 0.0, 0.0);
+        assert_approx_eq!(
+            a(far).max(b(far)),
+            smooth_intersection(&a, &b, 0.0)(far),
+            1e-12
+        );
+        assert_approx_eq!(
+            a(far).max(-b(far)),
+            smooth_subtraction(&a, &b, 0.0)(far),
+            1e-12
+        );
+
+        // Smoothing fills in the seam and deepens the cut, but not away from it
+        assert!(smooth_union(&a, &b, 0.5)(between) < union(between));
+        assert!(
+            smooth_intersection(&a, &b, 0.5)(Point3::zero())
+                > a(Point3::zero()).max(b(Point3::zero()))
+        );
+        assert_approx_eq!(a(far).min(b(far)), smooth_union(&a, &b, 0.5)(far), 1e-12);
+        // Subtracting b leaves its hole in a
+        let cut = smooth_subtraction(&a, &b, 0.1);
+        assert!(cut(Point3::new(0.1, 0.0, 0.0)) > 0.0);
+        assert!(cut(Point3::new(-1.0, 0.0, 0.0)) < 0.0);
+    }
+
+    #[test]
+    fn sdf_repeat() {
+        let spheres = repeat(Vec3::new(2.0, 0.0, 2.0), sphere(0.5));
+        let p = Point3::new(0.3, 0.2, -0.1);
+        assert_approx_eq!(spheres(p), spheres(p + Vec3::new(4.0, 0.0, -6.0)), 1e-12);
+        // Not repeated along y
+        assert!(spheres(p + Vec3::new(0.0, 2.0, 0.0)) > 1.0);
+
+        let sdf = Sdf::new(
+            translate(Vec3::new(0.0, 0.0, 1.0), spheres),
+            around(30.0),
+            0,
+        );
+        let ray = Ray::new(Point3::new(20.0, 0.0, 4.0), Vec3::new(0.0, 0.0, -1.0));
+        let record = sdf
+            .hit(&ray, 0.001, f64::MAX, &mut StdRng::seed_from_u64(1))
+            .unwrap();
+        assert_approx_eq!(0.5, record.t, 1e-6);
+    }
+}
@@ -0,0 +1,628 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::color::ColorSpace;
+use crate::denoise::Denoiser;
+use crate::exr::{Compression, Precision};
+use crate::filter::{self, Filter, FilterKind};
+use crate::material::Ior;
+use crate::post::{PostProcess, BLOOM_SCALES};
+use crate::sampler::SamplerKind;
+use crate::scene::Scene;
+use crate::subdivision::{self, Scheme};
+use crate::texture::Source;
+use crate::tile::TileOrder;
+use crate::tonemap::{ToneMap, View};
+use crate::{IMAGE_HEIGHT, IMAGE_WIDTH};
+
+pub struct Settings {
+    pub samples: Option<u32>,
+    pub noise_threshold: Option<f64>,
+    pub min_samples: u32,
+    pub sample_map: Option<PathBuf>,
+    pub time_limit: Option<Duration>,
+    pub output: Option<PathBuf>,
+    pub aovs: bool,
+    pub exr_precision: Precision,
+    pub exr_compression: Compression,
+    pub view: View,
+    pub denoise: Option<Denoiser>,
+    pub post: PostProcess,
+    pub filter: Filter,
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    pub checkpoint: Option<PathBuf>,
+    // Seconds between checkpoints
+    pub checkpoint_interval: u64,
+    pub resume: Option<PathBuf>,
+    pub sampler: SamplerKind,
+    pub seed: u64,
+    pub scene: Scene,
+    pub aperture: f64,
+    pub spectral: bool,
+    // Makes the center sphere glass with this index of refraction
+    pub glass: Option<Ior>,
+    pub volume: Option<PathBuf>,
+    pub volume_density: f64,
+    pub mesh: Option<PathBuf>,
+    pub subdivision: Option<Scheme>,
+    pub subdivision_level: u32,
+    pub displacement: Option<Source>,
+    pub displacement_scale: f64,
+    pub displacement_edge: f64,
+    pub particles: Option<PathBuf>,
+    // For particles without a radius of their own
+    pub particle_radius: f64,
+    pub serve: Option<String>,
+    pub worker: Option<String>,
+    // Seconds a worker gets for a tile before it is given to another
+    pub worker_timeout: u64,
+}
+
+impl Settings {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Settings, String> {
+        let mut settings = Settings {
+            samples: None,
+            noise_threshold: None,
+            min_samples: 16,
+            sample_map: None,
+            time_limit: None,
+            output: None,
+            aovs: false,
+            exr_precision: Precision::Half,
+            exr_compression: Compression::Rle,
+            view: View::new(0.0, ToneMap::None, 11.2, ColorSpace::Srgb, ColorSpace::Srgb),
+            denoise: None,
+            post: PostProcess {
+                threshold: 1.0,
+                bloom: 0.0,
+                bloom_radius: 4.0,
+                glare: 0.0,
+                glare_blades: 6,
+                glare_length: 40.0,
+                vignette: 0.0,
+                chromatic_aberration: 0.0,
+            },
+            filter: Filter::new(FilterKind::Box),
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            checkpoint: None,
+            checkpoint_interval: 60,
+            resume: None,
+            sampler: SamplerKind::Sobol,
+            seed: 0,
+            scene: Scene::Spheres,
+            aperture: 0.0,
+            spectral: false,
+            glass: None,
+            volume: None,
+            volume_density: 1.0,
+            mesh: None,
+            subdivision: None,
+            subdivision_level: 2,
+            displacement: None,
+            displacement_scale: 0.1,
+            displacement_edge: 0.05,
+            particles: None,
+            particle_radius: 0.01,
+            serve: None,
+            worker: None,
+            worker_timeout: 60,
+        };
+
+        let mut filter_radius = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+            match arg.as_str() {
+                "--samples" => settings.samples = Some(parse(&arg, &value()?)?),
+                "--noise-threshold" => settings.noise_threshold = Some(parse(&arg, &value()?)?),
+                "--min-samples" => settings.min_samples = parse(&arg, &value()?)?,
+                "--sample-map" => settings.sample_map = Some(PathBuf::from(value()?)),
+                "--time-limit" => {
+                    let seconds = parse(&arg, &value()?)?;
+                    settings.time_limit = Some(
+                        Duration::try_from_secs_f64(seconds)
+                            .map_err(|_| format!("invalid value {} for {}", seconds, arg))?,
+                    )
+                }
+                "--output" => settings.output = Some(PathBuf::from(value()?)),
+                "--aovs" => settings.aovs = true,
+                "--exr-precision" => settings.exr_precision = parse(&arg, &value()?)?,
+                "--exr-compression" => settings.exr_compression = parse(&arg, &value()?)?,
+                "--exposure" => settings.view.exposure = parse(&arg, &value()?)?,
+                "--tone-map" => settings.view.tone_map = parse(&arg, &value()?)?,
+                "--white-point" => {
+                    settings.view.white_point = parse_checked(&arg, &value()?, positive)?
+                }
+                "--working-space" => {
+                    let space: ColorSpace = parse(&arg, &value()?)?;
+                    if !space.is_working_space() {
+                        return Err(format!("{} is not a working space", space.name()));
+                    }
+                    settings.view.set_working_space(space);
+                }
+                "--output-space" => {
+                    let space: ColorSpace = parse(&arg, &value()?)?;
+                    if !space.is_output_space() {
+                        return Err(format!("{} is not an output space", space.name()));
+                    }
+                    settings.view.set_output_space(space);
+                }
+                "--denoise" => settings.denoise = Some(parse(&arg, &value()?)?),
+                "--bloom" => settings.post.bloom = parse_checked(&arg, &value()?, non_negative)?,
+                "--bloom-threshold" => {
+                    settings.post.threshold = parse_checked(&arg, &value()?, non_negative)?
+                }
+                // The widest blur is no wider than the image
+                "--bloom-radius" => {
+                    let widest = (1 << (BLOOM_SCALES - 1)) as f64;
+                    let check = |x| positive(x) && x * widest <= image_diagonal();
+                    settings.post.bloom_radius = parse_checked(&arg, &value()?, check)?
+                }
+                "--glare" => settings.post.glare = parse_checked(&arg, &value()?, non_negative)?,
+                "--glare-blades" => settings.post.glare_blades = parse(&arg, &value()?)?,
+                "--glare-length" => {
+                    let check = |x| non_negative(x) && x <= image_diagonal();
+                    settings.post.glare_length = parse_checked(&arg, &value()?, check)?
+                }
+                "--vignette" => {
+                    settings.post.vignette = parse_checked(&arg, &value()?, non_negative)?
+                }
+                // Blue is pulled in from the corners, by less than the way to the center
+                "--chromatic-aberration" => {
+                    let check = |x| non_negative(x) && x < image_diagonal() / 2.0;
+                    settings.post.chromatic_aberration = parse_checked(&arg, &value()?, check)?
+                }
+                "--filter" => settings.filter = Filter::new(parse(&arg, &value()?)?),
+                "--filter-radius" => {
+                    let check = |x| positive(x) && x <= filter::MAX_RADIUS;
+                    filter_radius = Some(parse_checked(&arg, &value()?, check)?);
+                }
+                "--tile-size" => settings.tile_size = parse(&arg, &value()?)?,
+                "--tile-order" => settings.tile_order = parse(&arg, &value()?)?,
+                "--checkpoint" => settings.checkpoint = Some(PathBuf::from(value()?)),
+                "--checkpoint-interval" => settings.checkpoint_interval = parse(&arg, &value()?)?,
+                "--resume" => settings.resume = Some(PathBuf::from(value()?)),
+                "--sampler" => settings.sampler = parse(&arg, &value()?)?,
+                "--seed" => settings.seed = parse(&arg, &value()?)?,
+                "--scene" => settings.scene = parse(&arg, &value()?)?,
+                "--aperture" => settings.aperture = parse_checked(&arg, &value()?, non_negative)?,
+                "--spectral" => settings.spectral = true,
+                "--glass" => settings.glass = Some(parse(&arg, &value()?)?),
+                "--volume" => settings.volume = Some(PathBuf::from(value()?)),
+                "--volume-density" => {
+                    settings.volume_density = parse_checked(&arg, &value()?, positive)?
+                }
+                "--mesh" => settings.mesh = Some(PathBuf::from(value()?)),
+                "--subdivision" => settings.subdivision = Some(parse(&arg, &value()?)?),
+                "--subdivision-level" => {
+                    let level: u32 = parse(&arg, &value()?)?;
+                    if level > subdivision::MAX_LEVEL {
+                        return Err(format!("invalid value {} for {}", level, arg));
+                    }
+                    settings.subdivision_level = level;
+                }
+                "--displacement" => settings.displacement = Some(parse(&arg, &value()?)?),
+                "--displacement-scale" => {
+                    settings.displacement_scale = parse_checked(&arg, &value()?, f64::is_finite)?
+                }
+                "--displacement-edge" => {
+                    settings.displacement_edge = parse_checked(&arg, &value()?, positive)?
+                }
+                "--particles" => settings.particles = Some(PathBuf::from(value()?)),
+                "--particle-radius" => {
+                    settings.particle_radius = parse_checked(&arg, &value()?, positive)?
+                }
+                "--serve" => settings.serve = Some(value()?),
+                "--worker" => settings.worker = Some(value()?),
+                "--worker-timeout" => {
+                    let seconds: u64 = parse(&arg, &value()?)?;
+                    if seconds == 0 {
+                        return Err(format!("invalid value {} for {}", seconds, arg));
+                    }
+                    settings.worker_timeout = seconds;
+                }
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+
+        // The radius applies to whichever filter is chosen, before or after it
+        if let Some(radius) = filter_radius {
+            settings.filter.radius = radius;
+        }
+        if settings.aovs && settings.output.is_none() {
+            return Err(String::from("--aovs needs --output"));
+        }
+        if settings.displacement.is_some() && settings.mesh.is_none() {
+            return Err(String::from("--displacement needs --mesh"));
+        }
+
+        Ok(settings)
+    }
+}
+
+fn parse<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {} for {}", value, arg))
+}
+
+// A number the check accepts
+fn parse_checked(arg: &str, value: &str, check: impl Fn(f64) -> bool) -> Result<f64, String> {
+    let number = parse(arg, value)?;
+    if check(number) {
+        Ok(number)
+    } else {
+        Err(format!("invalid value {} for {}", value, arg))
+    }
+}
+
+// Both false for NaN
+fn non_negative(x: f64) -> bool {
+    x.is_finite() && x >= 0.0
+}
+
+fn positive(x: f64) -> bool {
+    x.is_finite() && x > 0.0
+}
+
+fn image_diagonal() -> f64 {
+    (IMAGE_WIDTH as f64).hypot(IMAGE_HEIGHT as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn settings_defaults() {
+        let settings = Settings::from_args(args("")).unwrap();
+
+        assert!(settings.samples.is_none());
+        assert!(settings.noise_threshold.is_none());
+        assert_eq!(16, settings.min_samples);
+        assert!(settings.sample_map.is_none());
+        assert!(settings.time_limit.is_none());
+        assert!(settings.output.is_none());
+        assert!(!settings.aovs);
+        assert_eq!(Precision::Half, settings.exr_precision);
+        assert_eq!(Compression::Rle, settings.exr_compression);
+        let view = View::new(0.0, ToneMap::None, 11.2, ColorSpace::Srgb, ColorSpace::Srgb);
+        assert_eq!(view, settings.view);
+        assert!(settings.denoise.is_none());
+        assert!(!settings.post.is_enabled());
+        assert_eq!(Filter::new(FilterKind::Box), settings.filter);
+        assert_eq!(32, settings.tile_size);
+        assert_eq!(TileOrder::Spiral, settings.tile_order);
+        assert!(settings.checkpoint.is_none());
+        assert_eq!(60, settings.checkpoint_interval);
+        assert!(settings.resume.is_none());
+        assert_eq!(SamplerKind::Sobol, settings.sampler);
+        assert_eq!(0, settings.seed);
+        assert_eq!(Scene::Spheres, settings.scene);
+        assert_eq!(0.0, settings.aperture);
+        assert!(!settings.spectral);
+        assert!(settings.glass.is_none());
+        assert!(settings.volume.is_none());
+        assert_eq!(1.0, settings.volume_density);
+        assert!(settings.mesh.is_none());
+        assert!(settings.subdivision.is_none());
+        assert_eq!(2, settings.subdivision_level);
+        assert!(settings.displacement.is_none());
+        assert_eq!(0.1, settings.displacement_scale);
+        assert_eq!(0.05, settings.displacement_edge);
+        assert!(settings.particles.is_none());
+        assert_eq!(0.01, settings.particle_radius);
+        assert!(settings.serve.is_none());
+        assert!(settings.worker.is_none());
+        assert_eq!(60, settings.worker_timeout);
+    }
+
+    #[test]
+    fn settings_adaptive() {
+        let settings = Settings::from_args(args(
+            "--samples 1024 --noise-threshold 0.01 --min-samples 32 --sample-map map.ppm",
+        ))
+        .unwrap();
+
+        assert_eq!(Some(1024), settings.samples);
+        assert_eq!(Some(0.01), settings.noise_threshold);
+        assert_eq!(32, settings.min_samples);
+        assert_eq!(Some(PathBuf::from("map.ppm")), settings.sample_map);
+    }
+
+    #[test]
+    fn settings_time_limit() {
+        let settings = Settings::from_args(args("--time-limit 90 --output render.ppm")).unwrap();
+
+        assert_eq!(Some(Duration::from_secs(90)), settings.time_limit);
+        assert_eq!(Some(PathBuf::from("render.ppm")), settings.output);
+        assert!(Settings::from_args(args("--time-limit -1")).is_err());
+    }
+
+    #[test]
+    fn settings_aovs() {
+        let settings = Settings::from_args(args("--aovs --output render.ppm")).unwrap();
+
+        assert!(settings.aovs);
+        assert!(Settings::from_args(args("--aovs")).is_err());
+    }
+
+    #[test]
+    fn settings_exr() {
+        let settings =
+            Settings::from_args(args("--exr-precision float --exr-compression none")).unwrap();
+
+        assert_eq!(Precision::Float, settings.exr_precision);
+        assert_eq!(Compression::None, settings.exr_compression);
+        assert!(Settings::from_args(args("--exr-compression zip")).is_err());
+    }
+
+    #[test]
+    fn settings_view() {
+        let settings =
+            Settings::from_args(args("--exposure -1.5 --tone-map agx --white-point 4")).unwrap();
+
+        assert_eq!(-1.5, settings.view.exposure);
+        assert_eq!(ToneMap::Agx, settings.view.tone_map);
+        assert_eq!(4.0, settings.view.white_point);
+        assert!(Settings::from_args(args("--tone-map filmic")).is_err());
+        for invalid in ["0", "-4", "nan", "inf"] {
+            let error = Settings::from_args(args(&format!("--white-point {}", invalid)));
+            assert!(
+                error.err().unwrap().starts_with("invalid value"),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn settings_color_spaces() {
+        let settings =
+            Settings::from_args(args("--working-space acescg --output-space display-p3")).unwrap();
+
+        let view = View::new(
+            0.0,
+            ToneMap::None,
+            11.2,
+            ColorSpace::AcesCg,
+            ColorSpace::DisplayP3,
+        );
+        assert_eq!(view, settings.view);
+        assert!(Settings::from_args(args("--working-space rec2020")).is_err());
+        assert!(Settings::from_args(args("--output-space acescg")).is_err());
+    }
+
+    #[test]
+    fn settings_denoise() {
+        let settings = Settings::from_args(args("--denoise bilateral")).unwrap();
+
+        assert_eq!(Some(Denoiser::Bilateral), settings.denoise);
+        assert!(Settings::from_args(args("--denoise")).is_err());
+    }
+
+    #[test]
+    fn settings_post() {
+        let settings = Settings::from_args(args(
+            "--bloom 0.2 --bloom-threshold 2 --bloom-radius 3 --glare 0.1 --glare-blades 5 \
+             --glare-length 60 --vignette 0.5 --chromatic-aberration 1.5",
+        ))
+        .unwrap();
+
+        let post = settings.post;
+        assert_eq!(
+            (0.2, 2.0, 3.0),
+            (post.bloom, post.threshold, post.bloom_radius)
+        );
+        assert_eq!(
+            (0.1, 5, 60.0),
+            (post.glare, post.glare_blades, post.glare_length)
+        );
+        assert_eq!((0.5, 1.5), (post.vignette, post.chromatic_aberration));
+        assert!(Settings::from_args(args("--glare-blades 5.5")).is_err());
+
+        for invalid in [
+            "--bloom-radius 0",
+            "--bloom-radius nan",
+            "--bloom-radius 1e12",
+            "--glare-length 1e12",
+            "--bloom -0.5",
+            "--bloom-threshold -1",
+            "--glare inf",
+            "--glare-length -10",
+            "--vignette nan",
+            "--chromatic-aberration -1",
+            "--chromatic-aberration 400",
+        ] {
+            let error = Settings::from_args(args(invalid)).err().unwrap();
+            assert!(error.starts_with("invalid value"), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn settings_filter() {
+        let settings = Settings::from_args(args("--filter mitchell")).unwrap();
+        assert_eq!(Filter::new(FilterKind::Mitchell), settings.filter);
+
+        let settings = Settings::from_args(args("--filter-radius 1.5 --filter tent")).unwrap();
+        assert_eq!(FilterKind::Tent, settings.filter.kind);
+        assert_eq!(1.5, settings.filter.radius);
+        for invalid in ["0", "nan", "inf", "1e9"] {
+            let error = Settings::from_args(args(&format!("--filter-radius {}", invalid)));
+            assert!(
+                error.err().unwrap().starts_with("invalid value"),
+                "{}",
+                invalid
+            );
+        }
+        assert!(Settings::from_args(args("--filter sinc")).is_err());
+    }
+
+    #[test]
+    fn settings_tiles() {
+        let settings = Settings::from_args(args("--tile-size 64 --tile-order hilbert")).unwrap();
+
+        assert_eq!(64, settings.tile_size);
+        assert_eq!(TileOrder::Hilbert, settings.tile_order);
+    }
+
+    #[test]
+    fn settings_checkpoint() {
+        let settings = Settings::from_args(args(
+            "--checkpoint render.ckpt --checkpoint-interval 300 --resume old.ckpt",
+        ))
+        .unwrap();
+
+        assert_eq!(Some(PathBuf::from("render.ckpt")), settings.checkpoint);
+        assert_eq!(300, settings.checkpoint_interval);
+        assert_eq!(Some(PathBuf::from("old.ckpt")), settings.resume);
+    }
+
+    #[test]
+    fn settings_sampler() {
+        let settings =
+            Settings::from_args(args("--sampler halton --seed 9 --aperture 0.1")).unwrap();
+
+        assert_eq!(SamplerKind::Halton, settings.sampler);
+        assert_eq!(9, settings.seed);
+        assert_eq!(0.1, settings.aperture);
+        for invalid in ["-0.1", "nan", "inf"] {
+            let error = Settings::from_args(args(&format!("--aperture {}", invalid)));
+            assert!(
+                error.err().unwrap().starts_with("invalid value"),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn settings_scene() {
+        let settings = Settings::from_args(args("--scene shapes")).unwrap();
+
+        assert_eq!(Scene::Shapes, settings.scene);
+        assert!(Settings::from_args(args("--scene teapot")).is_err());
+    }
+
+    #[test]
+    fn settings_spectral() {
+        let settings = Settings::from_args(args("--spectral --glass sf11")).unwrap();
+
+        assert!(settings.spectral);
+        assert_eq!(Some(Ior::SF11), settings.glass);
+        assert!(Settings::from_args(args("--glass cauchy:1.5")).is_err());
+    }
+
+    #[test]
+    fn settings_volume() {
+        let settings =
+            Settings::from_args(args("--volume smoke.vol --volume-density 4.5")).unwrap();
+
+        assert_eq!(Some(PathBuf::from("smoke.vol")), settings.volume);
+        assert_eq!(4.5, settings.volume_density);
+        for invalid in ["0", "-1", "nan", "inf"] {
+            let error = Settings::from_args(args(&format!("--volume-density {}", invalid)));
+            assert!(
+                error.err().unwrap().starts_with("invalid value"),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn settings_mesh() {
+        let settings = Settings::from_args(args(
+            "--mesh cage.obj --subdivision catmull-clark --subdivision-level 3",
+        ))
+        .unwrap();
+
+        assert_eq!(Some(PathBuf::from("cage.obj")), settings.mesh);
+        assert_eq!(Some(Scheme::CatmullClark), settings.subdivision);
+        assert_eq!(3, settings.subdivision_level);
+        assert!(Settings::from_args(args("--subdivision butterfly")).is_err());
+        let error = Settings::from_args(args("--subdivision-level 9"))
+            .err()
+            .unwrap();
+        assert!(error.starts_with("invalid value"));
+    }
+
+    #[test]
+    fn settings_displacement() {
+        let settings = Settings::from_args(args(
+            "--mesh rock.obj --displacement noise --displacement-scale -0.2 --displacement-edge 0.01",
+        ))
+        .unwrap();
+
+        assert_eq!(Some(Source::Noise), settings.displacement);
+        assert_eq!(-0.2, settings.displacement_scale);
+        assert_eq!(0.01, settings.displacement_edge);
+        let settings =
+            Settings::from_args(args("--mesh rock.obj --displacement height.pgm")).unwrap();
+        assert_eq!(
+            Some(Source::Image(PathBuf::from("height.pgm"))),
+            settings.displacement
+        );
+        for invalid in [
+            "--displacement-edge 0",
+            "--displacement-edge nan",
+            "--displacement-scale inf",
+        ] {
+            let error = Settings::from_args(args(invalid)).err().unwrap();
+            assert!(error.starts_with("invalid value"), "{}", invalid);
+        }
+        assert_eq!(
+            Err(String::from("--displacement needs --mesh")),
+            Settings::from_args(args("--displacement noise")).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn settings_particles() {
+        let settings =
+            Settings::from_args(args("--particles splash.ply --particle-radius 0.002")).unwrap();
+
+        assert_eq!(Some(PathBuf::from("splash.ply")), settings.particles);
+        assert_eq!(0.002, settings.particle_radius);
+        for invalid in ["big", "-0.1", "0", "nan", "inf"] {
+            let error = Settings::from_args(args(&format!("--particle-radius {}", invalid)));
+            assert!(
+                error.err().unwrap().starts_with("invalid value"),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn settings_distributed() {
+        let settings =
+            Settings::from_args(args("--serve 0.0.0.0:7878 --worker-timeout 5")).unwrap();
+
+        assert_eq!(Some(String::from("0.0.0.0:7878")), settings.serve);
+        assert_eq!(5, settings.worker_timeout);
+        for invalid in ["0", "-1", "soon"] {
+            let error = Settings::from_args(args(&format!("--worker-timeout {}", invalid)));
+            assert!(
+                error.err().unwrap().starts_with("invalid value"),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn settings_errors() {
+        assert!(Settings::from_args(args("--volume")).is_err());
+        assert!(Settings::from_args(args("--volume-density thick")).is_err());
+        assert!(Settings::from_args(args("--sampler uniform")).is_err());
+        assert!(Settings::from_args(args("--tile-order random")).is_err());
+        assert!(Settings::from_args(args("--bogus")).is_err());
+    }
+}
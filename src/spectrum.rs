@@ -0,0 +1,260 @@
+use std::ops::{Add, AddAssign, Mul};
+
+use crate::color::{adapt, ColorSpace, Matrix3};
+use crate::vector::Color;
+
+// The visible range that wavelengths are sampled from, in nanometers
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+// Wavelengths carried by every path
+pub const N_WAVELENGTHS: usize = 4;
+
+// Integral of the CIE Y matching function over the visible range
+const CIE_Y_INTEGRAL: f64 = 106.922;
+
+// The wavelengths of a path: a hero wavelength and others spaced evenly over the visible
+// range from it. A pdf of zero marks a wavelength that no longer contributes
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SampledWavelengths {
+    pub lambda: [f64; N_WAVELENGTHS],
+    pub pdf: [f64; N_WAVELENGTHS],
+}
+
+impl SampledWavelengths {
+    pub fn sample_uniform(u: f64) -> SampledWavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let lambda = std::array::from_fn(|i| {
+            let offset = (u + i as f64 / N_WAVELENGTHS as f64).fract();
+            LAMBDA_MIN + offset * range
+        });
+        SampledWavelengths {
+            lambda,
+            pdf: [1.0 / range; N_WAVELENGTHS],
+        }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    // Keeps only the hero wavelength, for when the path splits up by wavelength as in
+    // dispersion. The hero then stands in for all of them
+    pub fn terminate_secondary(&mut self) {
+        if self.is_terminated() {
+            return;
+        }
+        self.pdf[0] /= N_WAVELENGTHS as f64;
+        self.pdf[1..].fill(0.0);
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|&pdf| pdf == 0.0)
+    }
+}
+
+// Values of a spectrum at the wavelengths of a path
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SampledSpectrum(pub [f64; N_WAVELENGTHS]);
+
+impl SampledSpectrum {
+    pub fn zero() -> SampledSpectrum {
+        SampledSpectrum([0.0; N_WAVELENGTHS])
+    }
+
+    // A linear sRGB color, such as a reflectance, upsampled to a smooth spectrum
+    pub fn from_srgb(color: Color, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        SampledSpectrum(wavelengths.lambda.map(|lambda| upsample(color, lambda)))
+    }
+
+    // Estimates the CIE XYZ color of the spectrum, relative to a flat spectrum of one having
+    // a Y of one
+    pub fn to_xyz(self, wavelengths: &SampledWavelengths) -> Color {
+        let mut xyz = Color::zero();
+        for i in 0..N_WAVELENGTHS {
+            if wavelengths.pdf[i] > 0.0 {
+                xyz += cie_xyz(wavelengths.lambda[i]) * (self.0[i] / wavelengths.pdf[i]);
+            }
+        }
+        xyz / (N_WAVELENGTHS as f64 * CIE_Y_INTEGRAL)
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn add(self, other: SampledSpectrum) -> SampledSpectrum {
+        SampledSpectrum(std::array::from_fn(|i| self.0[i] + other.0[i]))
+    }
+}
+
+impl AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, other: SampledSpectrum) {
+        *self = *self + other;
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn mul(self, other: SampledSpectrum) -> SampledSpectrum {
+        SampledSpectrum(std::array::from_fn(|i| self.0[i] * other.0[i]))
+    }
+}
+
+// Converts XYZ from SampledSpectrum::to_xyz to linear RGB in the space. Spectra are lit by
+// an equal energy white, which is adapted to the white of the space so that a flat spectrum
+// becomes white
+pub fn xyz_to_rgb(space: ColorSpace) -> Matrix3 {
+    let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+    let mut white = Color::zero();
+    for i in 0..steps {
+        white += cie_xyz(LAMBDA_MIN + i as f64 + 0.5);
+    }
+    let to_xyz = space.to_xyz();
+    let adaptation = adapt(white / CIE_Y_INTEGRAL, to_xyz * Color::one());
+    to_xyz.inverse() * adaptation
+}
+
+// CIE 1931 color matching functions, in the multi-lobe Gaussian fit of Wyman, Sloan and
+// Shirley
+pub fn cie_xyz(lambda: f64) -> Color {
+    let g = |mu: f64, sigma_below: f64, sigma_above: f64| {
+        let sigma = if lambda < mu {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Color::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+// Smits' basis spectra in ten bins from 380 to 720 nm
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+// Smits' RGB to spectrum conversion: white for the smallest channel, then the secondary
+// and primary colors for the rest. Interpolates between the bins so the spectra are smooth
+fn upsample(color: Color, lambda: f64) -> f64 {
+    let bin = |spectrum: &[f64; 10]| {
+        let t = ((lambda - 380.0) / (720.0 - 380.0) * 10.0 - 0.5).clamp(0.0, 9.0);
+        let i = (t as usize).min(8);
+        let f = t - i as f64;
+        spectrum[i] * (1.0 - f) + spectrum[i + 1] * f
+    };
+    let Color { x: r, y: g, z: b } = color;
+    if r <= g && r <= b {
+        let rest = if g <= b {
+            (g - r) * bin(&SMITS_CYAN) + (b - g) * bin(&SMITS_BLUE)
+        } else {
+            (b - r) * bin(&SMITS_CYAN) + (g - b) * bin(&SMITS_GREEN)
+        };
+        r * bin(&SMITS_WHITE) + rest
+    } else if g <= r && g <= b {
+        let rest = if r <= b {
+            (r - g) * bin(&SMITS_MAGENTA) + (b - r) * bin(&SMITS_BLUE)
+        } else {
+            (b - g) * bin(&SMITS_MAGENTA) + (r - b) * bin(&SMITS_RED)
+        };
+        g * bin(&SMITS_WHITE) + rest
+    } else {
+        let rest = if r <= g {
+            (r - b) * bin(&SMITS_YELLOW) + (g - r) * bin(&SMITS_GREEN)
+        } else {
+            (g - b) * bin(&SMITS_YELLOW) + (r - g) * bin(&SMITS_RED)
+        };
+        b * bin(&SMITS_WHITE) + rest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+
+    // The RGB color of an upsampled color, integrated over many wavelengths
+    fn round_trip(color: Color) -> Color {
+        let mut xyz = Color::zero();
+        let n = 1000;
+        for i in 0..n {
+            let wavelengths = SampledWavelengths::sample_uniform((i as f64 + 0.5) / n as f64);
+            xyz += SampledSpectrum::from_srgb(color, &wavelengths).to_xyz(&wavelengths);
+        }
+        xyz_to_rgb(ColorSpace::Srgb) * (xyz / n as f64)
+    }
+
+    #[test]
+    fn spectrum_hero_wavelengths() {
+        let wavelengths = SampledWavelengths::sample_uniform(0.9);
+
+        assert_approx_eq!(783.0, wavelengths.hero(), 1e-9);
+        assert_approx_eq!(360.0 + 0.15 * 470.0, wavelengths.lambda[1], 1e-9);
+        assert!(wavelengths
+            .lambda
+            .iter()
+            .all(|l| (LAMBDA_MIN..LAMBDA_MAX).contains(l)));
+    }
+
+    #[test]
+    fn spectrum_terminate_secondary() {
+        let mut wavelengths = SampledWavelengths::sample_uniform(0.3);
+        let full = SampledSpectrum([1.0, 0.0, 0.0, 0.0]).to_xyz(&wavelengths);
+        wavelengths.terminate_secondary();
+        wavelengths.terminate_secondary();
+
+        assert!(wavelengths.is_terminated());
+        // The hero now counts for all wavelengths
+        let hero = SampledSpectrum([1.0, 5.0, 5.0, 5.0]).to_xyz(&wavelengths);
+        assert_approx_eq!(4.0 * full.y, hero.y, 1e-12);
+    }
+
+    #[test]
+    fn spectrum_white_is_flat() {
+        for lambda in [360.0, 400.0, 550.0, 700.0, 829.0] {
+            assert_approx_eq!(1.0, upsample(Color::one(), lambda), 1e-3);
+        }
+        let white = round_trip(Color::one() * 0.5);
+        assert_approx_eq!(0.5, white.x, 1e-3);
+        assert_approx_eq!(0.5, white.y, 1e-3);
+        assert_approx_eq!(0.5, white.z, 1e-3);
+    }
+
+    #[test]
+    fn spectrum_round_trip() {
+        for color in [
+            Color::new(0.8, 0.3, 0.2),
+            Color::new(0.2, 0.5, 0.9),
+            Color::new(0.5, 0.7, 1.0),
+            Color::new(0.1, 0.6, 0.1),
+        ] {
+            let result = round_trip(color);
+            assert_approx_eq!(color.x, result.x, 0.06);
+            assert_approx_eq!(color.y, result.y, 0.06);
+            assert_approx_eq!(color.z, result.z, 0.06);
+        }
+    }
+}
@@ -1,20 +1,37 @@
-use crate::hittable::{HitRecord, Hittable};
+use std::f64::consts::PI;
+
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::{Crossing, HitRecord, Hittable, Solid, Span};
 use crate::ray::Ray;
 use crate::vector::Vec3;
 
 pub struct Sphere {
     center: Vec3,
     radius: f64,
+    material: usize,
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius: f64) -> Sphere {
-        Sphere { center, radius }
+    pub fn new(center: Vec3, radius: f64, material: usize) -> Sphere {
+        Sphere {
+            center,
+            radius,
+            material,
+        }
+    }
+
+    // U around the y axis starting from -x, v from the bottom to the top
+    pub fn uv(normal: Vec3) -> (f64, f64) {
+        let phi = (-normal.z).atan2(normal.x) + PI;
+        let theta = (-normal.y).clamp(-1.0, 1.0).acos();
+        (phi / (2.0 * PI), theta / PI)
     }
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
         let oc = ray.origin - self.center;
         let a = ray.direction.length_squared();
         let half_b = oc.dot(ray.direction);
@@ -29,7 +46,10 @@ impl Hittable for Sphere {
                 let p = ray.at(t);
                 let outward_normal = (p - self.center) / self.radius;
                 let front_face = ray.direction.dot(outward_normal) < 0.0;
-                return Some(HitRecord::new(p, outward_normal, t, front_face));
+                let (u, v) = Sphere::uv(outward_normal);
+                return Some(
+                    HitRecord::new(p, outward_normal, t, front_face, self.material).with_uv(u, v),
+                );
             }
             temp = (-half_b + root) / a;
             if temp < t_max && temp > t_min {
@@ -37,10 +57,48 @@ impl Hittable for Sphere {
                 let p = ray.at(t);
                 let outward_normal = (p - self.center) / self.radius;
                 let front_face = ray.direction.dot(outward_normal) < 0.0;
-                return Some(HitRecord::new(p, outward_normal, t, front_face));
+                let (u, v) = Sphere::uv(outward_normal);
+                return Some(
+                    HitRecord::new(p, outward_normal, t, front_face, self.material).with_uv(u, v),
+                );
             }
         }
 
         None
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius.abs();
+        let extent = Vec3::new(r, r, r);
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+}
+impl Solid for Sphere {
+    fn spans(&self, ray: &Ray, _rng: &mut dyn RngCore) -> Vec<Span> {
+        let oc = ray.origin - self.center;
+        let a = ray.direction.length_squared();
+        let half_b = oc.dot(ray.direction);
+        let c = oc.length_squared() - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant <= 0.0 {
+            return Vec::new();
+        }
+
+        let root = discriminant.sqrt();
+        let crossing = |t: f64| {
+            let normal = (ray.at(t) - self.center) / self.radius;
+            let (u, v) = Sphere::uv(normal);
+            Crossing {
+                t,
+                normal,
+                material: self.material,
+                u,
+                v,
+            }
+        };
+        vec![Span {
+            enter: crossing((-half_b - root) / a),
+            exit: crossing((-half_b + root) / a),
+        }]
+    }
 }
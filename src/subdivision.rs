@@ -0,0 +1,485 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use crate::vector::Point3;
+
+// Every level multiplies the faces by four, so past this even a cube has hundreds of millions
+pub const MAX_LEVEL: u32 = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Scheme {
+    // Triangles, other polygons are split into triangles first
+    Loop,
+    // Any polygons, all quads after the first level
+    CatmullClark,
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Scheme, String> {
+        match s {
+            "loop" => Ok(Scheme::Loop),
+            "catmull-clark" => Ok(Scheme::CatmullClark),
+            _ => Err(format!("unknown subdivision scheme {}", s)),
+        }
+    }
+}
+
+// An edge between two positions, the lower index first
+pub type Edge = (usize, usize);
+
+pub fn edge(a: usize, b: usize) -> Edge {
+    (a.min(b), a.max(b))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Corner {
+    pub position: usize,
+    pub uv: Option<usize>,
+}
+
+// A mesh of polygons that share positions. UVs belong to the corners of faces, so faces on
+// either side of a seam can have their own
+#[derive(Debug, Clone, Default)]
+pub struct PolyMesh {
+    pub positions: Vec<Point3>,
+    pub uvs: Vec<(f64, f64)>,
+    // Counter-clockwise seen from outside
+    pub faces: Vec<Vec<Corner>>,
+    // Edges that stay sharp. Edges on the boundary, with a single face, always are
+    pub creases: HashSet<Edge>,
+}
+
+// Which edges and faces meet where, in the order they first appear in the faces
+pub struct Topology {
+    pub edges: Vec<Edge>,
+    pub edge_faces: Vec<Vec<usize>>,
+    pub edge_index: HashMap<Edge, usize>,
+    pub vertex_edges: Vec<Vec<usize>>,
+    pub vertex_faces: Vec<Vec<usize>>,
+}
+
+impl Topology {
+    pub fn new(mesh: &PolyMesh) -> Topology {
+        let mut topology = Topology {
+            edges: Vec::new(),
+            edge_faces: Vec::new(),
+            edge_index: HashMap::new(),
+            vertex_edges: vec![Vec::new(); mesh.positions.len()],
+            vertex_faces: vec![Vec::new(); mesh.positions.len()],
+        };
+        for (f, face) in mesh.faces.iter().enumerate() {
+            for (i, corner) in face.iter().enumerate() {
+                topology.vertex_faces[corner.position].push(f);
+                let next = face[(i + 1) % face.len()].position;
+                let key = edge(corner.position, next);
+                let index = *topology.edge_index.entry(key).or_insert_with(|| {
+                    topology.edges.push(key);
+                    topology.edge_faces.push(Vec::new());
+                    topology.vertex_edges[key.0].push(topology.edges.len() - 1);
+                    topology.vertex_edges[key.1].push(topology.edges.len() - 1);
+                    topology.edges.len() - 1
+                });
+                topology.edge_faces[index].push(f);
+            }
+        }
+        topology
+    }
+
+    // Creased, on the boundary, or shared by more than two faces
+    pub fn is_sharp(&self, mesh: &PolyMesh, index: usize) -> bool {
+        self.edge_faces[index].len() != 2 || mesh.creases.contains(&self.edges[index])
+    }
+
+    // The other ends of the sharp edges at a position
+    fn sharp_neighbours(&self, mesh: &PolyMesh, position: usize) -> Vec<usize> {
+        self.vertex_edges[position]
+            .iter()
+            .filter(|&&e| self.is_sharp(mesh, e))
+            .map(|&e| other_end(self.edges[e], position))
+            .collect()
+    }
+}
+
+fn other_end(edge: Edge, position: usize) -> usize {
+    if edge.0 == position {
+        edge.1
+    } else {
+        edge.0
+    }
+}
+
+fn average(points: impl Iterator<Item = Point3>) -> Point3 {
+    let (sum, count) = points.fold((Point3::zero(), 0), |(sum, n), p| (sum + p, n + 1));
+    sum / count as f64
+}
+
+impl PolyMesh {
+    pub fn subdivided(&self, scheme: Scheme, levels: u32) -> PolyMesh {
+        let mut mesh = self.clone();
+        for _ in 0..levels {
+            mesh = match scheme {
+                Scheme::Loop => mesh.triangulated().loop_step(),
+                Scheme::CatmullClark => mesh.catmull_clark_step(),
+            };
+        }
+        mesh
+    }
+
+    // Polygons split into fans of triangles
+    pub fn triangulated(&self) -> PolyMesh {
+        let faces = self
+            .faces
+            .iter()
+            .flat_map(|face| (1..face.len() - 1).map(move |i| vec![face[0], face[i], face[i + 1]]))
+            .collect();
+        PolyMesh {
+            faces,
+            ..self.clone()
+        }
+    }
+
+    fn uv(&self, corner: Corner) -> Option<(f64, f64)> {
+        corner.uv.map(|uv| self.uvs[uv])
+    }
+
+    // Adds the average of the UVs of the corners, if they all have one. UVs are only ever
+    // interpolated linearly, the original corners keep theirs where the positions are smoothed
+    fn add_uv_between(uvs: &mut Vec<(f64, f64)>, corners: &[Option<(f64, f64)>]) -> Option<usize> {
+        let sum = corners
+            .iter()
+            .try_fold((0.0, 0.0), |(u, v), uv| uv.map(|uv| (u + uv.0, v + uv.1)))?;
+        let n = corners.len() as f64;
+        uvs.push((sum.0 / n, sum.1 / n));
+        Some(uvs.len() - 1)
+    }
+
+    // Both halves of every crease, split at the points added on the edges
+    fn split_creases(&self, topology: &Topology, first_edge_point: usize) -> HashSet<Edge> {
+        self.creases
+            .iter()
+            .filter_map(|crease| topology.edge_index.get(crease))
+            .flat_map(|&e| {
+                let (a, b) = topology.edges[e];
+                let middle = first_edge_point + e;
+                [edge(a, middle), edge(middle, b)]
+            })
+            .collect()
+    }
+
+    // Where a position moves to along sharp edges: corners where three or more meet stay put,
+    // as do boundary corners between just two edges, and creases pull it along their line
+    fn sharp_rule(&self, topology: &Topology, position: usize) -> Option<Point3> {
+        let sharp = topology.sharp_neighbours(self, position);
+        let v = self.positions[position];
+        match sharp.len() {
+            0 | 1 => None,
+            2 if topology.vertex_edges[position].len() == 2 => Some(v),
+            2 => Some(v * 0.75 + (self.positions[sharp[0]] + self.positions[sharp[1]]) * 0.125),
+            _ => Some(v),
+        }
+    }
+
+    // Positions are the moved originals, then a new point on every edge. Each triangle is split
+    // into four
+    fn loop_step(&self) -> PolyMesh {
+        let topology = Topology::new(self);
+        let p = &self.positions;
+
+        let mut positions: Vec<Point3> = (0..p.len())
+            .map(|v| {
+                if let Some(moved) = self.sharp_rule(&topology, v) {
+                    return moved;
+                }
+                let neighbours = &topology.vertex_edges[v];
+                if neighbours.is_empty() {
+                    return p[v];
+                }
+                let n = neighbours.len() as f64;
+                let beta = if neighbours.len() == 3 {
+                    3.0 / 16.0
+                } else {
+                    3.0 / (8.0 * n)
+                };
+                let sum = neighbours
+                    .iter()
+                    .map(|&e| p[other_end(topology.edges[e], v)])
+                    .fold(Point3::zero(), |sum, q| sum + q);
+                p[v] * (1.0 - n * beta) + sum * beta
+            })
+            .collect();
+        for (e, &(a, b)) in topology.edges.iter().enumerate() {
+            let middle = if topology.is_sharp(self, e) {
+                (p[a] + p[b]) * 0.5
+            } else {
+                // The corners across from the edge in its two triangles
+                let opposite = topology.edge_faces[e].iter().map(|&f| {
+                    let face = &self.faces[f];
+                    face.iter()
+                        .find(|c| c.position != a && c.position != b)
+                        .map_or(p[a], |c| p[c.position])
+                });
+                (p[a] + p[b]) * 0.375 + opposite.fold(Point3::zero(), |sum, q| sum + q) * 0.125
+            };
+            positions.push(middle);
+        }
+
+        let first_edge_point = p.len();
+        let mut uvs = self.uvs.clone();
+        let mut faces = Vec::with_capacity(self.faces.len() * 4);
+        for face in &self.faces {
+            let middles: Vec<Corner> = (0..3)
+                .map(|i| {
+                    let (a, b) = (face[i], face[(i + 1) % 3]);
+                    let e = topology.edge_index[&edge(a.position, b.position)];
+                    Corner {
+                        position: first_edge_point + e,
+                        uv: PolyMesh::add_uv_between(&mut uvs, &[self.uv(a), self.uv(b)]),
+                    }
+                })
+                .collect();
+            for i in 0..3 {
+                faces.push(vec![face[i], middles[i], middles[(i + 2) % 3]]);
+            }
+            faces.push(middles);
+        }
+
+        PolyMesh {
+            positions,
+            uvs,
+            faces,
+            creases: self.split_creases(&topology, first_edge_point),
+        }
+    }
+
+    // Positions are the moved originals, a new point in every face, then one on every edge.
+    // Each polygon is split into a quad for every corner
+    fn catmull_clark_step(&self) -> PolyMesh {
+        let topology = Topology::new(self);
+        let p = &self.positions;
+        let face_points: Vec<Point3> = self
+            .faces
+            .iter()
+            .map(|face| average(face.iter().map(|c| p[c.position])))
+            .collect();
+
+        let mut positions: Vec<Point3> = (0..p.len())
+            .map(|v| {
+                if let Some(moved) = self.sharp_rule(&topology, v) {
+                    return moved;
+                }
+                let edges = &topology.vertex_edges[v];
+                if edges.is_empty() {
+                    return p[v];
+                }
+                let n = edges.len() as f64;
+                let faces = average(topology.vertex_faces[v].iter().map(|&f| face_points[f]));
+                let middles = average(edges.iter().map(|&e| {
+                    let (a, b) = topology.edges[e];
+                    (p[a] + p[b]) * 0.5
+                }));
+                (faces + middles * 2.0 + p[v] * (n - 3.0)) / n
+            })
+            .collect();
+        positions.extend(&face_points);
+        for (e, &(a, b)) in topology.edges.iter().enumerate() {
+            let middle = if topology.is_sharp(self, e) {
+                (p[a] + p[b]) * 0.5
+            } else {
+                let faces = &topology.edge_faces[e];
+                (p[a] + p[b] + face_points[faces[0]] + face_points[faces[1]]) * 0.25
+            };
+            positions.push(middle);
+        }
+
+        let first_face_point = p.len();
+        let first_edge_point = first_face_point + self.faces.len();
+        let mut uvs = self.uvs.clone();
+        let mut faces = Vec::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            let corner_uvs: Vec<_> = face.iter().map(|&c| self.uv(c)).collect();
+            let center = Corner {
+                position: first_face_point + f,
+                uv: PolyMesh::add_uv_between(&mut uvs, &corner_uvs),
+            };
+            let middles: Vec<Corner> = (0..face.len())
+                .map(|i| {
+                    let (a, b) = (face[i], face[(i + 1) % face.len()]);
+                    let e = topology.edge_index[&edge(a.position, b.position)];
+                    Corner {
+                        position: first_edge_point + e,
+                        uv: PolyMesh::add_uv_between(&mut uvs, &[self.uv(a), self.uv(b)]),
+                    }
+                })
+                .collect();
+            for i in 0..face.len() {
+                let before = middles[(i + face.len() - 1) % face.len()];
+                faces.push(vec![face[i], middles[i], center, before]);
+            }
+        }
+
+        PolyMesh {
+            positions,
+            uvs,
+            faces,
+            creases: self.split_creases(&topology, first_edge_point),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+
+    fn corners(positions: &[usize]) -> Vec<Corner> {
+        positions
+            .iter()
+            .map(|&position| Corner { position, uv: None })
+            .collect()
+    }
+
+    // A cube from -1 to 1, its faces counter-clockwise from outside
+    pub fn cube() -> PolyMesh {
+        let positions = (0..8)
+            .map(|i| {
+                let side = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+                Point3::new(side(1), side(2), side(4))
+            })
+            .collect();
+        let faces = [
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+        ]
+        .iter()
+        .map(|face| corners(face))
+        .collect();
+        PolyMesh {
+            positions,
+            faces,
+            ..PolyMesh::default()
+        }
+    }
+
+    fn every_edge_creased(mesh: PolyMesh) -> PolyMesh {
+        let creases = Topology::new(&mesh).edges.into_iter().collect();
+        PolyMesh { creases, ..mesh }
+    }
+
+    #[test]
+    fn subdivision_catmull_clark_cube() {
+        let once = cube().subdivided(Scheme::CatmullClark, 1);
+        assert_eq!(8 + 6 + 12, once.positions.len());
+        assert_eq!(24, once.faces.len());
+        assert!(once.faces.iter().all(|face| face.len() == 4));
+
+        // The cube shrinks towards a sphere, corners more than faces
+        let twice = cube().subdivided(Scheme::CatmullClark, 2);
+        for p in &twice.positions {
+            assert!(p.length() < 3.0f64.sqrt() - 0.1);
+            assert!(p.length() > 0.7);
+        }
+        // The moved corner: (F + 2R + (n - 3) v) / n with faces at 2/3 and edges at 1/2
+        assert_approx_eq!(-5.0 / 9.0, once.positions[0].x, 1e-12);
+    }
+
+    #[test]
+    fn subdivision_creases_keep_the_cube() {
+        let mesh = every_edge_creased(cube()).subdivided(Scheme::CatmullClark, 3);
+        for p in &mesh.positions {
+            let furthest = p.x.abs().max(p.y.abs()).max(p.z.abs());
+            assert_approx_eq!(1.0, furthest, 1e-12);
+        }
+        let loop_mesh = every_edge_creased(cube()).subdivided(Scheme::Loop, 2);
+        for p in &loop_mesh.positions {
+            let furthest = p.x.abs().max(p.y.abs()).max(p.z.abs());
+            assert_approx_eq!(1.0, furthest, 1e-12);
+        }
+    }
+
+    #[test]
+    fn subdivision_loop_tetrahedron() {
+        let positions = vec![
+            Point3::new(1.0, 1.0, 1.0),
+            Point3::new(1.0, -1.0, -1.0),
+            Point3::new(-1.0, 1.0, -1.0),
+            Point3::new(-1.0, -1.0, 1.0),
+        ];
+        let faces = [[0, 1, 2], [0, 3, 1], [0, 2, 3], [1, 3, 2]]
+            .iter()
+            .map(|face| corners(face))
+            .collect();
+        let mesh = PolyMesh {
+            positions,
+            faces,
+            ..PolyMesh::default()
+        };
+
+        let once = mesh.subdivided(Scheme::Loop, 1);
+        assert_eq!(4 + 6, once.positions.len());
+        assert_eq!(16, once.faces.len());
+        // Valence three: 1 - 3 · 3/16 of itself and 3/16 of each neighbour
+        assert_approx_eq!(0.25, once.positions[0].x, 1e-12);
+        // Edges: 3/8 of both ends and 1/8 of both opposite corners
+        let middle = once.positions[4];
+        assert_approx_eq!(0.5, middle.x, 1e-12);
+        assert_approx_eq!(0.0, middle.y, 1e-12);
+        assert_approx_eq!(0.0, middle.z, 1e-12);
+        // Winding stays outwards
+        for face in &once.faces {
+            let [a, b, c] = [0, 1, 2].map(|i| once.positions[face[i].position]);
+            let normal = (b - a).cross(c - a);
+            assert!(normal.dot(a + b + c) > 0.0);
+        }
+    }
+
+    #[test]
+    fn subdivision_boundary_and_uvs() {
+        // A single quad in the plane z = 0 with UVs across it
+        let mesh = PolyMesh {
+            positions: vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(1.0, 1.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            uvs: vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+            faces: vec![(0..4)
+                .map(|i| Corner {
+                    position: i,
+                    uv: Some(i),
+                })
+                .collect()],
+            creases: HashSet::new(),
+        };
+
+        for scheme in [Scheme::CatmullClark, Scheme::Loop] {
+            let subdivided = mesh.subdivided(scheme, 2);
+            // Everything stays flat
+            assert!(subdivided.positions.iter().all(|p| p.z == 0.0));
+            // UVs follow the positions
+            for face in &subdivided.faces {
+                for corner in face {
+                    let p = subdivided.positions[corner.position];
+                    let (u, v) = subdivided.uvs[corner.uv.unwrap()];
+                    assert_approx_eq!(p.x, u, 0.2);
+                    assert_approx_eq!(p.y, v, 0.2);
+                }
+            }
+        }
+        // Corners between two boundary edges stay put
+        let subdivided = mesh.subdivided(Scheme::CatmullClark, 2);
+        assert_eq!(&mesh.positions[..], &subdivided.positions[..4]);
+    }
+
+    #[test]
+    fn subdivision_scheme_from_str() {
+        assert_eq!(Ok(Scheme::CatmullClark), "catmull-clark".parse());
+        assert!("doo-sabin".parse::<Scheme>().is_err());
+    }
+}
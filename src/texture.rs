@@ -0,0 +1,231 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::ppm;
+use crate::vector::{Point3, Vec3};
+
+// A value from 0 to 1 for a point on a surface, found from its texture coordinates or from
+// where it is in space
+pub trait ScalarTexture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: Point3) -> f64;
+}
+
+// Where a scalar texture comes from: generated noise or a greyscale image file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Noise,
+    Image(PathBuf),
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Source, String> {
+        match s {
+            "noise" => Ok(Source::Noise),
+            "" => Err(String::from("unknown texture source")),
+            _ => Ok(Source::Image(PathBuf::from(s))),
+        }
+    }
+}
+
+impl Source {
+    pub fn load(&self) -> io::Result<Box<dyn ScalarTexture>> {
+        Ok(match self {
+            Source::Noise => Box::new(Noise::new(4.0, 5)),
+            Source::Image(path) => Box::new(Image::load(path)?),
+        })
+    }
+}
+
+// Perlin gradient noise summed over octaves, each twice the frequency and half the amplitude
+// of the one before. The gradients come from hashing the lattice points, so the noise is the
+// same everywhere it is used without tables or a seed
+pub struct Noise {
+    frequency: f64,
+    octaves: u32,
+}
+
+impl Noise {
+    pub fn new(frequency: f64, octaves: u32) -> Noise {
+        Noise { frequency, octaves }
+    }
+}
+
+impl ScalarTexture for Noise {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> f64 {
+        let (mut sum, mut total) = (0.0, 0.0);
+        let (mut frequency, mut amplitude) = (self.frequency, 1.0);
+        for _ in 0..self.octaves.max(1) {
+            sum += perlin(p * frequency) * amplitude;
+            total += amplitude;
+            frequency *= 2.0;
+            amplitude *= 0.5;
+        }
+        (0.5 + 0.5 * sum / total).clamp(0.0, 1.0)
+    }
+}
+
+fn hash(x: i64, y: i64, z: i64) -> u64 {
+    let mut h = (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+        ^ (z as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+    h ^= h >> 31;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 29;
+    h
+}
+
+// One of the twelve directions to the edges of a cube, as in improved Perlin noise
+fn gradient(x: i64, y: i64, z: i64) -> Vec3 {
+    match hash(x, y, z) % 12 {
+        0 => Vec3::new(1.0, 1.0, 0.0),
+        1 => Vec3::new(-1.0, 1.0, 0.0),
+        2 => Vec3::new(1.0, -1.0, 0.0),
+        3 => Vec3::new(-1.0, -1.0, 0.0),
+        4 => Vec3::new(1.0, 0.0, 1.0),
+        5 => Vec3::new(-1.0, 0.0, 1.0),
+        6 => Vec3::new(1.0, 0.0, -1.0),
+        7 => Vec3::new(-1.0, 0.0, -1.0),
+        8 => Vec3::new(0.0, 1.0, 1.0),
+        9 => Vec3::new(0.0, -1.0, 1.0),
+        10 => Vec3::new(0.0, 1.0, -1.0),
+        _ => Vec3::new(0.0, -1.0, -1.0),
+    }
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+// Roughly from -1 to 1, zero on the lattice points
+fn perlin(p: Point3) -> f64 {
+    let cell = [p.x.floor(), p.y.floor(), p.z.floor()];
+    let [x, y, z] = cell.map(|c| c as i64);
+    let f = p - Point3::new(cell[0], cell[1], cell[2]);
+    let corner = |i: i64, j: i64, k: i64| {
+        gradient(x + i, y + j, z + k).dot(f - Vec3::new(i as f64, j as f64, k as f64))
+    };
+    let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), u),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), u),
+            v,
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), u),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), u),
+            v,
+        ),
+        w,
+    )
+}
+
+// Grey levels looked up by texture coordinates, filtered bilinearly and repeating in both
+// directions. v runs from the bottom row up, as in OBJ files
+pub struct Image {
+    width: usize,
+    height: usize,
+    values: Vec<f64>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, values: Vec<f64>) -> Image {
+        assert_eq!(width * height, values.len());
+        Image {
+            width,
+            height,
+            values,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        let (width, height, values) = ppm::read_grey(path)?;
+        if width == 0 || height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty image"));
+        }
+        Ok(Image::new(width as usize, height as usize, values))
+    }
+
+    fn texel(&self, x: i64, y: i64) -> f64 {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.values[y * self.width + x]
+    }
+}
+
+impl ScalarTexture for Image {
+    fn value(&self, u: f64, v: f64, _p: Point3) -> f64 {
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        lerp(
+            lerp(self.texel(x0, y0), self.texel(x0 + 1, y0), tx),
+            lerp(self.texel(x0, y0 + 1), self.texel(x0 + 1, y0 + 1), tx),
+            ty,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+    use rand::prelude::*;
+
+    #[test]
+    fn texture_noise() {
+        let noise = Noise::new(3.0, 4);
+        let mut rng = StdRng::seed_from_u64(2);
+        let (mut low, mut high) = (1.0f64, 0.0f64);
+        for _ in 0..1000 {
+            let p = Vec3::random_unit_vector(&mut rng) * 2.0;
+            let value = noise.value(0.0, 0.0, p);
+            assert!((0.0..=1.0).contains(&value));
+            assert_eq!(value, noise.value(0.5, 0.5, p));
+            // Continuous, nearby points have nearby values
+            let nearby = noise.value(0.0, 0.0, p + Vec3::new(1e-6, 0.0, 0.0));
+            assert!((value - nearby).abs() < 1e-3);
+            low = low.min(value);
+            high = high.max(value);
+        }
+        assert!(high - low > 0.3);
+
+        // A single octave is flat on the lattice
+        let lattice = Noise::new(1.0, 1);
+        assert_eq!(0.5, lattice.value(0.0, 0.0, Point3::new(3.0, -2.0, 7.0)));
+    }
+
+    #[test]
+    fn texture_image() {
+        // Black on the bottom row, white on the top
+        let image = Image::new(2, 2, vec![1.0, 1.0, 0.0, 0.0]);
+        let p = Point3::zero();
+        assert_approx_eq!(1.0, image.value(0.25, 0.75, p), 1e-12);
+        assert_approx_eq!(0.0, image.value(0.75, 0.25, p), 1e-12);
+        assert_approx_eq!(0.5, image.value(0.3, 0.5, p), 1e-12);
+        // Repeating, so halfway between the top and the bottom row again across the edge
+        assert_approx_eq!(0.5, image.value(0.3, 1.0, p), 1e-12);
+        assert_approx_eq!(image.value(0.1, 0.6, p), image.value(2.1, -0.4, p), 1e-12);
+    }
+
+    #[test]
+    fn texture_source() {
+        assert_eq!(Ok(Source::Noise), "noise".parse());
+        assert_eq!(
+            Ok(Source::Image(PathBuf::from("rock.pgm"))),
+            "rock.pgm".parse()
+        );
+        assert!(Source::Image(PathBuf::from("/nonexistent.pgm"))
+            .load()
+            .is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    // Pixel coordinates of the tile, row by row
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let tile = *self;
+        (tile.y..tile.y + tile.height)
+            .flat_map(move |y| (tile.x..tile.x + tile.width).map(move |x| (x, y)))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TileOrder {
+    // Outwards from the center of the image
+    Spiral,
+    Hilbert,
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TileOrder, String> {
+        match s {
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!("unknown tile order {}", s)),
+        }
+    }
+}
+
+// Square tiles covering the image in render order, tiles on the right and bottom edges are
+// clipped to the image
+pub fn tiles(image_width: u32, image_height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let columns = image_width.div_ceil(size);
+    let rows = image_height.div_ceil(size);
+
+    let cells = match order {
+        TileOrder::Spiral => spiral(columns, rows),
+        TileOrder::Hilbert => hilbert(columns, rows),
+    };
+
+    cells
+        .into_iter()
+        .map(|(column, row)| {
+            let x = column * size;
+            let y = row * size;
+            Tile {
+                x,
+                y,
+                width: size.min(image_width - x),
+                height: size.min(image_height - y),
+            }
+        })
+        .collect()
+}
+
+fn spiral(columns: u32, rows: u32) -> Vec<(u32, u32)> {
+    let total = (columns * rows) as usize;
+    let mut cells = Vec::with_capacity(total);
+    let (mut x, mut y) = (((columns as i64) - 1) / 2, ((rows as i64) - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let push = |x: i64, y: i64, cells: &mut Vec<(u32, u32)>| {
+        if x >= 0 && y >= 0 && x < columns as i64 && y < rows as i64 {
+            cells.push((x as u32, y as u32));
+        }
+    };
+
+    push(x, y, &mut cells);
+    let mut leg = 0;
+    while cells.len() < total {
+        let (dx, dy) = directions[leg % 4];
+        // Legs grow by one every second turn: 1, 1, 2, 2, 3, 3, ...
+        for _ in 0..leg / 2 + 1 {
+            x += dx;
+            y += dy;
+            push(x, y, &mut cells);
+        }
+        leg += 1;
+    }
+    cells
+}
+
+fn hilbert(columns: u32, rows: u32) -> Vec<(u32, u32)> {
+    let n = columns.max(rows).next_power_of_two();
+    (0..n as u64 * n as u64)
+        .map(|d| hilbert_cell(n, d))
+        .filter(|&(x, y)| x < columns && y < rows)
+        .collect()
+}
+
+// Position of the d-th cell along a Hilbert curve filling an n by n grid
+fn hilbert_cell(n: u32, d: u64) -> (u32, u32) {
+    let (mut x, mut y) = (0u32, 0u32);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = (1 & (t / 2)) as u32;
+        let ry = (1 & (t ^ rx as u64)) as u32;
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn covers_image_once(tiles: &[Tile], width: u32, height: u32) -> bool {
+        let mut covered = vec![0; (width * height) as usize];
+        for tile in tiles {
+            for (x, y) in tile.pixels() {
+                covered[(y * width + x) as usize] += 1;
+            }
+        }
+        covered.iter().all(|&c| c == 1)
+    }
+
+    #[test]
+    fn tiles_cover_image() {
+        for order in [TileOrder::Spiral, TileOrder::Hilbert] {
+            let tiles = tiles(100, 37, 16, order);
+
+            assert_eq!(7 * 3, tiles.len());
+            assert!(covers_image_once(&tiles, 100, 37));
+        }
+    }
+
+    #[test]
+    fn tiles_spiral_starts_in_center() {
+        let tiles = tiles(80, 48, 16, TileOrder::Spiral);
+
+        assert_eq!(
+            Tile {
+                x: 32,
+                y: 16,
+                width: 16,
+                height: 16
+            },
+            tiles[0]
+        );
+        assert_eq!(
+            Tile {
+                x: 48,
+                y: 16,
+                width: 16,
+                height: 16
+            },
+            tiles[1]
+        );
+    }
+
+    #[test]
+    fn tiles_hilbert_steps_to_neighbours() {
+        let tiles = tiles(64, 64, 8, TileOrder::Hilbert);
+
+        for pair in tiles.windows(2) {
+            let distance = pair[0].x.abs_diff(pair[1].x) + pair[0].y.abs_diff(pair[1].y);
+            assert_eq!(8, distance);
+        }
+    }
+
+    #[test]
+    fn tile_order_from_str() {
+        assert_eq!(Ok(TileOrder::Hilbert), "hilbert".parse());
+        assert!("zigzag".parse::<TileOrder>().is_err());
+    }
+}
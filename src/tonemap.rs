@@ -0,0 +1,312 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::color::{ColorSpace, Matrix3};
+use crate::vector::Color;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMap {
+    // Clips at white
+    None,
+    Reinhard,
+    // Reinhard that reaches white at the white point instead of at infinity
+    ReinhardExtended,
+    // Stephen Hill's fit of the ACES reference and sRGB output transforms
+    Aces,
+    // Troy Sobotka's AgX, in the polynomial approximation by Benjamin Wrensch
+    Agx,
+    // John Hable's filmic curve
+    Uncharted2,
+}
+
+impl ToneMap {
+    pub const ALL: [ToneMap; 6] = [
+        ToneMap::None,
+        ToneMap::Reinhard,
+        ToneMap::ReinhardExtended,
+        ToneMap::Aces,
+        ToneMap::Agx,
+        ToneMap::Uncharted2,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneMap::None => "none",
+            ToneMap::Reinhard => "reinhard",
+            ToneMap::ReinhardExtended => "reinhard-extended",
+            ToneMap::Aces => "aces",
+            ToneMap::Agx => "agx",
+            ToneMap::Uncharted2 => "uncharted2",
+        }
+    }
+
+    // The next operator, for cycling through them in the viewer
+    pub fn next(self) -> ToneMap {
+        let index = ToneMap::ALL.iter().position(|&t| t == self).unwrap();
+        ToneMap::ALL[(index + 1) % ToneMap::ALL.len()]
+    }
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ToneMap, String> {
+        ToneMap::ALL
+            .into_iter()
+            .find(|t| t.name() == s)
+            .ok_or(format!("unknown tone map {}", s))
+    }
+}
+
+// Turns linear colors in the working space into display colors: exposure, tone mapping in
+// Rec. 709, which the tone maps are made for, conversion to the output space and its transfer
+// function
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct View {
+    // In stops, every stop doubles the light
+    pub exposure: f64,
+    pub tone_map: ToneMap,
+    // Luminance that maps to white, for extended Reinhard and Uncharted 2
+    pub white_point: f64,
+    // The space colors are rendered in
+    working_space: ColorSpace,
+    output_space: ColorSpace,
+    // From the working space to Rec. 709 and from there to the output space, kept along with
+    // the spaces so they are worked out once
+    to_rec709: Matrix3,
+    to_output: Matrix3,
+}
+
+impl View {
+    pub fn new(
+        exposure: f64,
+        tone_map: ToneMap,
+        white_point: f64,
+        working_space: ColorSpace,
+        output_space: ColorSpace,
+    ) -> View {
+        View {
+            exposure,
+            tone_map,
+            white_point,
+            working_space,
+            output_space,
+            to_rec709: working_space.conversion(ColorSpace::Srgb),
+            to_output: ColorSpace::Srgb.conversion(output_space),
+        }
+    }
+
+    pub fn working_space(&self) -> ColorSpace {
+        self.working_space
+    }
+
+    pub fn set_working_space(&mut self, space: ColorSpace) {
+        *self = View::new(
+            self.exposure,
+            self.tone_map,
+            self.white_point,
+            space,
+            self.output_space,
+        );
+    }
+
+    pub fn set_output_space(&mut self, space: ColorSpace) {
+        *self = View::new(
+            self.exposure,
+            self.tone_map,
+            self.white_point,
+            self.working_space,
+            space,
+        );
+    }
+
+    pub fn apply(&self, color: Color) -> Color {
+        let color = self.to_rec709 * (color * 2f64.powf(self.exposure));
+        let mapped = match self.tone_map {
+            ToneMap::None => color,
+            ToneMap::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMap::ReinhardExtended => {
+                let white = self.white_point * self.white_point;
+                scale_luminance(color, |l| l * (1.0 + l / white) / (1.0 + l))
+            }
+            ToneMap::Aces => aces(color),
+            ToneMap::Agx => agx(color),
+            ToneMap::Uncharted2 => {
+                // The curve is applied with an exposure bias of 2, and so is the white point
+                let white = uncharted2(2.0 * self.white_point);
+                map_channels(color, |x| uncharted2(2.0 * x) / white)
+            }
+        };
+        map_channels(self.to_output * mapped, |x| self.output_space.encode(x))
+    }
+
+    // Converts and encodes for the output space, without exposure or tone mapping
+    pub fn encode(&self, color: Color) -> Color {
+        let color = self.to_output * (self.to_rec709 * color);
+        map_channels(color, |x| self.output_space.encode(x))
+    }
+}
+
+impl fmt::Display for View {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "exposure {:+.1}, tone map {}",
+            self.exposure,
+            self.tone_map.name()
+        )
+    }
+}
+
+fn map_channels(color: Color, f: impl Fn(f64) -> f64) -> Color {
+    Color::new(f(color.x), f(color.y), f(color.z))
+}
+
+// Maps the luminance and scales the color along, which keeps the hue
+fn scale_luminance(color: Color, f: impl Fn(f64) -> f64) -> Color {
+    let luminance = color.luminance();
+    if luminance <= 0.0 {
+        return Color::zero();
+    }
+    color * (f(luminance) / luminance)
+}
+
+fn aces(color: Color) -> Color {
+    const INPUT: Matrix3 = Matrix3([
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ]);
+    const OUTPUT: Matrix3 = Matrix3([
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ]);
+    let fitted = map_channels(INPUT * color, |v| {
+        (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081)
+    });
+    map_channels(OUTPUT * fitted, |v| v.clamp(0.0, 1.0))
+}
+
+fn agx(color: Color) -> Color {
+    const INSET: Matrix3 = Matrix3([
+        [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+        [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+        [0.0423756549057051, 0.0784336, 0.879142973793104],
+    ]);
+    const OUTSET: Matrix3 = Matrix3([
+        [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+        [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+        [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+    ]);
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+
+    let encoded = map_channels(INSET * color, |v| {
+        let x = (v.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+    // The curve gives display values, back to linear for the sRGB encoding
+    map_channels(OUTSET * encoded, |v| v.max(0.0).powf(2.2))
+}
+
+fn uncharted2(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{rec2020_oetf, srgb_oetf};
+    use assert_approx_eq::*;
+
+    fn view(tone_map: ToneMap) -> View {
+        View::new(0.0, tone_map, 11.2, ColorSpace::Srgb, ColorSpace::Srgb)
+    }
+
+    #[test]
+    fn tonemap_exposure_in_stops() {
+        let mut brighter = view(ToneMap::None);
+        brighter.exposure = 1.0;
+
+        assert_eq!(
+            view(ToneMap::None).apply(Color::one() * 0.5),
+            brighter.apply(Color::one() * 0.25)
+        );
+    }
+
+    #[test]
+    fn tonemap_operators_are_monotonic_and_bounded() {
+        for tone_map in ToneMap::ALL {
+            let mut previous = -1.0;
+            for i in 0..200 {
+                let value = view(tone_map).apply(Color::one() * (i as f64 * 0.1)).y;
+                assert!((0.0..=1.0).contains(&value), "{:?}", tone_map);
+                assert!(value >= previous - 1e-6, "{:?} at {}", tone_map, i);
+                previous = value;
+            }
+        }
+    }
+
+    #[test]
+    fn tonemap_white_points() {
+        assert_approx_eq!(
+            1.0,
+            view(ToneMap::Uncharted2).apply(Color::one() * 11.2).x,
+            1e-9
+        );
+        assert!(view(ToneMap::Uncharted2).apply(Color::one() * 5.6).x < 0.99);
+        assert_approx_eq!(
+            1.0,
+            view(ToneMap::ReinhardExtended).apply(Color::one() * 11.2).x,
+            1e-9
+        );
+        // Plain Reinhard maps 1 to a half
+        assert_approx_eq!(
+            srgb_oetf(0.5),
+            view(ToneMap::Reinhard).apply(Color::one()).y,
+            1e-9
+        );
+        assert!(view(ToneMap::Aces).apply(Color::one() * 100.0).x > 0.99);
+        assert!(view(ToneMap::Agx).apply(Color::one() * 0.18).x > 0.3);
+    }
+
+    #[test]
+    fn tonemap_output_spaces() {
+        let mut wide = view(ToneMap::None);
+        wide.set_output_space(ColorSpace::Rec2020);
+        let mut acescg = view(ToneMap::None);
+        acescg.set_working_space(ColorSpace::AcesCg);
+
+        // White stays white in every space, only the encoding differs
+        assert_approx_eq!(rec2020_oetf(0.5), wide.apply(Color::one() * 0.5).y, 1e-9);
+        assert_approx_eq!(srgb_oetf(0.5), acescg.apply(Color::one() * 0.5).z, 1e-9);
+        // sRGB red is a mix in the wider Rec. 2020 primaries
+        assert!(wide.apply(Color::new(1.0, 0.0, 0.0)).y > 0.0);
+        // The ACEScg green primary is outside of sRGB and clips
+        assert_eq!(0.0, acescg.apply(Color::new(0.0, 0.5, 0.0)).x);
+        assert_eq!(wide.encode(Color::one()), wide.apply(Color::one()));
+
+        // Tone maps see Rec. 709 colors whatever the working space
+        let color = Color::new(0.3, 1.5, 4.0);
+        let rec709 = ColorSpace::AcesCg.conversion(ColorSpace::Srgb) * color;
+        for tone_map in [ToneMap::Aces, ToneMap::Agx] {
+            let mut acescg = view(tone_map);
+            acescg.set_working_space(ColorSpace::AcesCg);
+            let expected = view(tone_map).apply(rec709);
+            assert_approx_eq!(expected.x, acescg.apply(color).x, 1e-9);
+            assert_approx_eq!(expected.z, acescg.apply(color).z, 1e-9);
+        }
+    }
+
+    #[test]
+    fn tone_map_from_str() {
+        assert_eq!(Ok(ToneMap::ReinhardExtended), "reinhard-extended".parse());
+        assert!("filmic".parse::<ToneMap>().is_err());
+        assert_eq!(ToneMap::None, ToneMap::Uncharted2.next());
+    }
+}
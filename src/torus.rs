@@ -0,0 +1,380 @@
+use std::f64::consts::PI;
+
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::{pair_crossings, Crossing, HitRecord, Hittable, Solid, Span};
+use crate::quadric::Frame;
+use crate::ray::Ray;
+use crate::vector::{Point3, Vec3};
+
+// Value of the polynomial with the coefficients from the constant term up
+fn evaluate(coefficients: &[f64], x: f64) -> f64 {
+    coefficients.iter().rev().fold(0.0, |sum, &c| sum * x + c)
+}
+
+fn derivative(coefficients: &[f64]) -> Vec<f64> {
+    coefficients
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(power, &c)| c * power as f64)
+        .collect()
+}
+
+// The real roots of the polynomial within the range, in increasing order. Rather than the
+// closed form, which loses most of its precision when roots are close together as for rays
+// grazing a torus, the roots of the derivative split the range into pieces where the
+// polynomial only rises or falls, each holding at most one root that Newton's method finds
+// within a bracket
+fn roots_between(coefficients: &[f64], min: f64, max: f64) -> Vec<f64> {
+    let coefficients = match coefficients.iter().rposition(|&c| c != 0.0) {
+        Some(degree) => &coefficients[..=degree],
+        None => return Vec::new(),
+    };
+    if coefficients.len() == 1 {
+        return Vec::new();
+    }
+    if coefficients.len() == 2 {
+        let root = -coefficients[0] / coefficients[1];
+        return if root >= min && root <= max {
+            vec![root]
+        } else {
+            Vec::new()
+        };
+    }
+
+    let slope = derivative(coefficients);
+    let mut ends = vec![min];
+    ends.extend(roots_between(&slope, min, max));
+    ends.push(max);
+    let mut roots: Vec<f64> = Vec::new();
+    for pair in ends.windows(2) {
+        if let Some(root) = bracketed_root(coefficients, &slope, pair[0], pair[1]) {
+            // A root on the end of one piece is also on the start of the next
+            if roots.last() != Some(&root) {
+                roots.push(root);
+            }
+        }
+    }
+    roots
+}
+
+// The root of a polynomial that rises or falls monotonically between the ends, if it has one
+fn bracketed_root(coefficients: &[f64], slope: &[f64], min: f64, max: f64) -> Option<f64> {
+    let (at_min, at_max) = (evaluate(coefficients, min), evaluate(coefficients, max));
+    if at_min == 0.0 {
+        return Some(min);
+    }
+    if at_max == 0.0 {
+        return Some(max);
+    }
+    if (at_min < 0.0) == (at_max < 0.0) {
+        return None;
+    }
+
+    let (mut low, mut high) = if at_min < 0.0 { (min, max) } else { (max, min) };
+    let mut x = 0.5 * (min + max);
+    for _ in 0..100 {
+        let value = evaluate(coefficients, x);
+        if value == 0.0 {
+            return Some(x);
+        }
+        if value < 0.0 {
+            low = x;
+        } else {
+            high = x;
+        }
+        // Newton's step, or bisection when it leaves the bracket
+        let newton = x - value / evaluate(slope, x);
+        let next = if newton.is_finite() && newton > low.min(high) && newton < low.max(high) {
+            newton
+        } else {
+            0.5 * (low + high)
+        };
+        if (next - x).abs() <= 1e-14 * (1.0 + x.abs()) {
+            return Some(next);
+        }
+        x = next;
+    }
+    Some(x)
+}
+
+// The real roots of the quartic with the coefficients from the constant term up, in increasing
+// order
+pub fn solve_quartic(coefficients: [f64; 5]) -> Vec<f64> {
+    // All roots are within this bound (Cauchy)
+    let leading = coefficients[4];
+    if leading == 0.0 {
+        return Vec::new();
+    }
+    let bound = 1.0
+        + coefficients[..4]
+            .iter()
+            .map(|c| (c / leading).abs())
+            .fold(0.0, f64::max);
+    roots_between(&coefficients, -bound, bound)
+}
+
+// A torus around the axis through the center, with the major radius to the middle of the tube
+// and the minor radius of the tube. U goes around the axis, v around the tube starting from
+// its outside
+pub struct Torus {
+    frame: Frame,
+    major_radius: f64,
+    minor_radius: f64,
+    material: usize,
+}
+
+impl Torus {
+    pub fn new(
+        center: Point3,
+        axis: Vec3,
+        major_radius: f64,
+        minor_radius: f64,
+        material: usize,
+    ) -> Torus {
+        Torus {
+            frame: Frame::new(center, axis),
+            major_radius,
+            minor_radius,
+            material,
+        }
+    }
+}
+
+impl Torus {
+    // Every distance along the ray to the surface, in order
+    fn roots(&self, ray: &Ray) -> Vec<f64> {
+        let origin = self.frame.local_point(ray.origin);
+        let d = self.frame.local_vector(ray.direction);
+        let (major, minor) = (self.major_radius, self.minor_radius);
+
+        // Solve from the point of the ray closest to the center, which keeps the coefficients
+        // small when the ray starts far away
+        let shift = -origin.dot(d) / d.dot(d);
+        let o = origin + d * shift;
+        let outer = major + minor;
+        if o.length_squared() > outer * outer {
+            return Vec::new();
+        }
+
+        // (|p|² + R² - r²)² = 4 R² (x² + y²) along the ray
+        let a = d.dot(d);
+        let b = 2.0 * o.dot(d);
+        let c = o.length_squared() + major * major - minor * minor;
+        let four_r2 = 4.0 * major * major;
+        let coefficients = [
+            c * c - four_r2 * (o.x * o.x + o.y * o.y),
+            2.0 * b * c - four_r2 * 2.0 * (o.x * d.x + o.y * d.y),
+            b * b + 2.0 * a * c - four_r2 * (d.x * d.x + d.y * d.y),
+            2.0 * a * b,
+            a * a,
+        ];
+        solve_quartic(coefficients)
+            .into_iter()
+            .map(|t| t + shift)
+            .collect()
+    }
+
+    fn crossing(&self, ray: &Ray, t: f64) -> Crossing {
+        let (major, minor) = (self.major_radius, self.minor_radius);
+        let p = self.frame.local_point(ray.at(t));
+        let s = p.length_squared() + major * major - minor * minor;
+        let outward = p * s - Vec3::new(p.x, p.y, 0.0) * (2.0 * major * major);
+        Crossing {
+            t,
+            normal: self.frame.world_vector(outward).unit_vector(),
+            material: self.material,
+            u: p.y.atan2(p.x).rem_euclid(2.0 * PI) / (2.0 * PI),
+            v: p.z.atan2(p.x.hypot(p.y) - major).rem_euclid(2.0 * PI) / (2.0 * PI),
+        }
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let t = self
+            .roots(ray)
+            .into_iter()
+            .find(|&t| t > t_min && t < t_max)?;
+        Some(self.crossing(ray, t).record(ray))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let outer = self.major_radius + self.minor_radius;
+        Some(self.frame.bounds(
+            Point3::new(-outer, -outer, -self.minor_radius),
+            Point3::new(outer, outer, self.minor_radius),
+        ))
+    }
+}
+
+impl Solid for Torus {
+    fn spans(&self, ray: &Ray, _rng: &mut dyn RngCore) -> Vec<Span> {
+        let crossings = self.roots(ray).into_iter().map(|t| self.crossing(ray, t));
+        pair_crossings(ray, crossings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+    use rand::prelude::*;
+
+    #[test]
+    fn torus_solve_quartic() {
+        // (t - 1)(t - 2)(t - 3)(t - 4)
+        let roots = solve_quartic([24.0, -50.0, 35.0, -10.0, 1.0]);
+        assert_eq!(4, roots.len());
+        for (expected, root) in [1.0, 2.0, 3.0, 4.0].iter().zip(&roots) {
+            assert_approx_eq!(expected, root, 1e-12);
+        }
+        // Roots very close together, where the closed form breaks down
+        let (p, q) = (1.0, 1.0 + 1e-6);
+        let roots = solve_quartic([p * q * 4.0, -(p + q) * 4.0, p * q + 4.0, -(p + q), 1.0]);
+        assert_eq!(2, roots.len());
+        assert_approx_eq!(p, roots[0], 1e-9);
+        assert_approx_eq!(q, roots[1], 1e-9);
+        // t⁴ + 1 has no real roots
+        assert!(solve_quartic([1.0, 0.0, 0.0, 0.0, 1.0]).is_empty());
+    }
+
+    // Distance to the torus in its own frame
+    fn distance(torus: &Torus, p: Point3) -> f64 {
+        let p = torus.frame.local_point(p);
+        (p.x.hypot(p.y) - torus.major_radius).hypot(p.z) - torus.minor_radius
+    }
+
+    // The first hit found by stepping along the ray by the distance to the surface, which
+    // can never overshoot it
+    fn ray_march(torus: &Torus, ray: &Ray) -> Option<f64> {
+        let direction = ray.direction.unit_vector();
+        let mut travelled = 0.0;
+        for _ in 0..10_000 {
+            let step = distance(torus, ray.origin + direction * travelled);
+            if step < 1e-10 {
+                return Some(travelled / ray.direction.length());
+            }
+            travelled += step;
+            if travelled > 100.0 {
+                return None;
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn torus_matches_ray_marching() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let torus = Torus::new(
+            Point3::new(0.5, -0.2, 1.0),
+            Vec3::new(0.3, 1.0, -0.4),
+            1.0,
+            0.25,
+            0,
+        );
+        let mut hits = 0;
+        for _ in 0..1000 {
+            let origin = Point3::random(-4.0..4.0, &mut rng);
+            if distance(&torus, origin) < 0.01 {
+                continue;
+            }
+            // Aim near the tube so many rays graze it
+            let target = Point3::new(0.5, -0.2, 1.0) + Vec3::random(-1.3..1.3, &mut rng);
+            let ray = Ray::new(origin, target - origin);
+            let found = torus.hit(&ray, 0.0, f64::MAX, &mut rng).map(|r| r.t);
+            match (ray_march(&torus, &ray), found) {
+                (Some(expected), Some(t)) => {
+                    hits += 1;
+                    assert_approx_eq!(expected, t, 1e-6);
+                }
+                (None, None) => {}
+                // Rays just touching the tube may go either way
+                (expected, t) => {
+                    let t = expected.or(t).unwrap();
+                    let closest = (0..=100)
+                        .map(|i| distance(&torus, ray.at(t * (0.9 + 0.002 * i as f64))))
+                        .fold(f64::MAX, f64::min);
+                    assert!(closest.abs() < 1e-4, "{:?} {}", ray.direction, t);
+                }
+            }
+        }
+        assert!(hits > 200, "{}", hits);
+    }
+
+    #[test]
+    fn torus_normals_and_uvs() {
+        let torus = Torus::new(Point3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, 0.25, 0);
+        let rng = &mut StdRng::seed_from_u64(1);
+
+        // Into the outside of the tube along the x axis
+        let ray = Ray::new(Point3::new(3.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let record = torus.hit(&ray, 0.001, f64::MAX, rng).unwrap();
+        assert_approx_eq!(1.75, record.t, 1e-9);
+        assert_approx_eq!(1.0, record.normal.x, 1e-9);
+        assert_approx_eq!(0.0, record.u, 1e-9);
+        assert_approx_eq!(0.0, record.v, 1e-9);
+
+        // Down onto the top of the tube
+        let ray = Ray::new(Point3::new(0.0, 1.0, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        let record = torus.hit(&ray, 0.001, f64::MAX, rng).unwrap();
+        assert_approx_eq!(1.75, record.t, 1e-9);
+        assert_approx_eq!(1.0, record.normal.z, 1e-9);
+        assert_approx_eq!(0.25, record.u, 1e-9);
+        assert_approx_eq!(0.25, record.v, 1e-9);
+
+        // Through the hole
+        let ray = Ray::new(Point3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(torus.hit(&ray, 0.001, f64::MAX, rng).is_none());
+
+        // From inside the tube
+        let ray = Ray::new(Point3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let record = torus.hit(&ray, 0.001, f64::MAX, rng).unwrap();
+        assert!(!record.front_face);
+        assert_approx_eq!(0.25, record.t, 1e-9);
+    }
+
+    #[test]
+    fn torus_bounding_box() {
+        let torus = Torus::new(
+            Point3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+            0.5,
+            0,
+        );
+        let bounds = torus.bounding_box().unwrap();
+        assert_approx_eq!(-0.5, bounds.min.x, 1e-9);
+        assert_approx_eq!(2.5, bounds.max.x, 1e-9);
+        assert_approx_eq!(-0.5, bounds.min.y, 1e-9);
+        assert_approx_eq!(0.5, bounds.max.y, 1e-9);
+    }
+
+    #[test]
+    fn torus_spans() {
+        let torus = Torus::new(Point3::zero(), Vec3::new(0.0, 0.0, 1.0), 1.0, 0.25, 0);
+        let rng = &mut StdRng::seed_from_u64(1);
+        let ray = Ray::new(Point3::new(-3.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let spans = torus.spans(&ray, rng);
+
+        // Through the tube on both sides of the hole
+        assert_eq!(2, spans.len());
+        assert_approx_eq!(1.75, spans[0].enter.t, 1e-9);
+        assert_approx_eq!(2.25, spans[0].exit.t, 1e-9);
+        assert_approx_eq!(1.0, spans[0].exit.normal.x, 1e-9);
+        assert_approx_eq!(3.75, spans[1].enter.t, 1e-9);
+
+        // Grazing the top of the tube on both sides neither goes in nor comes out
+        let ray = Ray::new(Point3::new(-3.0, 0.0, 0.25), Vec3::new(1.0, 0.0, 0.0));
+        assert!(torus.spans(&ray, rng).is_empty());
+
+        // Touching the edge of the hole from inside the tube stays inside
+        let ray = Ray::new(Point3::new(0.75, -3.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let spans = torus.spans(&ray, rng);
+        assert_eq!(1, spans.len());
+        assert_approx_eq!(2.0, spans[0].enter.t, 1e-6);
+        assert_approx_eq!(4.0, spans[0].exit.t, 1e-6);
+    }
+}
@@ -1,5 +1,6 @@
 use core::fmt;
 use rand::prelude::Rng;
+use rand::RngCore;
 use std::ops::Range;
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
@@ -66,10 +67,24 @@ impl Vec3 {
         Vec3::new(self.x.sqrt(), self.y.sqrt(), self.z.sqrt())
     }
 
+    // Rec. 709 relative luminance of a linear color
+    pub fn luminance(self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
     pub fn reflect(self, other: Vec3) -> Vec3 {
         self - 2.0 * self.dot(other) * other
     }
 
+    // Bends a unit vector through a surface with the given normal, eta is the ratio of the
+    // indices of refraction on the incoming and the outgoing side
+    pub fn refract(self, normal: Vec3, eta: f64) -> Vec3 {
+        let cos_theta = (-self).dot(normal).min(1.0);
+        let perpendicular = eta * (self + cos_theta * normal);
+        let parallel = -(1.0 - perpendicular.length_squared()).abs().sqrt() * normal;
+        perpendicular + parallel
+    }
+
     pub fn to_rgba(self) -> [u8; 4] {
         fn f(num: f64) -> u8 {
             if num < 0.0 {
@@ -83,9 +98,7 @@ impl Vec3 {
         [f(self.x), f(self.y), f(self.z), 0xff]
     }
 
-    pub fn random(r: Range<f64>) -> Vec3 {
-        let mut rng = rand::thread_rng();
-
+    pub fn random(r: Range<f64>, rng: &mut dyn RngCore) -> Vec3 {
         Vec3::new(
             rng.gen_range(r.clone()),
             rng.gen_range(r.clone()),
@@ -93,17 +106,21 @@ impl Vec3 {
         )
     }
 
-    pub fn random_in_unit_sphere() -> Vec3 {
+    pub fn random_in_unit_sphere(rng: &mut dyn RngCore) -> Vec3 {
         loop {
-            let v = Vec3::random(-1.0..1.0);
+            let v = Vec3::random(-1.0..1.0, rng);
             if v.length() < 1.0 {
                 return v;
             }
         }
     }
 
-    pub fn random_in_hemisphere(normal: Vec3) -> Vec3 {
-        let in_unit_sphere = Self::random_in_unit_sphere();
+    pub fn random_unit_vector(rng: &mut dyn RngCore) -> Vec3 {
+        Self::random_in_unit_sphere(rng).unit_vector()
+    }
+
+    pub fn random_in_hemisphere(normal: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        let in_unit_sphere = Self::random_in_unit_sphere(rng);
         if in_unit_sphere.dot(normal) > 0.0 {
             // In the same hemisphere as the normal
             in_unit_sphere
@@ -112,9 +129,44 @@ impl Vec3 {
         }
     }
 
-    pub fn random_in_unit_disk() -> Vec3 {
-        let mut rng = rand::thread_rng();
+    // Uniform direction on the hemisphere around normal from a sample in the unit square
+    pub fn in_hemisphere(normal: Vec3, u: (f64, f64)) -> Vec3 {
+        let z = u.0;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * u.1;
+        let (tangent, bitangent) = normal.unit_vector().orthonormal_basis();
+        r * phi.cos() * tangent + r * phi.sin() * bitangent + z * normal.unit_vector()
+    }
+
+    // Concentric mapping of a sample in the unit square onto the unit disk
+    pub fn in_unit_disk(u: (f64, f64)) -> Vec3 {
+        let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vec3::zero();
+        }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, std::f64::consts::FRAC_PI_4 * (b / a))
+        } else {
+            (
+                b,
+                std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (a / b),
+            )
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+    }
+
+    // Two unit vectors perpendicular to self and each other, self must be normalised
+    pub fn orthonormal_basis(self) -> (Vec3, Vec3) {
+        let sign = if self.z >= 0.0 { 1.0 } else { -1.0 };
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        (
+            Vec3::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x),
+            Vec3::new(b, sign + self.y * self.y * a, -self.y),
+        )
+    }
 
+    pub fn random_in_unit_disk(rng: &mut dyn RngCore) -> Vec3 {
         loop {
             let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
             if p.length() < 1.0 {
@@ -257,6 +309,8 @@ impl fmt::Display for Vec3 {
 mod tests {
     use super::*;
     use assert_approx_eq::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     macro_rules! assert_vec3_equal {
         ($expected:expr, $actual:expr) => {
@@ -499,6 +553,12 @@ mod tests {
         assert_vec3_equal!(expected.z, input.z);
     }
 
+    #[test]
+    fn vector_luminance() {
+        assert_vec3_equal!(1.0, Vec3::one().luminance());
+        assert_vec3_equal!(0.7152, Vec3::new(0.0, 1.0, 0.0).luminance());
+    }
+
     #[test]
     fn vector_to_rbga() {
         let result = Vec3::new(-1.0, 0.5, 1.4).to_rgba();
@@ -509,16 +569,24 @@ mod tests {
 
     #[test]
     fn vector_random_unit_sphere() {
-        let input = Vec3::random_in_unit_sphere();
+        let input = Vec3::random_in_unit_sphere(&mut StdRng::seed_from_u64(1));
         let result = input.length();
 
         assert_ne!(1.0, result);
     }
 
+    #[test]
+    fn vector_random_unit_vector() {
+        let input = Vec3::random_unit_vector(&mut StdRng::seed_from_u64(1));
+
+        assert_vec3_equal!(1.0, input.length());
+    }
+
     #[test]
     fn vector_random_unit_hemisphere() {
-        let input1 = Vec3::random_in_hemisphere(Vec3::new(0.0, 1.0, 0.0));
-        let input2 = Vec3::random_in_hemisphere(Vec3::new(0.0, -1.0, 0.0));
+        let mut rng = StdRng::seed_from_u64(1);
+        let input1 = Vec3::random_in_hemisphere(Vec3::new(0.0, 1.0, 0.0), &mut rng);
+        let input2 = Vec3::random_in_hemisphere(Vec3::new(0.0, -1.0, 0.0), &mut rng);
         let result1 = input1.y > 0.0;
         let result2 = input2.y < 0.0;
 
@@ -530,13 +598,68 @@ mod tests {
 
     #[test]
     fn vector_random_unit_disc() {
-        let input = Vec3::random_in_unit_disk();
+        let input = Vec3::random_in_unit_disk(&mut StdRng::seed_from_u64(1));
         let result = input.length();
 
         assert_ne!(1.0, result);
         assert_vec3_equal!(0.0, input.z);
     }
 
+    #[test]
+    fn vector_in_hemisphere() {
+        let normal = Vec3::new(0.0, 0.0, -1.0);
+        for u in [(0.0, 0.0), (0.5, 0.25), (0.99, 0.9)] {
+            let input = Vec3::in_hemisphere(normal, u);
+
+            assert_vec3_equal!(1.0, input.length());
+            assert!(input.dot(normal) >= 0.0);
+        }
+    }
+
+    #[test]
+    fn vector_in_unit_disk() {
+        for u in [(0.5, 0.5), (0.0, 0.0), (1.0, 0.3), (0.2, 0.9)] {
+            let input = Vec3::in_unit_disk(u);
+
+            assert!(input.length() <= 1.0 + 1e-9);
+            assert_vec3_equal!(0.0, input.z);
+        }
+    }
+
+    #[test]
+    fn vector_orthonormal_basis() {
+        let normal = Vec3::new(1.0, 2.0, -3.0).unit_vector();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+
+        assert_vec3_equal!(0.0, tangent.dot(normal));
+        assert_vec3_equal!(0.0, bitangent.dot(normal));
+        assert_vec3_equal!(0.0, tangent.dot(bitangent));
+        assert_vec3_equal!(1.0, tangent.length());
+        assert_vec3_equal!(1.0, bitangent.length());
+    }
+
+    #[test]
+    fn vector_random_is_seeded() {
+        let a = Vec3::random(0.0..1.0, &mut StdRng::seed_from_u64(7));
+        let b = Vec3::random(0.0..1.0, &mut StdRng::seed_from_u64(7));
+
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn vector_refract() {
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let input = Vec3::new(1.0, -1.0, 0.0).unit_vector();
+        let result = input.refract(normal, 1.0 / 1.5);
+
+        // Snell's law: sin(45°) = 1.5 sin(θ)
+        assert_vec3_equal!(1.0, result.length());
+        assert_vec3_equal!((0.5f64).sqrt() / 1.5, result.x);
+        let straight = input.refract(normal, 1.0);
+        assert_vec3_equal!(input.x, straight.x);
+        assert_vec3_equal!(input.y, straight.y);
+    }
+
     #[test]
     fn vector_square_root() {
         let input = Vec3::new(144.0, 144.0, 144.0);
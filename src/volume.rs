@@ -0,0 +1,399 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use rand::prelude::*;
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::vector::{Point3, Vec3};
+
+const VOXEL_MAGIC: &[u8; 4] = b"VOXG";
+
+// Tracking gives up after this many tentative collisions along one ray, which only a majorant
+// far too large for the grid needs
+const MAX_STEPS: usize = 1 << 20;
+
+pub struct VoxelGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    bounds: Aabb,
+    densities: Vec<f32>,
+    max_density: f64,
+}
+
+impl VoxelGrid {
+    // Densities are stored x-fastest, then y, then z
+    pub fn new(nx: usize, ny: usize, nz: usize, bounds: Aabb, densities: Vec<f32>) -> VoxelGrid {
+        assert_eq!(nx * ny * nz, densities.len(), "voxel count mismatch");
+        let max_density = densities.iter().fold(0.0f32, |max, &d| max.max(d)) as f64;
+
+        VoxelGrid {
+            nx,
+            ny,
+            nz,
+            bounds,
+            densities,
+            max_density,
+        }
+    }
+
+    // File layout, all little endian:
+    //   "VOXG", nx ny nz as u32, bounds min xyz and max xyz as f32, nx*ny*nz f32 densities
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<VoxelGrid> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != VOXEL_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a voxel grid file",
+            ));
+        }
+
+        let nx = read_u32(&mut reader)? as usize;
+        let ny = read_u32(&mut reader)? as usize;
+        let nz = read_u32(&mut reader)? as usize;
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(invalid("voxel grid has no voxels"));
+        }
+
+        let mut corners = [0.0; 6];
+        for corner in corners.iter_mut() {
+            *corner = read_f32(&mut reader)? as f64;
+        }
+        let bounds = Aabb::new(
+            Point3::new(corners[0], corners[1], corners[2]),
+            Point3::new(corners[3], corners[4], corners[5]),
+        );
+        // Also false for NaN corners
+        if !(0..3).all(|i| corners[i] < corners[i + 3]) || !corners.iter().all(|c| c.is_finite()) {
+            return Err(invalid("voxel grid bounds are empty"));
+        }
+
+        // Checked against the file before allocating, so a bad header can't ask for more
+        let size = nx
+            .checked_mul(ny)
+            .and_then(|n| n.checked_mul(nz))
+            .and_then(|n| n.checked_mul(4))
+            .filter(|&size| size as u64 == length.saturating_sub(40))
+            .ok_or_else(|| invalid("voxel grid size doesn't match the file"))?;
+        let mut bytes = vec![0u8; size];
+        reader.read_exact(&mut bytes)?;
+        let densities: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        if !densities.iter().all(|d| d.is_finite() && *d >= 0.0) {
+            return Err(invalid(
+                "voxel grid densities must be finite and not negative",
+            ));
+        }
+
+        Ok(VoxelGrid::new(nx, ny, nz, bounds, densities))
+    }
+
+    fn lookup(&self, x: i64, y: i64, z: i64) -> f64 {
+        if x < 0
+            || y < 0
+            || z < 0
+            || x >= self.nx as i64
+            || y >= self.ny as i64
+            || z >= self.nz as i64
+        {
+            return 0.0;
+        }
+        let index = (z as usize * self.ny + y as usize) * self.nx + x as usize;
+        self.densities[index] as f64
+    }
+
+    // Trilinear interpolation between voxel centers, zero outside the grid
+    pub fn density(&self, p: Point3) -> f64 {
+        let extent = self.bounds.max - self.bounds.min;
+        let local = p - self.bounds.min;
+        let gx = local.x / extent.x * self.nx as f64 - 0.5;
+        let gy = local.y / extent.y * self.ny as f64 - 0.5;
+        let gz = local.z / extent.z * self.nz as f64 - 0.5;
+
+        let (x0, y0, z0) = (gx.floor(), gy.floor(), gz.floor());
+        let (dx, dy, dz) = (gx - x0, gy - y0, gz - z0);
+        let (x, y, z) = (x0 as i64, y0 as i64, z0 as i64);
+
+        let lerp = |t: f64, a: f64, b: f64| (1.0 - t) * a + t * b;
+        let d00 = lerp(dx, self.lookup(x, y, z), self.lookup(x + 1, y, z));
+        let d10 = lerp(dx, self.lookup(x, y + 1, z), self.lookup(x + 1, y + 1, z));
+        let d01 = lerp(dx, self.lookup(x, y, z + 1), self.lookup(x + 1, y, z + 1));
+        let d11 = lerp(
+            dx,
+            self.lookup(x, y + 1, z + 1),
+            self.lookup(x + 1, y + 1, z + 1),
+        );
+
+        lerp(dz, lerp(dy, d00, d10), lerp(dy, d01, d11))
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(f32::from_le_bytes(buf))
+}
+
+pub struct HeterogeneousMedium {
+    grid: VoxelGrid,
+    // Extinction coefficient per unit density
+    sigma_t: f64,
+    material: usize,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(grid: VoxelGrid, sigma_t: f64, material: usize) -> HeterogeneousMedium {
+        HeterogeneousMedium {
+            grid,
+            sigma_t,
+            material,
+        }
+    }
+
+    fn majorant(&self) -> f64 {
+        self.grid.max_density * self.sigma_t
+    }
+
+    // Nothing to track through an empty medium, or one whose majorant would stall the steps
+    fn trackable(&self) -> bool {
+        let majorant = self.majorant();
+        majorant.is_finite() && majorant > 0.0
+    }
+
+    // Step to the next tentative collision against the majorant, in ray parameter units
+    fn step(&self, ray: &Ray, rng: &mut dyn RngCore) -> f64 {
+        let u: f64 = rng.gen();
+        -(1.0 - u).ln() / (self.majorant() * ray.direction.length())
+    }
+
+    // Ratio tracking estimate of the transmittance between t0 and t1: every tentative
+    // collision lets through the fraction of the majorant that is not really there
+    pub fn transmittance(&self, ray: &Ray, t0: f64, t1: f64, rng: &mut dyn RngCore) -> f64 {
+        let (t0, t1) = match self.grid.bounds.hit(ray, t0, t1) {
+            Some(interval) => interval,
+            None => return 1.0,
+        };
+        if !self.trackable() {
+            return 1.0;
+        }
+
+        let mut transmittance = 1.0;
+        let mut t = t0;
+        for _ in 0..MAX_STEPS {
+            t += self.step(ray, rng);
+            if t >= t1 {
+                return transmittance;
+            }
+            transmittance *= 1.0 - self.grid.density(ray.at(t)) / self.grid.max_density;
+        }
+        transmittance
+    }
+
+    // How much light gets through the middle of the medium seen from the point, on average
+    pub fn transmittance_from(&self, from: Point3, rng: &mut dyn RngCore) -> f64 {
+        let middle = (self.grid.bounds.min + self.grid.bounds.max) * 0.5;
+        let ray = Ray::new(from, middle - from);
+        let runs = 256;
+        (0..runs)
+            .map(|_| self.transmittance(&ray, 0.0, f64::INFINITY, rng))
+            .sum::<f64>()
+            / runs as f64
+    }
+}
+
+impl Hittable for HeterogeneousMedium {
+    // Delta tracking: sample free flights against the majorant and accept real collisions
+    // with probability density / max density
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let (t0, t1) = self.grid.bounds.hit(ray, t_min, t_max)?;
+        if !self.trackable() {
+            return None;
+        }
+
+        let mut t = t0;
+        for _ in 0..MAX_STEPS {
+            t += self.step(ray, rng);
+            if t >= t1 {
+                return None;
+            }
+            let p = ray.at(t);
+            if rng.gen::<f64>() * self.grid.max_density < self.grid.density(p) {
+                // The scattering direction is isotropic, so the normal is arbitrary
+                return Some(HitRecord::new(
+                    p,
+                    Vec3::random_unit_vector(rng),
+                    t,
+                    true,
+                    self.material,
+                ));
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.grid.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::*;
+    use rand::rngs::StdRng;
+
+    fn unit_cube() -> Aabb {
+        Aabb::new(Point3::zero(), Point3::one())
+    }
+
+    fn constant_grid(density: f32) -> VoxelGrid {
+        VoxelGrid::new(4, 4, 4, unit_cube(), vec![density; 64])
+    }
+
+    #[test]
+    fn voxel_grid_trilinear() {
+        let grid = VoxelGrid::new(2, 1, 1, unit_cube(), vec![1.0, 3.0]);
+
+        assert_approx_eq!(1.0, grid.density(Point3::new(0.25, 0.5, 0.5)), 0.0001);
+        assert_approx_eq!(2.0, grid.density(Point3::new(0.5, 0.5, 0.5)), 0.0001);
+        assert_approx_eq!(3.0, grid.density(Point3::new(0.75, 0.5, 0.5)), 0.0001);
+    }
+
+    #[test]
+    fn voxel_grid_load() {
+        let path = std::env::temp_dir().join("raytracing_weekend_voxel_grid_load.vol");
+        let mut bytes = VOXEL_MAGIC.to_vec();
+        for n in [2u32, 1, 1] {
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+        for c in [0.0f32, 0.0, 0.0, 2.0, 1.0, 1.0, 0.5, 1.5] {
+            bytes.extend_from_slice(&c.to_le_bytes());
+        }
+        std::fs::write(&path, bytes).unwrap();
+
+        let grid = VoxelGrid::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((2, 1, 1), (grid.nx, grid.ny, grid.nz));
+        assert_eq!(Point3::new(2.0, 1.0, 1.0), grid.bounds.max);
+        assert_approx_eq!(1.5, grid.max_density, 0.0001);
+    }
+
+    #[test]
+    fn voxel_grid_load_invalid() {
+        let path = std::env::temp_dir().join("raytracing_weekend_voxel_grid_load_invalid.vol");
+        let header = |counts: [u32; 3], corners: [f32; 6], densities: usize| {
+            let mut bytes = VOXEL_MAGIC.to_vec();
+            for n in counts {
+                bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            for c in corners {
+                bytes.extend_from_slice(&c.to_le_bytes());
+            }
+            bytes.resize(bytes.len() + densities * 4, 0);
+            bytes
+        };
+        let unit = [0.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+        let density = |density: f32| {
+            let mut bytes = header([1, 1, 1], unit, 0);
+            bytes.extend_from_slice(&density.to_le_bytes());
+            bytes
+        };
+        let files = [
+            header([0, 1, 1], unit, 0),
+            header([u32::MAX, u32::MAX, u32::MAX], unit, 1),
+            header([1 << 20, 1 << 20, 1], unit, 1),
+            header([2, 1, 1], unit, 1),
+            header([1, 1, 1], [0.0, 0.0, 0.0, 1.0, 0.0, 1.0], 1),
+            header([1, 1, 1], [0.0, f32::NAN, 0.0, 1.0, 1.0, 1.0], 1),
+            density(f32::NAN),
+            density(f32::INFINITY),
+            density(-1.0),
+        ];
+        for bytes in files {
+            std::fs::write(&path, bytes).unwrap();
+            let error = VoxelGrid::load(&path).err().unwrap();
+            assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn medium_ratio_tracking() {
+        let medium = HeterogeneousMedium::new(constant_grid(1.0), 2.0, 0);
+        let ray = Ray::new(Point3::new(0.5, 0.5, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let mut rng = StdRng::seed_from_u64(1);
+        let runs = 20000;
+        let estimate: f64 = (0..runs)
+            .map(|_| medium.transmittance(&ray, 0.0, f64::MAX, &mut rng))
+            .sum::<f64>()
+            / runs as f64;
+
+        // Trilinear lookups fade out over the outer half voxel, so compare against a bracket
+        assert!(estimate > (-2.0f64).exp());
+        assert!(estimate < (-2.0f64 * 0.75).exp());
+    }
+
+    #[test]
+    fn medium_delta_tracking_matches_ratio_tracking() {
+        let medium = HeterogeneousMedium::new(constant_grid(1.0), 2.0, 0);
+        let ray = Ray::new(Point3::new(0.5, 0.5, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let mut rng = StdRng::seed_from_u64(1);
+        let runs = 20000;
+        let escaped = (0..runs)
+            .filter(|_| medium.hit(&ray, 0.0, f64::MAX, &mut rng).is_none())
+            .count() as f64
+            / runs as f64;
+        let transmittance: f64 = (0..runs)
+            .map(|_| medium.transmittance(&ray, 0.0, f64::MAX, &mut rng))
+            .sum::<f64>()
+            / runs as f64;
+
+        assert_approx_eq!(transmittance, escaped, 0.02);
+
+        // The same looking at the middle from where the ray starts
+        let seen = medium.transmittance_from(ray.origin, &mut rng);
+        assert!(seen > (-2.0f64).exp() && seen < (-2.0f64 * 0.75).exp());
+    }
+
+    #[test]
+    fn medium_without_a_usable_majorant() {
+        let ray = Ray::new(Point3::new(0.5, 0.5, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let mut rng = StdRng::seed_from_u64(1);
+        for sigma_t in [0.0, f64::NAN, f64::INFINITY] {
+            let medium = HeterogeneousMedium::new(constant_grid(1.0), sigma_t, 0);
+            assert!(medium.hit(&ray, 0.0, f64::MAX, &mut rng).is_none());
+            assert_eq!(1.0, medium.transmittance(&ray, 0.0, f64::MAX, &mut rng));
+        }
+    }
+
+    #[test]
+    fn medium_hit_inside_bounds() {
+        let medium = HeterogeneousMedium::new(constant_grid(50.0), 1.0, 0);
+        let ray = Ray::new(Point3::new(0.5, 0.5, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let record = medium
+            .hit(&ray, 0.0, f64::MAX, &mut StdRng::seed_from_u64(1))
+            .unwrap();
+
+        assert!(record.t > 1.0 && record.t < 2.0);
+        assert_approx_eq!(1.0, record.normal.length(), 0.0001);
+    }
+}